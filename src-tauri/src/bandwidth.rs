@@ -0,0 +1,584 @@
+// src-tauri/src/bandwidth.rs
+
+//! Upload and download limits, see [`BandwidthSettings`].
+//!
+//! Rates are enforced on the bytes themselves: uploads as the blobs protocol
+//! reads them from the store ([`ThrottledStore`]), downloads as they are
+//! written to it (`storage::download_blob`). Each direction has a token
+//! bucket holding up to a second's worth of its rate, and a read or write
+//! that overdraws it waits until the debt is paid back, which holds up the
+//! QUIC stream behind it. New limits and schedule windows apply to running
+//! transfers within a second.
+//!
+//! Concurrency is limited per transfer. An upload takes a slot when its
+//! request arrives and frees it when the provider reports it finished, when
+//! its connection closes, or after [`UPLOAD_IDLE_TIMEOUT`] without progress,
+//! as the provider does not report every request that fails.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use chrono::Local;
+use futures::FutureExt;
+use iroh::{endpoint::Connection, protocol::ProtocolHandler};
+use iroh_blobs::{
+    net_protocol::Blobs,
+    provider::{CustomEventSender, Event},
+    store::{
+        bao_tree::io::fsm::{BaoContentItem, Outboard},
+        fs::Store,
+        BaoBatchWriter, BaoBlobSize, Map, MapEntry,
+    },
+    Hash,
+};
+use iroh_io::AsyncSliceReader;
+use log::info;
+use tokio::sync::Notify;
+
+use crate::settings::{BandwidthSettings, TransferLimits};
+
+/// How often waiting transfers look at the limits again, so schedule windows
+/// take effect without a settings change.
+const RECHECK: Duration = Duration::from_secs(1);
+
+/// How long an upload may go without sending anything before its slot is
+/// given to another request.
+pub const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// The limits in force and the transfers running in each direction.
+#[derive(Debug)]
+pub struct Bandwidth {
+    settings: Mutex<BandwidthSettings>,
+    upload: Meter,
+    download: Meter,
+}
+
+#[derive(Debug)]
+struct Meter {
+    state: Mutex<MeterState>,
+    /// Woken when a slot frees up or the settings change.
+    changed: Notify,
+}
+
+#[derive(Debug)]
+struct MeterState {
+    active: u32,
+    /// Bytes that may still be moved at the current rate; negative while
+    /// paying back what was moved ahead of it.
+    allowance: f64,
+    refilled: Instant,
+}
+
+/// A running transfer. Frees its slot when dropped.
+#[derive(Debug)]
+pub struct TransferSlot {
+    bandwidth: Arc<Bandwidth>,
+    direction: Direction,
+}
+
+impl Bandwidth {
+    pub fn new(settings: BandwidthSettings) -> Self {
+        Self {
+            settings: Mutex::new(settings),
+            upload: Meter::new(),
+            download: Meter::new(),
+        }
+    }
+
+    pub fn settings(&self) -> BandwidthSettings {
+        self.settings
+            .lock()
+            .expect("bandwidth settings poisoned")
+            .clone()
+    }
+
+    /// Replaces the limits. Running transfers move to the new rates, but
+    /// are not interrupted to make room under a lower concurrency limit.
+    pub fn apply(&self, settings: BandwidthSettings) {
+        info!("Bandwidth: {:?}", settings);
+        *self.settings.lock().expect("bandwidth settings poisoned") = settings;
+        self.upload.changed.notify_waiters();
+        self.download.changed.notify_waiters();
+    }
+
+    /// The limits of the schedule window we are in, if any.
+    pub fn current_limits(&self) -> TransferLimits {
+        self.settings
+            .lock()
+            .expect("bandwidth settings poisoned")
+            .limits_at(Local::now().time())
+    }
+
+    /// Waits until a slot for a transfer in `direction` is free.
+    pub async fn acquire(self: &Arc<Self>, direction: Direction) -> TransferSlot {
+        let meter = self.meter(direction);
+        loop {
+            let changed = meter.changed.notified();
+            let (max_concurrent, _) = direction.limits(&self.current_limits());
+            {
+                let mut state = meter.state.lock().expect("bandwidth meter poisoned");
+                if max_concurrent.is_none_or(|max| state.active < max) {
+                    state.active += 1;
+                    return TransferSlot {
+                        bandwidth: self.clone(),
+                        direction,
+                    };
+                }
+            }
+            tokio::select! {
+                _ = changed => {}
+                _ = tokio::time::sleep(RECHECK) => {}
+            }
+        }
+    }
+
+    /// Counts `bytes` just moved in `direction` and waits until the rate
+    /// limit has caught up with them.
+    pub async fn throttle(&self, direction: Direction, bytes: u64) {
+        let meter = self.meter(direction);
+        let mut owed = bytes as f64;
+        loop {
+            let changed = meter.changed.notified();
+            let (_, rate) = direction.limits(&self.current_limits());
+            let wait = {
+                let mut state = meter.state.lock().expect("bandwidth meter poisoned");
+                state.refill(rate);
+                let Some(rate) = rate else {
+                    return;
+                };
+                state.allowance -= std::mem::take(&mut owed);
+                if state.allowance >= 0.0 {
+                    return;
+                }
+                Duration::from_secs_f64(-state.allowance / rate as f64).min(RECHECK)
+            };
+            tokio::select! {
+                _ = changed => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+
+    fn meter(&self, direction: Direction) -> &Meter {
+        match direction {
+            Direction::Upload => &self.upload,
+            Direction::Download => &self.download,
+        }
+    }
+}
+
+impl Direction {
+    /// Concurrency and rate limit for this direction. Zero, which
+    /// `TransferLimits::validate` refuses, counts as no limit rather than
+    /// stalling or dividing by it.
+    fn limits(self, limits: &TransferLimits) -> (Option<u32>, Option<u64>) {
+        let (max_concurrent, rate) = match self {
+            Direction::Upload => (limits.max_concurrent_uploads, limits.upload_bytes_per_sec),
+            Direction::Download => (
+                limits.max_concurrent_downloads,
+                limits.download_bytes_per_sec,
+            ),
+        };
+        (
+            max_concurrent.filter(|&max| max > 0),
+            rate.filter(|&rate| rate > 0),
+        )
+    }
+}
+
+impl Meter {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(MeterState::new(Instant::now())),
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl MeterState {
+    fn new(now: Instant) -> Self {
+        Self {
+            active: 0,
+            allowance: 0.0,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, rate: Option<u64>) {
+        self.refill_at(Instant::now(), rate);
+    }
+
+    /// Adds what `rate` allows since the last refill, up to one second's
+    /// worth so an idle link does not save up for a long burst.
+    fn refill_at(&mut self, now: Instant, rate: Option<u64>) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.refilled = now;
+        self.allowance = match rate {
+            Some(rate) => (self.allowance + elapsed * rate as f64).min(rate as f64),
+            None => 0.0,
+        };
+    }
+}
+
+impl Drop for TransferSlot {
+    fn drop(&mut self) {
+        let meter = self.bandwidth.meter(self.direction);
+        meter.state.lock().expect("bandwidth meter poisoned").active -= 1;
+        meter.changed.notify_waiters();
+    }
+}
+
+/// Holds the upload slots of the blob requests peers make to us. Installed
+/// as the event sender of the blobs protocol handler, which waits for it
+/// before serving a request.
+#[derive(Debug, Clone)]
+pub struct UploadSlots {
+    bandwidth: Arc<Bandwidth>,
+    /// Requests being served, by connection and request id.
+    uploads: Arc<Mutex<HashMap<(u64, u64), Upload>>>,
+}
+
+#[derive(Debug)]
+struct Upload {
+    _slot: TransferSlot,
+    /// When the request last sent something.
+    active: Instant,
+}
+
+impl UploadSlots {
+    pub fn new(bandwidth: Arc<Bandwidth>) -> Self {
+        Self {
+            bandwidth,
+            uploads: Arc::default(),
+        }
+    }
+
+    /// Frees the slots of every request on a closed connection.
+    fn close_connection(&self, connection_id: u64) {
+        self.uploads
+            .lock()
+            .expect("uploads poisoned")
+            .retain(|(connection, _), _| *connection != connection_id);
+    }
+
+    /// Frees the slots of requests that have not sent anything for
+    /// [`UPLOAD_IDLE_TIMEOUT`].
+    fn free_idle(&self) {
+        self.uploads
+            .lock()
+            .expect("uploads poisoned")
+            .retain(|_, upload| upload.active.elapsed() < UPLOAD_IDLE_TIMEOUT);
+    }
+
+    async fn acquire(&self) -> TransferSlot {
+        loop {
+            self.free_idle();
+            tokio::select! {
+                slot = self.bandwidth.acquire(Direction::Upload) => return slot,
+                _ = tokio::time::sleep(UPLOAD_IDLE_TIMEOUT) => {}
+            }
+        }
+    }
+}
+
+impl CustomEventSender for UploadSlots {
+    fn send(&self, event: Event) -> futures_lite::future::Boxed<()> {
+        match event {
+            Event::GetRequestReceived {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                let this = self.clone();
+                async move {
+                    let slot = this.acquire().await;
+                    this.uploads.lock().expect("uploads poisoned").insert(
+                        (connection_id, request_id),
+                        Upload {
+                            _slot: slot,
+                            active: Instant::now(),
+                        },
+                    );
+                }
+                .boxed()
+            }
+            Event::TransferCompleted {
+                connection_id,
+                request_id,
+                ..
+            }
+            | Event::TransferAborted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                self.uploads
+                    .lock()
+                    .expect("uploads poisoned")
+                    .remove(&(connection_id, request_id));
+                futures::future::ready(()).boxed()
+            }
+            _ => futures::future::ready(()).boxed(),
+        }
+    }
+
+    fn try_send(&self, event: Event) {
+        let Event::TransferProgress {
+            connection_id,
+            request_id,
+            ..
+        } = event
+        else {
+            return;
+        };
+        if let Some(upload) = self
+            .uploads
+            .lock()
+            .expect("uploads poisoned")
+            .get_mut(&(connection_id, request_id))
+        {
+            upload.active = Instant::now();
+        }
+    }
+}
+
+/// Serves blobs to peers like [`Blobs`] does, but from a [`ThrottledStore`],
+/// and frees the upload slots of a connection once it closes.
+#[derive(Debug, Clone)]
+pub struct ThrottledBlobs {
+    blobs: Blobs<Store>,
+    store: ThrottledStore,
+    slots: UploadSlots,
+}
+
+impl ThrottledBlobs {
+    /// `blobs` must send its events to `slots`.
+    pub fn new(blobs: Blobs<Store>, bandwidth: Arc<Bandwidth>, slots: UploadSlots) -> Self {
+        Self {
+            store: ThrottledStore {
+                store: blobs.store().clone(),
+                bandwidth,
+            },
+            blobs,
+            slots,
+        }
+    }
+}
+
+impl ProtocolHandler for ThrottledBlobs {
+    fn accept(&self, connection: Connection) -> futures_lite::future::Boxed<Result<()>> {
+        let this = self.clone();
+        async move {
+            let connection_id = connection.stable_id() as u64;
+            iroh_blobs::provider::handle_connection(
+                connection,
+                this.store.clone(),
+                this.blobs.events().clone(),
+                this.blobs.rt().clone(),
+            )
+            .await;
+            this.slots.close_connection(connection_id);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn shutdown(&self) -> futures_lite::future::Boxed<()> {
+        self.blobs.shutdown()
+    }
+}
+
+/// The blob store as peers read from it, paced by the upload rate.
+#[derive(Debug, Clone)]
+pub struct ThrottledStore {
+    store: Store,
+    bandwidth: Arc<Bandwidth>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThrottledEntry {
+    entry: <Store as Map>::Entry,
+    bandwidth: Arc<Bandwidth>,
+}
+
+impl Map for ThrottledStore {
+    type Entry = ThrottledEntry;
+
+    fn get(&self, hash: &Hash) -> impl Future<Output = io::Result<Option<Self::Entry>>> + Send {
+        let bandwidth = self.bandwidth.clone();
+        self.store
+            .get(hash)
+            .map(|entry| Ok(entry?.map(|entry| ThrottledEntry { entry, bandwidth })))
+    }
+}
+
+impl MapEntry for ThrottledEntry {
+    fn hash(&self) -> Hash {
+        self.entry.hash()
+    }
+
+    fn size(&self) -> BaoBlobSize {
+        self.entry.size()
+    }
+
+    fn is_complete(&self) -> bool {
+        self.entry.is_complete()
+    }
+
+    async fn outboard(&self) -> io::Result<impl Outboard> {
+        MapEntry::outboard(&self.entry).await
+    }
+
+    async fn data_reader(&self) -> io::Result<impl AsyncSliceReader> {
+        Ok(ThrottledReader {
+            reader: MapEntry::data_reader(&self.entry).await?,
+            bandwidth: self.bandwidth.clone(),
+        })
+    }
+}
+
+/// Reads blob data for a peer no faster than the upload rate.
+#[derive(Debug)]
+struct ThrottledReader<R> {
+    reader: R,
+    bandwidth: Arc<Bandwidth>,
+}
+
+impl<R: AsyncSliceReader> AsyncSliceReader for ThrottledReader<R> {
+    async fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Bytes> {
+        let data = self.reader.read_at(offset, len).await?;
+        self.bandwidth
+            .throttle(Direction::Upload, data.len() as u64)
+            .await;
+        Ok(data)
+    }
+
+    async fn size(&mut self) -> io::Result<u64> {
+        self.reader.size().await
+    }
+}
+
+/// Writes downloaded blob data to the store no faster than the download
+/// rate. As the next batch is only read off the stream once this one is
+/// written, the sender is held back too.
+#[derive(Debug)]
+pub struct ThrottledWriter<W> {
+    writer: W,
+    bandwidth: Arc<Bandwidth>,
+}
+
+impl<W: BaoBatchWriter> ThrottledWriter<W> {
+    pub fn new(writer: W, bandwidth: Arc<Bandwidth>) -> Self {
+        Self { writer, bandwidth }
+    }
+}
+
+impl<W: BaoBatchWriter> BaoBatchWriter for ThrottledWriter<W> {
+    async fn write_batch(&mut self, size: u64, batch: Vec<BaoContentItem>) -> io::Result<()> {
+        let bytes = batch
+            .iter()
+            .map(|item| match item {
+                BaoContentItem::Leaf(leaf) => leaf.data.len() as u64,
+                BaoContentItem::Parent(_) => 0,
+            })
+            .sum();
+        self.writer.write_batch(size, batch).await?;
+        self.bandwidth.throttle(Direction::Download, bytes).await;
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        self.writer.sync().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refill_adds_what_the_rate_allows() {
+        let start = Instant::now();
+        let mut state = MeterState::new(start);
+        state.refill_at(start + Duration::from_millis(500), Some(1000));
+        assert_eq!(state.allowance, 500.0);
+        state.refill_at(start + Duration::from_millis(750), Some(1000));
+        assert_eq!(state.allowance, 750.0);
+    }
+
+    #[test]
+    fn refill_saves_up_at_most_one_second() {
+        let start = Instant::now();
+        let mut state = MeterState::new(start);
+        state.refill_at(start + Duration::from_secs(60), Some(1000));
+        assert_eq!(state.allowance, 1000.0);
+        // A lower rate lowers what was saved up too.
+        state.refill_at(start + Duration::from_secs(61), Some(10));
+        assert_eq!(state.allowance, 10.0);
+    }
+
+    #[test]
+    fn refill_pays_back_bytes_moved_ahead() {
+        let start = Instant::now();
+        let mut state = MeterState::new(start);
+        state.allowance = -3000.0;
+        state.refill_at(start + Duration::from_secs(1), Some(1000));
+        assert_eq!(state.allowance, -2000.0);
+        state.refill_at(start + Duration::from_secs(4), Some(1000));
+        assert_eq!(state.allowance, 1000.0);
+    }
+
+    #[test]
+    fn refill_without_rate_clears_allowance() {
+        let start = Instant::now();
+        let mut state = MeterState::new(start);
+        state.allowance = -3000.0;
+        state.refill_at(start + Duration::from_secs(1), None);
+        assert_eq!(state.allowance, 0.0);
+    }
+
+    #[test]
+    fn zero_limits_count_as_unset() {
+        let limits = TransferLimits {
+            upload_bytes_per_sec: Some(0),
+            max_concurrent_uploads: Some(0),
+            download_bytes_per_sec: Some(10),
+            max_concurrent_downloads: Some(2),
+        };
+        assert_eq!(Direction::Upload.limits(&limits), (None, None));
+        assert_eq!(Direction::Download.limits(&limits), (Some(2), Some(10)));
+    }
+
+    #[tokio::test]
+    async fn throttle_with_zero_rate_returns() {
+        let bandwidth = Bandwidth::new(BandwidthSettings {
+            limits: TransferLimits {
+                upload_bytes_per_sec: Some(0),
+                ..TransferLimits::default()
+            },
+            schedule: Vec::new(),
+        });
+        bandwidth.throttle(Direction::Upload, 1 << 20).await;
+    }
+
+    #[test]
+    fn refill_ignores_time_going_backwards() {
+        let start = Instant::now();
+        let mut state = MeterState::new(start + Duration::from_secs(1));
+        state.refill_at(start, Some(1000));
+        assert_eq!(state.allowance, 0.0);
+    }
+}
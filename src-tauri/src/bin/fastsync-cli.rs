@@ -0,0 +1,362 @@
+// src-tauri/src/bin/fastsync-cli.rs
+
+//! Command-line client for FastSync.
+//!
+//! Every invocation runs its own node from `--data-dir`, separate from the
+//! desktop app and `fastsyncd`. Pass `--json` for machine-readable output;
+//! `join` then prints one JSON object per event.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand};
+use fastsync_lib::{
+    collection::{get_iroh_collection, is_collection, list_collection},
+    daemon::wait_for_shutdown_signal,
+    events::{EventSink, LogSink},
+    fs_watcher::switch_sync_folder,
+    iroh_fns::{
+        create_iroh_gossip_ticket, create_iroh_ticket, get_iroh_blob, load_or_create_topic, setup,
+        shutdown_node, start_gossip, GossipTicket,
+    },
+    presence::{list_devices, DeviceInfo},
+    settings::{NetworkMode, NetworkSettings},
+    state::{AppState, NodeLifecycle},
+    storage::share_tag,
+};
+use futures_util::TryStreamExt;
+use iroh::NodeId;
+use iroh_blobs::ticket::BlobTicket;
+use serde::Serialize;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Parser)]
+#[command(
+    name = "fastsync",
+    version,
+    about = "Share files and sync folders from the command line"
+)]
+struct Cli {
+    /// Directory holding this client's node key and blob store.
+    #[arg(long, global = true, env = "FASTSYNC_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Print machine-readable JSON instead of text.
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Only talk to peers on the local network: no relays, no public discovery.
+    #[arg(long, global = true)]
+    lan_only: bool,
+
+    /// Use this self-hosted relay instead of the public ones.
+    #[arg(long, global = true, env = "FASTSYNC_RELAY_URL")]
+    relay_url: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import a file or folder and print a ticket for it. Keeps serving until interrupted.
+    Share { path: PathBuf },
+    /// Download the file or folder behind a ticket to `dest`.
+    Get {
+        ticket: String,
+        #[arg(required_unless_present = "list")]
+        dest: Option<PathBuf>,
+        /// Only fetch this file or folder of a shared folder. Repeatable.
+        #[arg(long = "entry")]
+        entries: Vec<String>,
+        /// List the files of a shared folder instead of downloading them.
+        #[arg(long)]
+        list: bool,
+    },
+    /// Print a gossip ticket inviting others into this client's sync group.
+    Invite,
+    /// Join a sync group and keep `folder` in sync until interrupted.
+    Join { ticket: String, folder: PathBuf },
+    /// Show the node id, sync group and blob store usage.
+    Status,
+    /// Rejoin the last sync group for a few seconds and list the devices seen.
+    Peers {
+        /// Seconds to wait for neighbors to show up.
+        #[arg(long, default_value_t = 5)]
+        wait: u64,
+    },
+}
+
+#[derive(Serialize)]
+struct ShareOutput {
+    path: PathBuf,
+    ticket: String,
+}
+
+#[derive(Serialize)]
+struct GetOutput {
+    dest: PathBuf,
+    /// Files written, for a shared folder.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct InviteOutput {
+    topic: String,
+    ticket: String,
+}
+
+#[derive(Serialize)]
+struct StatusOutput {
+    node_id: NodeId,
+    data_dir: PathBuf,
+    topic: Option<String>,
+    blob_count: u64,
+    blob_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct PeersOutput {
+    topic: String,
+    peers: Vec<DeviceInfo>,
+}
+
+/// Ticket of the group joined last, used by `peers` to rejoin it.
+const GROUP_TICKET_FILE: &str = "group-ticket";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Logs go to stderr so stdout stays parseable.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+        )
+        .init();
+
+    let cli = Cli::parse();
+    let data_dir = match cli.data_dir {
+        Some(dir) => dir,
+        None => default_data_dir()?,
+    };
+    let network = NetworkSettings {
+        mode: match cli.lan_only {
+            true => NetworkMode::LanOnly,
+            false => NetworkMode::Default,
+        },
+        relay_url: cli.relay_url,
+        ..NetworkSettings::default()
+    };
+
+    match cli.command {
+        Command::Share { path } => {
+            let path = std::path::absolute(&path)?;
+            let app_state = start_node(&data_dir, &network, Arc::new(LogSink)).await?;
+            let ticket = create_iroh_ticket(
+                app_state.blobs.clone(),
+                app_state.endpoint.clone(),
+                path.clone(),
+                share_tag(&path),
+            )
+            .await?;
+            print_output(cli.json, &ShareOutput { path, ticket }, |out| {
+                format!(
+                    "{}\nServing {:?}. Press Ctrl-C to stop.",
+                    out.ticket, out.path
+                )
+            })?;
+            wait_for_shutdown_signal().await?;
+            shutdown_node(&app_state).await
+        }
+        Command::Get {
+            ticket,
+            dest,
+            entries,
+            list,
+        } => {
+            let blob_ticket = BlobTicket::from_str(&ticket)?;
+            let app_state = start_node(&data_dir, &network, Arc::new(LogSink)).await?;
+            if list {
+                let result = list_collection(&app_state, &blob_ticket).await;
+                shutdown_node(&app_state).await?;
+                return print_output(cli.json, &result?, |entries| {
+                    entries
+                        .iter()
+                        .map(|entry| entry.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                });
+            }
+
+            let dest = std::path::absolute(dest.context("Missing destination")?)?;
+            let result = if is_collection(&blob_ticket) {
+                let selection = (!entries.is_empty()).then_some(entries.as_slice());
+                get_iroh_collection(&app_state, &blob_ticket, &dest, selection)
+                    .await
+                    .map(Some)
+            } else if !entries.is_empty() {
+                Err(anyhow!("--entry only applies to shared folders"))
+            } else {
+                get_iroh_blob(&app_state, ticket, dest.clone(), None, None)
+                    .await
+                    .map(|_| None)
+            };
+            shutdown_node(&app_state).await?;
+            let files = result?;
+            print_output(cli.json, &GetOutput { dest, files }, |out| {
+                match &out.files {
+                    Some(files) => format!("Saved {} files to {:?}", files.len(), out.dest),
+                    None => format!("Saved to {:?}", out.dest),
+                }
+            })
+        }
+        Command::Invite => {
+            let app_state = start_node(&data_dir, &network, Arc::new(LogSink)).await?;
+            let topic = load_or_create_topic(&data_dir)?;
+            let ticket = create_iroh_gossip_ticket(app_state.endpoint.clone(), topic).await?;
+            std::fs::write(data_dir.join(GROUP_TICKET_FILE), &ticket)?;
+            shutdown_node(&app_state).await?;
+            let topic = topic.to_string();
+            print_output(cli.json, &InviteOutput { topic, ticket }, |out| {
+                out.ticket.clone()
+            })
+        }
+        Command::Join { ticket, folder } => {
+            let topic = GossipTicket::from_str(&ticket)?.topic;
+            let events: Arc<dyn EventSink> = if cli.json {
+                Arc::new(JsonLinesSink)
+            } else {
+                Arc::new(LogSink)
+            };
+            let app_state = start_node(&data_dir, &network, events).await?;
+            switch_sync_folder(&app_state, Some(std::path::absolute(&folder)?)).await?;
+            start_gossip(&app_state, &ticket).await?;
+            std::fs::write(data_dir.join(GROUP_TICKET_FILE), &ticket)?;
+            std::fs::write(data_dir.join("topic-id"), topic.to_string())?;
+            if !cli.json {
+                eprintln!("Syncing {:?}. Press Ctrl-C to stop.", folder);
+            }
+            wait_for_shutdown_signal().await?;
+            shutdown_node(&app_state).await
+        }
+        Command::Status => {
+            let app_state = start_node(&data_dir, &network, Arc::new(LogSink)).await?;
+            let topic = saved_group_ticket(&data_dir)?.map(|ticket| ticket.topic.to_string());
+            let (blob_count, blob_bytes) = app_state
+                .blobs
+                .client()
+                .list()
+                .await?
+                .try_fold((0, 0), |(count, bytes), blob| async move {
+                    Ok((count + 1, bytes + blob.size))
+                })
+                .await?;
+            let output = StatusOutput {
+                node_id: app_state.endpoint.node_id(),
+                data_dir: data_dir.clone(),
+                topic,
+                blob_count,
+                blob_bytes,
+            };
+            shutdown_node(&app_state).await?;
+            print_output(cli.json, &output, |out| {
+                format!(
+                    "node id:  {}\ndata dir: {:?}\ngroup:    {}\nblobs:    {} ({} bytes)",
+                    out.node_id,
+                    out.data_dir,
+                    out.topic.as_deref().unwrap_or("none"),
+                    out.blob_count,
+                    out.blob_bytes
+                )
+            })
+        }
+        Command::Peers { wait } => {
+            let ticket = std::fs::read_to_string(data_dir.join(GROUP_TICKET_FILE))
+                .context("No sync group joined yet. Run `join` or `invite` first.")?;
+            let ticket = ticket.trim();
+            let topic = GossipTicket::from_str(ticket)?.topic.to_string();
+
+            let app_state = start_node(&data_dir, &network, Arc::new(LogSink)).await?;
+            start_gossip(&app_state, ticket).await?;
+            tokio::time::sleep(Duration::from_secs(wait)).await;
+            let peers = list_devices(&app_state).await;
+            shutdown_node(&app_state).await?;
+
+            print_output(cli.json, &PeersOutput { topic, peers }, |out| {
+                match out.peers.is_empty() {
+                    true => "No peers seen.".to_string(),
+                    false => out
+                        .peers
+                        .iter()
+                        .map(|peer| {
+                            format!(
+                                "{}  {}{}",
+                                peer.node_id,
+                                peer.name.as_deref().unwrap_or("(unnamed)"),
+                                if peer.online { "" } else { "  (offline)" }
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                }
+            })
+        }
+    }
+}
+
+async fn start_node(
+    data_dir: &Path,
+    network: &NetworkSettings,
+    events: Arc<dyn EventSink>,
+) -> Result<AppState> {
+    let lifecycle = NodeLifecycle::new(events.clone());
+    setup(data_dir.to_path_buf(), events, lifecycle, network)
+        .await
+        .with_context(|| format!("Failed to start node in {:?}", data_dir))
+}
+
+fn default_data_dir() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .context("Cannot determine home directory. Pass --data-dir.")?;
+    Ok(PathBuf::from(home).join(".fastsync-cli"))
+}
+
+fn saved_group_ticket(data_dir: &Path) -> Result<Option<GossipTicket>> {
+    let path = data_dir.join(GROUP_TICKET_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = std::fs::read_to_string(path)?;
+    GossipTicket::from_str(text.trim()).map(Some)
+}
+
+fn print_output<T: Serialize>(
+    json: bool,
+    value: &T,
+    text: impl FnOnce(&T) -> String,
+) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(value)?);
+    } else {
+        println!("{}", text(value));
+    }
+    Ok(())
+}
+
+/// Prints every event as a single JSON line on stdout.
+struct JsonLinesSink;
+
+impl EventSink for JsonLinesSink {
+    fn emit_json(&self, event: &str, payload: serde_json::Value) -> Result<()> {
+        let line = serde_json::json!({ "event": event, "payload": payload });
+        println!("{}", line);
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+// src-tauri/src/bin/fastsyncd.rs
+
+//! Headless FastSync peer.
+//!
+//! Usage: `fastsyncd [CONFIG]` where `CONFIG` defaults to `fastsyncd.toml`.
+//! Log verbosity follows `RUST_LOG` (default `info,iroh=warn`).
+
+use std::path::PathBuf;
+
+use fastsync_lib::daemon::{self, DaemonConfig};
+use tracing_subscriber::EnvFilter;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info,iroh=warn")),
+        )
+        .init();
+
+    let config_path = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("fastsyncd.toml"));
+    let config = DaemonConfig::load(&config_path)?;
+
+    daemon::run(config).await
+}
@@ -0,0 +1,173 @@
+// src-tauri/src/collection.rs
+
+//! Folders shared as a single ticket.
+//!
+//! `create_iroh_ticket` imports a folder as an iroh-blobs collection: a hash
+//! sequence whose entries are named by their path relative to the folder,
+//! with `/` separators. Downloading fetches the entries one by one, so the
+//! caller can pick which ones it wants and is told as each file completes.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use iroh_blobs::{
+    format::collection::Collection, hashseq::HashSeq, ticket::BlobTicket, BlobFormat, Hash,
+};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    protocol::from_wire_path,
+    state::AppState,
+    storage::{download_blob, export_blob, release_download},
+};
+
+/// One file in a shared folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct CollectionEntry {
+    /// Path relative to the shared folder, `/`-separated.
+    pub name: String,
+    pub hash: Hash,
+}
+
+/// Sent as `blob://collection-progress` after each file of a collection
+/// download has been written.
+#[derive(Debug, Clone, Serialize)]
+pub struct CollectionProgress {
+    pub collection: Hash,
+    pub name: String,
+    /// Size of this file in bytes.
+    pub size: u64,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+}
+
+/// Whether `ticket` points at a collection rather than a single file.
+pub fn is_collection(ticket: &BlobTicket) -> bool {
+    ticket.format() == BlobFormat::HashSeq
+}
+
+/// Fetches the list of files in the collection behind `ticket`, without
+/// their contents.
+pub async fn list_collection(
+    app_state: &AppState,
+    ticket: &BlobTicket,
+) -> Result<Vec<CollectionEntry>> {
+    let collection = fetch_collection(app_state, ticket).await?;
+    Ok(collection
+        .iter()
+        .map(|(name, hash)| CollectionEntry {
+            name: name.clone(),
+            hash: *hash,
+        })
+        .collect())
+}
+
+/// Downloads the collection behind `ticket` and recreates it under
+/// `dest_dir`. With `selection`, only entries named in it, or lying in a
+/// folder named in it, are fetched. Returns the names written.
+pub async fn get_iroh_collection(
+    app_state: &AppState,
+    ticket: &BlobTicket,
+    dest_dir: &Path,
+    selection: Option<&[String]>,
+) -> Result<Vec<String>> {
+    let collection = fetch_collection(app_state, ticket).await?;
+    let entries: Vec<(PathBuf, &String, Hash)> = collection
+        .iter()
+        .filter(|(name, _)| selection.is_none_or(|selection| is_selected(name, selection)))
+        .map(|(name, hash)| {
+            let relative = from_wire_path(name).with_context(|| {
+                format!("Collection entry {:?} points outside the folder", name)
+            })?;
+            Ok((relative, name, *hash))
+        })
+        .collect::<Result<_>>()?;
+    if let Some(selection) = selection {
+        for wanted in selection {
+            if !collection
+                .iter()
+                .any(|(name, _)| is_selected(name, std::slice::from_ref(wanted)))
+            {
+                bail!("No entry {:?} in the shared folder", wanted);
+            }
+        }
+    }
+
+    let node_addr = ticket.node_addr().clone();
+    let files_total = entries.len();
+    let mut bytes_done = 0;
+    let mut written = Vec::with_capacity(files_total);
+    for (files_done, (relative, name, hash)) in entries.into_iter().enumerate() {
+        let outcome = download_blob(app_state, hash, node_addr.clone(), None, None)
+            .await
+            .with_context(|| format!("Failed to download {:?}", name))?;
+        export_blob(app_state, hash, dest_dir.join(relative))
+            .await
+            .with_context(|| format!("Failed to write {:?}", name))?;
+        release_download(&app_state.blobs, hash).await?;
+
+        let size = outcome.local_size + outcome.downloaded_size;
+        bytes_done += size;
+        let progress = CollectionProgress {
+            collection: ticket.hash(),
+            name: name.clone(),
+            size,
+            files_done: files_done + 1,
+            files_total,
+            bytes_done,
+        };
+        app_state
+            .events
+            .emit("blob://collection-progress", &progress)
+            .ok();
+        written.push(name.clone());
+    }
+    info!(
+        "Downloaded {} files ({} bytes) of collection {} to {:?}",
+        files_total,
+        bytes_done,
+        ticket.hash(),
+        dest_dir
+    );
+    Ok(written)
+}
+
+/// Downloads the hash sequence and its name list, which is all that is
+/// needed to read the collection. Neither is kept once read.
+async fn fetch_collection(app_state: &AppState, ticket: &BlobTicket) -> Result<Collection> {
+    if !is_collection(ticket) {
+        bail!("Ticket is for a single file, not a shared folder");
+    }
+    let blobs_client = app_state.blobs.client();
+    let node_addr = ticket.node_addr().clone();
+
+    download_blob(app_state, ticket.hash(), node_addr.clone(), None, None)
+        .await
+        .context("Failed to download the folder index")?;
+    let hash_seq = HashSeq::try_from(blobs_client.read_to_bytes(ticket.hash()).await?)?;
+    let meta = hash_seq
+        .iter()
+        .next()
+        .context("Shared folder index is empty")?;
+    download_blob(app_state, meta, node_addr, None, None)
+        .await
+        .context("Failed to download the folder's file names")?;
+
+    let collection = blobs_client.get_collection(ticket.hash()).await;
+    release_download(&app_state.blobs, ticket.hash()).await?;
+    release_download(&app_state.blobs, meta).await?;
+    collection
+}
+
+/// Whether `name` is named in `selection` or lies in a folder named in it.
+pub(crate) fn is_selected(name: &str, selection: &[String]) -> bool {
+    selection.iter().any(|wanted| {
+        let wanted = wanted.trim_matches('/');
+        name == wanted
+            || name
+                .strip_prefix(wanted)
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
@@ -3,27 +3,40 @@
 use std::{path::PathBuf, str::FromStr};
 
 use crate::{
-    fs_watcher,
+    collection::{self, get_iroh_collection, is_collection, CollectionEntry},
+    control,
+    error::{FastSyncError, FastSyncResult},
+    fs_watcher::switch_sync_folder,
     iroh_fns::{
-        create_iroh_gossip_ticket,
-        create_iroh_ticket,
-        get_iroh_blob,
-        join_iroh_gossip,
-        setup,
-        subscribe_loop,
-        GossipTicket, // Make sure this is correctly imported
+        create_iroh_gossip_ticket, create_iroh_ticket, get_iroh_blob, network_info, setup,
+        shutdown_node, start_gossip, GossipTicket, NetworkInfo,
     },
-    state::AppState, // Removed GossipState as gossip_sender is in AppState
+    pause::{self, PauseStatus, PauseTarget, PendingChange},
+    presence::{self, DeviceInfo},
+    quota::{self, DeferredDownload},
+    settings::{
+        BandwidthSettings, DeviceProfile, MetadataSettings, NetworkSettings, QuotaSettings,
+        SnapshotSettings, SymlinkPolicy, TrashSettings, VersionSettings, AUTO_SYNC_KEY,
+        BANDWIDTH_SETTINGS_KEY, DEVICE_PROFILE_KEY, METADATA_SETTINGS_KEY, NETWORK_SETTINGS_KEY,
+        QUOTA_SETTINGS_KEY, SNAPSHOT_SETTINGS_KEY, SYMLINK_POLICY_KEY, TRASH_SETTINGS_KEY,
+        VERSION_SETTINGS_KEY,
+    },
+    snapshot::{self, SnapshotDiff, SnapshotInfo},
+    state::{AppState, GossipStats, NodeLifecycle, NodeStatus},
+    storage::{self, share_tag, GcReport, StorageStats},
+    trash::{self, TrashEntry},
+    versions::{self, VersionInfo},
 };
-use anyhow::Error;
-use iroh::{NodeId, PublicKey};
+use anyhow::anyhow;
+use iroh::PublicKey;
+use iroh_blobs::ticket::BlobTicket;
 use iroh_gossip::proto::TopicId;
-// use iroh_gossip::net::GossipReceiver; // Not directly used here anymore
 use log::{error, info}; // Added error
-                        // Import necessary types for blobs and docs interaction
-use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Emitter, State};
-use tauri_plugin_store::StoreExt; // Added Manager
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use tauri::{AppHandle, Manager, State};
+use tauri_plugin_store::StoreExt;
+use tokio::sync::OnceCell; // Added Manager
 
 // --- Frontend Event Payloads --- (Keep existing ones)
 
@@ -48,107 +61,770 @@ use tauri_plugin_store::StoreExt; // Added Manager
 //     download_path: Option<String>,
 // }
 
-// --- New Gossip Event Payload (can also be in iroh_fns.rs) ---
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GossipEventPayload {
-    // Made public if subscribe_loop is in another module and needs this
-    pub from: NodeId,
-    pub topic: TopicId,
-    pub file_name: String,
-    pub relative_path: String,
-    pub message_content: String,
-}
-impl GossipEventPayload {
-    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
-        serde_json::from_slice(bytes).map_err(Into::into)
+// --- Command-Specific Structs --- (Keep existing ones)
+
+#[derive(Clone, Serialize, Debug)]
+pub struct NodeInfo {
+    node_id: Option<PublicKey>,
+    /// Why `node_id` is missing, if it is.
+    status: NodeStatus,
+    /// Addresses, relay, discovery and peer paths once the node is up.
+    #[serde(flatten)]
+    network: Option<NetworkInfo>,
+    /// Health of the gossip receiver once the node is up.
+    gossip: Option<GossipStats>,
+}
+
+/// Managed from app start so every command can resolve it, even while the
+/// node itself is still being set up in the background.
+pub struct Node {
+    pub lifecycle: NodeLifecycle,
+    state: OnceCell<AppState>,
+}
+
+impl Node {
+    pub fn new(handle: &AppHandle) -> Self {
+        Self {
+            lifecycle: NodeLifecycle::new(Arc::new(handle.clone())),
+            state: OnceCell::new(),
+        }
+    }
+
+    /// The node if setup has completed, whatever its current status.
+    pub fn started(&self) -> Option<&AppState> {
+        self.state.get()
     }
 
-    pub fn to_vec(&self) -> Vec<u8> {
-        serde_json::to_vec(self).expect("serde_json::to_vec is infallible")
+    /// The node, or `NotInitialized` saying why it cannot be used yet.
+    pub fn app_state(&self) -> FastSyncResult<&AppState> {
+        match (self.state.get(), self.lifecycle.status()) {
+            (_, NodeStatus::ShuttingDown) => Err(FastSyncError::NotInitialized(
+                "The node is shutting down".to_string(),
+            )),
+            (Some(app_state), _) => Ok(app_state),
+            (None, NodeStatus::Failed(reason)) => Err(FastSyncError::NotInitialized(format!(
+                "The node failed to start: {}",
+                reason
+            ))),
+            (None, _) => Err(FastSyncError::NotInitialized(
+                "The node is still starting".to_string(),
+            )),
+        }
+    }
+
+    /// Sets the node up, or waits for a setup already in progress. A failed
+    /// setup is recorded as `Failed` and retried by the next call.
+    pub async fn start(&self, handle: &AppHandle) -> FastSyncResult<&AppState> {
+        if self.lifecycle.status() == NodeStatus::ShuttingDown {
+            return self.app_state();
+        }
+        self.state
+            .get_or_try_init(|| async {
+                let result = start_node(handle, self.lifecycle.clone()).await;
+                if let Err(e) = &result {
+                    self.lifecycle.set(NodeStatus::Failed(e.to_string()));
+                }
+                result
+            })
+            .await
     }
 }
-// --- Command-Specific Structs --- (Keep existing ones)
 
-#[derive(Clone, Serialize, Debug)]
-pub struct FileEntryInfo {
-    filename: String,
-    hash: String,
-    size: u64,
+async fn start_node(handle: &AppHandle, lifecycle: NodeLifecycle) -> FastSyncResult<AppState> {
+    let store = handle.store("store.json")?;
+    // e.g. "127.0.0.1:7878"; the control API stays off when unset.
+    let control_listen = store
+        .get("control-api-listen")
+        .and_then(|value| value.as_str().map(str::to_owned));
+    let network = store.get(NETWORK_SETTINGS_KEY);
+    let device = store.get(DEVICE_PROFILE_KEY);
+    let snapshots = store.get(SNAPSHOT_SETTINGS_KEY);
+    let versions = store.get(VERSION_SETTINGS_KEY);
+    let trash_settings = store.get(TRASH_SETTINGS_KEY);
+    let quotas = store.get(QUOTA_SETTINGS_KEY);
+    let bandwidth = store.get(BANDWIDTH_SETTINGS_KEY);
+    let metadata = store.get(METADATA_SETTINGS_KEY);
+    let symlinks = store.get(SYMLINK_POLICY_KEY);
+    let auto_sync = store
+        .get(AUTO_SYNC_KEY)
+        .and_then(|value| value.as_bool())
+        .unwrap_or(true);
+    store.close_resource();
+    let network = parse_setting::<NetworkSettings>(network, "network settings")?;
+
+    info!("Starting Iroh setup...");
+    let data_root = handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| FastSyncError::Io(format!("No app data directory: {}", e)))?;
+    let app_state = setup(
+        data_root.clone(),
+        Arc::new(handle.clone()),
+        lifecycle,
+        &network,
+    )
+    .await
+    .inspect_err(|err| error!("❌❌❌ Iroh setup failed: {:?}", err))
+    .map_err(|e| FastSyncError::classify("Iroh setup failed", e, FastSyncError::Internal))?;
+    info!("Iroh Setup successful");
+
+    // A broken saved profile only costs the name, so fall back to the default.
+    let device = device.map(|value| {
+        let profile = serde_json::from_value::<DeviceProfile>(value)?;
+        profile.validate()?;
+        anyhow::Ok(profile)
+    });
+    match device {
+        Some(Ok(profile)) => *app_state.device.lock().await = profile,
+        Some(Err(e)) => error!("Ignoring invalid device profile: {:#}", e),
+        None => {}
+    }
+    // Saved settings are checked like new ones: an older version or a
+    // hand-edited store may hold values the setters would refuse.
+    match parse_valid_setting(snapshots, "snapshot settings", SnapshotSettings::validate) {
+        Ok(settings) => snapshot::apply_snapshot_settings(&app_state, settings).await,
+        Err(e) => error!("Automatic snapshots off: {}", e),
+    }
+    match parse_valid_setting(versions, "version settings", VersionSettings::validate) {
+        Ok(settings) => {
+            *app_state
+                .version_settings
+                .lock()
+                .expect("version settings poisoned") = settings
+        }
+        Err(e) => error!("Using default version settings: {}", e),
+    }
+    let trash_settings =
+        parse_valid_setting(trash_settings, "trash settings", TrashSettings::validate)
+            .unwrap_or_else(|e| {
+                error!("Using default trash settings: {}", e);
+                TrashSettings::default()
+            });
+    if let Err(e) = trash::apply_trash_settings(&app_state, trash_settings).await {
+        error!("Failed to purge the trash: {:#}", e);
+    }
+    match parse_valid_setting(quotas, "quota settings", QuotaSettings::validate) {
+        Ok(settings) => quota::apply_quota_settings(&app_state, settings),
+        Err(e) => error!("Quotas off: {}", e),
+    }
+    match parse_valid_setting(bandwidth, "bandwidth settings", BandwidthSettings::validate) {
+        Ok(settings) => app_state.bandwidth.apply(settings),
+        Err(e) => error!("Bandwidth unlimited: {}", e),
+    }
+    match parse_setting::<MetadataSettings>(metadata, "metadata settings") {
+        Ok(settings) => {
+            *app_state
+                .metadata_settings
+                .lock()
+                .expect("metadata settings poisoned") = settings
+        }
+        Err(e) => error!("Using default metadata settings: {}", e),
+    }
+    match parse_setting::<SymlinkPolicy>(symlinks, "symlink policy") {
+        Ok(policy) => {
+            *app_state
+                .symlink_policy
+                .lock()
+                .expect("symlink policy poisoned") = policy
+        }
+        Err(e) => error!("Following links: {}", e),
+    }
+    if let Err(e) = pause::set_manual(&app_state, !auto_sync).await {
+        error!("Failed to apply auto-sync: {:#}", e);
+    }
+
+    // The control API is optional, so a bad address must not stop syncing.
+    if let Some(listen) = control_listen {
+        let started = match listen.parse() {
+            Ok(addr) => control::serve(&app_state, addr, &data_root).await,
+            Err(e) => Err(anyhow!("Invalid control-api-listen {:?}: {}", listen, e)),
+        };
+        if let Err(e) = started {
+            error!("Control API not started: {:?}", e);
+        }
+    }
+    Ok(app_state)
 }
 
-#[derive(Clone, Serialize, Debug)]
-pub struct ShareTicketResponse {
-    ticket: String,
+/// Reads a settings value from the store, `T::default()` when it is unset.
+fn parse_setting<T: DeserializeOwned + Default>(
+    value: Option<serde_json::Value>,
+    what: &str,
+) -> FastSyncResult<T> {
+    let Some(value) = value else {
+        return Ok(T::default());
+    };
+    serde_json::from_value(value)
+        .map_err(|e| FastSyncError::InvalidSetting(format!("Invalid {}: {}", what, e)))
 }
 
-#[derive(Clone, Serialize, Debug)]
-pub struct NodeInfo {
-    node_id: Option<PublicKey>,
+/// [`parse_setting`], then `validate`; invalid values are an error too.
+fn parse_valid_setting<T: DeserializeOwned + Default>(
+    value: Option<serde_json::Value>,
+    what: &str,
+    validate: impl FnOnce(&T) -> anyhow::Result<()>,
+) -> FastSyncResult<T> {
+    let setting = parse_setting(value, what)?;
+    validate(&setting)
+        .map_err(|e| FastSyncError::InvalidSetting(format!("Invalid {}: {:#}", what, e)))?;
+    Ok(setting)
+}
+
+#[tauri::command]
+pub async fn get_network_settings(app: AppHandle) -> FastSyncResult<NetworkSettings> {
+    let store = app.store("store.json")?;
+    let network = store.get(NETWORK_SETTINGS_KEY);
+    store.close_resource();
+    parse_setting(network, "network settings")
 }
 
+/// Saves network settings. They are applied when the node next starts.
 #[tauri::command]
-pub async fn get_node_info(state: State<'_, AppState>) -> Result<NodeInfo, String> {
-    let endpoint = state.endpoint.clone();
+pub async fn set_network_settings(app: AppHandle, settings: NetworkSettings) -> FastSyncResult<()> {
+    settings
+        .validate()
+        .map_err(|e| FastSyncError::InvalidSetting(format!("{:#}", e)))?;
 
-    let node_id = match endpoint {
-        Some(e) => Some(e.node_id()),
-        None => None,
-    };
+    let store = app.store("store.json")?;
+    store.set(
+        NETWORK_SETTINGS_KEY,
+        serde_json::to_value(&settings).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_node_info(node: State<'_, Node>) -> FastSyncResult<NodeInfo> {
+    let node_id = node.started().map(|state| state.endpoint.node_id());
+
+    Ok(NodeInfo {
+        node_id,
+        status: node.lifecycle.status(),
+        network: node.started().map(network_info),
+        gossip: node.started().map(|state| {
+            state
+                .gossip_stats
+                .lock()
+                .expect("gossip stats poisoned")
+                .clone()
+        }),
+    })
+}
+
+#[tauri::command]
+pub async fn get_device_profile(node: State<'_, Node>) -> FastSyncResult<DeviceProfile> {
+    Ok(node.app_state()?.device.lock().await.clone())
+}
+
+/// Saves the device name and colour and announces them to peers.
+#[tauri::command]
+pub async fn set_device_profile(
+    app: AppHandle,
+    node: State<'_, Node>,
+    profile: DeviceProfile,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    profile
+        .validate()
+        .map_err(|e| FastSyncError::InvalidSetting(e.to_string()))?;
+
+    let store = app.store("store.json")?;
+    store.set(
+        DEVICE_PROFILE_KEY,
+        serde_json::to_value(&profile).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
+
+    presence::set_device_profile(state, profile)
+        .await
+        .map_err(|e| {
+            FastSyncError::classify(
+                "Failed to announce device profile",
+                e,
+                FastSyncError::Network,
+            )
+        })
+}
 
-    Ok(NodeInfo { node_id })
+/// Peers seen on the topic, with their names and online state. Changes are
+/// also emitted as `devices://changed`.
+#[tauri::command]
+pub async fn list_devices(node: State<'_, Node>) -> FastSyncResult<Vec<DeviceInfo>> {
+    Ok(presence::list_devices(node.app_state()?).await)
 }
 
+/// Current lifecycle status. Changes are also emitted as `node://status`.
+#[tauri::command]
+pub async fn node_status(node: State<'_, Node>) -> FastSyncResult<NodeStatus> {
+    Ok(node.lifecycle.status())
+}
+
+/// Downloads the file or shared folder behind `str_ticket` to
+/// `str_dest_path`. For a folder, `entries` limits the download to the named
+/// files and folders; progress is reported as `blob://collection-progress`.
 #[tauri::command]
 pub async fn get_blob(
-    state: State<'_, AppState>,
+    node: State<'_, Node>,
     str_ticket: String,
     str_dest_path: String,
-) -> Result<(), String> {
-    let blobs = state
-        .blobs
-        .clone()
-        .ok_or_else(|| "Iroh blobs client not initialized".to_string())?;
+    entries: Option<Vec<String>>,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
     let dest_path = PathBuf::from(str_dest_path);
-    get_iroh_blob(blobs, str_ticket, dest_path)
+    if !dest_path.is_absolute() {
+        return Err(FastSyncError::PathRejected(format!(
+            "Destination {:?} must be an absolute path",
+            dest_path
+        )));
+    }
+    let ticket = parse_blob_ticket(&str_ticket)?;
+
+    let result = if is_collection(&ticket) {
+        get_iroh_collection(state, &ticket, &dest_path, entries.as_deref())
+            .await
+            .map(|_| ())
+    } else {
+        get_iroh_blob(state, str_ticket, dest_path, None, None)
+            .await
+            .map(|_| ())
+    };
+    result.map_err(|e| {
+        FastSyncError::classify(
+            "Failed to complete blob download",
+            e,
+            FastSyncError::Network,
+        )
+    })
+}
+
+/// Lists the files in the shared folder behind `str_ticket`.
+#[tauri::command]
+pub async fn list_collection(
+    node: State<'_, Node>,
+    str_ticket: String,
+) -> FastSyncResult<Vec<CollectionEntry>> {
+    let state = node.app_state()?;
+    let ticket = parse_blob_ticket(&str_ticket)?;
+    if !is_collection(&ticket) {
+        return Err(FastSyncError::InvalidTicket(
+            "Ticket is for a single file, not a shared folder".to_string(),
+        ));
+    }
+    collection::list_collection(state, &ticket)
+        .await
+        .map_err(|e| {
+            FastSyncError::classify("Failed to list shared folder", e, FastSyncError::Network)
+        })
+}
+
+fn parse_blob_ticket(str_ticket: &str) -> FastSyncResult<BlobTicket> {
+    BlobTicket::from_str(str_ticket)
+        .map_err(|e| FastSyncError::InvalidTicket(format!("Invalid blob ticket: {}", e)))
+}
+
+/// Captures the sync folder as a snapshot named `name`. Also emitted as
+/// `snapshots://created`.
+#[tauri::command]
+pub async fn create_snapshot(node: State<'_, Node>, name: String) -> FastSyncResult<SnapshotInfo> {
+    let state = node.app_state()?;
+    snapshot::create_snapshot(state, &name).await.map_err(|e| {
+        FastSyncError::classify("Failed to create snapshot", e, FastSyncError::Internal)
+    })
+}
+
+/// All snapshots, oldest first.
+#[tauri::command]
+pub async fn list_snapshots(node: State<'_, Node>) -> FastSyncResult<Vec<SnapshotInfo>> {
+    let state = node.app_state()?;
+    snapshot::list_snapshots(state).await.map_err(|e| {
+        FastSyncError::classify("Failed to list snapshots", e, FastSyncError::Internal)
+    })
+}
+
+/// Files added, removed and modified going from snapshot `from` to `to`.
+#[tauri::command]
+pub async fn compare_snapshots(
+    node: State<'_, Node>,
+    from: String,
+    to: String,
+) -> FastSyncResult<SnapshotDiff> {
+    let state = node.app_state()?;
+    snapshot::compare_snapshots(state, &from, &to)
+        .await
+        .map_err(|e| {
+            FastSyncError::classify("Failed to compare snapshots", e, FastSyncError::Internal)
+        })
+}
+
+/// Restores snapshot `id` into the sync folder, or only `entries` of it, and
+/// syncs the restored files to peers.
+#[tauri::command]
+pub async fn restore_snapshot(
+    node: State<'_, Node>,
+    id: String,
+    entries: Option<Vec<String>>,
+) -> FastSyncResult<Vec<String>> {
+    let state = node.app_state()?;
+    snapshot::restore_snapshot(state, &id, entries.as_deref())
+        .await
+        .map_err(|e| FastSyncError::classify("Failed to restore snapshot", e, FastSyncError::Io))
+}
+
+#[tauri::command]
+pub async fn delete_snapshot(node: State<'_, Node>, id: String) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    snapshot::delete_snapshot(state, &id).await.map_err(|e| {
+        FastSyncError::classify("Failed to delete snapshot", e, FastSyncError::Internal)
+    })
+}
+
+#[tauri::command]
+pub async fn get_snapshot_settings(app: AppHandle) -> FastSyncResult<SnapshotSettings> {
+    let store = app.store("store.json")?;
+    let snapshots = store.get(SNAPSHOT_SETTINGS_KEY);
+    store.close_resource();
+    parse_setting(snapshots, "snapshot settings")
+}
+
+/// Saves the automatic snapshot schedule and applies it right away.
+#[tauri::command]
+pub async fn set_snapshot_settings(
+    app: AppHandle,
+    node: State<'_, Node>,
+    settings: SnapshotSettings,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    settings
+        .validate()
+        .map_err(|e| FastSyncError::InvalidSetting(e.to_string()))?;
+
+    let store = app.store("store.json")?;
+    store.set(
+        SNAPSHOT_SETTINGS_KEY,
+        serde_json::to_value(&settings).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
+
+    snapshot::apply_snapshot_settings(state, settings).await;
+    Ok(())
+}
+
+/// Earlier versions of the file at `relative_path` in the sync folder,
+/// newest first.
+#[tauri::command]
+pub async fn list_versions(
+    node: State<'_, Node>,
+    relative_path: String,
+) -> FastSyncResult<Vec<VersionInfo>> {
+    let state = node.app_state()?;
+    versions::list_versions(state, &relative_path)
+        .await
+        .map_err(|e| FastSyncError::classify("Failed to list versions", e, FastSyncError::Internal))
+}
+
+/// Puts version `id` back in place and syncs it to peers. Returns the
+/// restored path.
+#[tauri::command]
+pub async fn restore_version(node: State<'_, Node>, id: String) -> FastSyncResult<String> {
+    let state = node.app_state()?;
+    versions::restore_version(state, &id)
         .await
-        .map_err(|e| format!("Failed to complete blob download: {}", e))?;
+        .map_err(|e| FastSyncError::classify("Failed to restore version", e, FastSyncError::Io))
+}
+
+#[tauri::command]
+pub async fn get_version_settings(node: State<'_, Node>) -> FastSyncResult<VersionSettings> {
+    let state = node.app_state()?;
+    Ok(state
+        .version_settings
+        .lock()
+        .expect("version settings poisoned")
+        .clone())
+}
+
+/// Saves how many versions to keep. Applies to the next change of each file.
+#[tauri::command]
+pub async fn set_version_settings(
+    app: AppHandle,
+    node: State<'_, Node>,
+    settings: VersionSettings,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    settings
+        .validate()
+        .map_err(|e| FastSyncError::InvalidSetting(e.to_string()))?;
 
+    let store = app.store("store.json")?;
+    store.set(
+        VERSION_SETTINGS_KEY,
+        serde_json::to_value(&settings).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
+
+    *state
+        .version_settings
+        .lock()
+        .expect("version settings poisoned") = settings;
     Ok(())
 }
 
+/// Files deleted by peers, most recently deleted first.
 #[tauri::command]
-pub async fn create_ticket(state: State<'_, AppState>, filepath: String) -> Result<String, String> {
-    let path: PathBuf = PathBuf::from(filepath);
+pub async fn list_trash(node: State<'_, Node>) -> FastSyncResult<Vec<TrashEntry>> {
+    let state = node.app_state()?;
+    trash::list_trash(state).await.map_err(|e| {
+        FastSyncError::classify("Failed to list the trash", e, FastSyncError::Internal)
+    })
+}
+
+/// Puts trash entry `id` back in place and syncs it to peers. Returns the
+/// restored path.
+#[tauri::command]
+pub async fn restore_from_trash(node: State<'_, Node>, id: String) -> FastSyncResult<String> {
+    let state = node.app_state()?;
+    trash::restore_from_trash(state, &id).await.map_err(|e| {
+        FastSyncError::classify("Failed to restore from the trash", e, FastSyncError::Io)
+    })
+}
+
+#[tauri::command]
+pub async fn empty_trash(node: State<'_, Node>) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    trash::empty_trash(state).await.map_err(|e| {
+        FastSyncError::classify("Failed to empty the trash", e, FastSyncError::Internal)
+    })
+}
 
-    let blobs = state
-        .blobs
-        .clone()
-        .ok_or_else(|| "Iroh blobs client not initialized".to_string())?;
+#[tauri::command]
+pub async fn get_trash_settings(node: State<'_, Node>) -> FastSyncResult<TrashSettings> {
+    let state = node.app_state()?;
+    Ok(state
+        .trash_settings
+        .lock()
+        .expect("trash settings poisoned")
+        .clone())
+}
 
-    let endpoint = state
-        .endpoint
-        .clone()
-        .ok_or_else(|| "Endpoint not initialized".to_string())?;
+/// Saves the trash retention period and purges what it no longer keeps.
+#[tauri::command]
+pub async fn set_trash_settings(
+    app: AppHandle,
+    node: State<'_, Node>,
+    settings: TrashSettings,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    settings
+        .validate()
+        .map_err(|e| FastSyncError::InvalidSetting(e.to_string()))?;
+
+    let store = app.store("store.json")?;
+    store.set(
+        TRASH_SETTINGS_KEY,
+        serde_json::to_value(&settings).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
 
-    let str_ticket = create_iroh_ticket(blobs, endpoint, path)
+    trash::apply_trash_settings(state, settings)
         .await
-        .map_err(|e| format!("Endpoint not initialized {}", e))?;
+        .map_err(|e| {
+            FastSyncError::classify("Failed to purge the trash", e, FastSyncError::Internal)
+        })
+}
 
-    Ok(str_ticket)
+#[tauri::command]
+pub async fn storage_stats(node: State<'_, Node>) -> FastSyncResult<StorageStats> {
+    let state = node.app_state()?;
+    storage::storage_stats(state).await.map_err(|e| {
+        FastSyncError::classify("Failed to measure the blob store", e, FastSyncError::Io)
+    })
+}
+
+#[tauri::command]
+pub async fn get_quota_settings(node: State<'_, Node>) -> FastSyncResult<QuotaSettings> {
+    let state = node.app_state()?;
+    Ok(quota::quota_settings(state))
+}
+
+/// Saves the quotas and download size policy. Applies to the next download.
+#[tauri::command]
+pub async fn set_quota_settings(
+    app: AppHandle,
+    node: State<'_, Node>,
+    settings: QuotaSettings,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    settings
+        .validate()
+        .map_err(|e| FastSyncError::InvalidSetting(e.to_string()))?;
+
+    let store = app.store("store.json")?;
+    store.set(
+        QUOTA_SETTINGS_KEY,
+        serde_json::to_value(&settings).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
+
+    quota::apply_quota_settings(state, settings);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_bandwidth_settings(node: State<'_, Node>) -> FastSyncResult<BandwidthSettings> {
+    let state = node.app_state()?;
+    Ok(state.bandwidth.settings())
+}
+
+/// Saves the transfer limits and schedule. Waiting transfers pick them up at
+/// once; running ones finish as they are.
+#[tauri::command]
+pub async fn set_bandwidth_settings(
+    app: AppHandle,
+    node: State<'_, Node>,
+    settings: BandwidthSettings,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    settings
+        .validate()
+        .map_err(|e| FastSyncError::InvalidSetting(format!("{:#}", e)))?;
+
+    let store = app.store("store.json")?;
+    store.set(
+        BANDWIDTH_SETTINGS_KEY,
+        serde_json::to_value(&settings).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
+
+    state.bandwidth.apply(settings);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_metadata_settings(node: State<'_, Node>) -> FastSyncResult<MetadataSettings> {
+    let state = node.app_state()?;
+    Ok(*state
+        .metadata_settings
+        .lock()
+        .expect("metadata settings poisoned"))
 }
 
+/// Saves which file attributes are synced. Applies to files announced or
+/// received from now on.
 #[tauri::command]
-pub async fn create_gossip_ticket(
+pub async fn set_metadata_settings(
     app: AppHandle,
-    state: State<'_, AppState>,
-) -> Result<String, String> {
-    let endpoint = state
-        .endpoint
-        .clone()
-        .ok_or_else(|| "Endpoint not initialized".to_string())?;
+    node: State<'_, Node>,
+    settings: MetadataSettings,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    let store = app.store("store.json")?;
+    store.set(
+        METADATA_SETTINGS_KEY,
+        serde_json::to_value(settings).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
+
+    *state
+        .metadata_settings
+        .lock()
+        .expect("metadata settings poisoned") = settings;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_symlink_policy(node: State<'_, Node>) -> FastSyncResult<SymlinkPolicy> {
+    let state = node.app_state()?;
+    Ok(*state
+        .symlink_policy
+        .lock()
+        .expect("symlink policy poisoned"))
+}
+
+/// Saves what happens to links in the sync folder. Applies to links
+/// announced or received from now on.
+#[tauri::command]
+pub async fn set_symlink_policy(
+    app: AppHandle,
+    node: State<'_, Node>,
+    policy: SymlinkPolicy,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    let store = app.store("store.json")?;
+    store.set(
+        SYMLINK_POLICY_KEY,
+        serde_json::to_value(policy).map_err(|e| FastSyncError::Internal(e.to_string()))?,
+    );
+    store.save()?;
+    store.close_resource();
+
+    *state
+        .symlink_policy
+        .lock()
+        .expect("symlink policy poisoned") = policy;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn deferred_downloads(node: State<'_, Node>) -> FastSyncResult<Vec<DeferredDownload>> {
+    let state = node.app_state()?;
+    Ok(quota::deferred_downloads(state))
+}
 
-    let store = app.store("store.json").map_err(|e| e.to_string())?;
+/// Downloads a file held back by the size policy or a quota.
+#[tauri::command]
+pub async fn fetch_deferred(node: State<'_, Node>, relative_path: String) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    quota::fetch_deferred(state, &relative_path)
+        .await
+        .map_err(|e| FastSyncError::classify("Failed to fetch file", e, FastSyncError::Network))
+}
+
+/// Releases stale tags; the space is freed by the next garbage collection.
+/// With `release_legacy`, tickets shared before tags had names stop working.
+#[tauri::command]
+pub async fn collect_garbage(
+    node: State<'_, Node>,
+    release_legacy: Option<bool>,
+) -> FastSyncResult<GcReport> {
+    let state = node.app_state()?;
+    storage::collect_garbage(state, release_legacy.unwrap_or(false))
+        .await
+        .map_err(|e| {
+            FastSyncError::classify("Failed to collect garbage", e, FastSyncError::Internal)
+        })
+}
+
+#[tauri::command]
+pub async fn create_ticket(node: State<'_, Node>, filepath: String) -> FastSyncResult<String> {
+    let state = node.app_state()?;
+    let path: PathBuf = PathBuf::from(filepath);
+    if !path.is_absolute() {
+        return Err(FastSyncError::PathRejected(format!(
+            "{:?} must be an absolute path",
+            path
+        )));
+    }
+
+    let tag = share_tag(&path);
+    let str_ticket = create_iroh_ticket(state.blobs.clone(), state.endpoint.clone(), path, tag)
+        .await
+        .map_err(|e| FastSyncError::classify("Failed to import", e, FastSyncError::Io))?;
+
+    Ok(str_ticket)
+}
 
+#[tauri::command]
+pub async fn create_gossip_ticket(app: AppHandle, node: State<'_, Node>) -> FastSyncResult<String> {
+    let state = node.app_state()?;
+    let store = app.store("store.json")?;
     let topic_id: TopicId = match store.get("topic-id") {
         Some(json_value) => {
             // Attempt to deserialize the JsonValue to TopicId
@@ -168,18 +844,19 @@ pub async fn create_gossip_ticket(
         None => {
             // TopicId not found in store, generate a new one, store it, and use it.
             info!("No topic-id found in store. Generating and storing a new one.");
-            let new_topic_id = TopicId::from_bytes(rand::random());
-            new_topic_id
+            TopicId::from_bytes(rand::random())
         }
     };
 
-    store.set("topic-id", topic_id.clone().to_string());
-    store.save().map_err(|e| e.to_string())?;
+    store.set("topic-id", topic_id.to_string());
+    store.save()?;
     store.close_resource();
 
-    let str_gossip_ticket = create_iroh_gossip_ticket(endpoint, topic_id)
+    let str_gossip_ticket = create_iroh_gossip_ticket(state.endpoint.clone(), topic_id)
         .await
-        .map_err(|e| format!("Endpoint not initialized {}", e))?;
+        .map_err(|e| {
+            FastSyncError::classify("Failed to create gossip ticket", e, FastSyncError::Network)
+        })?;
 
     Ok(str_gossip_ticket)
 }
@@ -187,168 +864,189 @@ pub async fn create_gossip_ticket(
 #[tauri::command]
 pub async fn join_gossip(
     app_handle: AppHandle,
-    app_state: State<'_, AppState>,
+    node: State<'_, Node>,
     str_gossip_ticket: String,
-) -> Result<bool, String> {
+) -> FastSyncResult<bool> {
     info!("join_gossip command started.");
-
-    let endpoint = app_state
-        .endpoint
-        .clone()
-        .ok_or_else(|| "Endpoint not initialized".to_string())?;
-    info!("Endpoint obtained.");
-
-    let gossip = app_state
-        .gossip
-        .clone()
-        .ok_or_else(|| "Gossip not initialized".to_string())?;
-    info!("Gossip handler obtained.");
+    let app_state = node.app_state()?;
 
     let GossipTicket { topic, nodes: _ } =
         GossipTicket::from_str(&str_gossip_ticket).map_err(|e| {
-            format!(
-                " Failed to Parse Gossip ticket {}: {}",
+            FastSyncError::InvalidTicket(format!(
+                "Failed to parse gossip ticket {}: {}",
                 str_gossip_ticket, e
-            )
+            ))
         })?;
     info!("Gossip ticket parsed, topic: {:?}", topic);
-    let store = app_handle.store("store.json").map_err(|e| e.to_string())?;
-    store.set("topic-id", topic.clone().to_string());
-    store.save().map_err(|e| e.to_string())?;
+    let store = app_handle.store("store.json")?;
+    store.set("topic-id", topic.to_string());
+    store.save()?;
     store.close_resource();
 
-    // Scope the lock for gossip_topic to release it before the await
-    {
-        info!("Attempting to lock gossip_topic in AppState.");
-        let mut gossip_topic_guard = app_state.gossip_topic.lock().await;
-        *gossip_topic_guard = Some(topic.clone()); // Clone topic for logging/potential reuse, ensure original moves
-        info!("gossip_topic in AppState set and lock released.");
-    } // gossip_topic_guard is dropped here, and the lock is released.
-
-    info!("Calling join_iroh_gossip (iroh_fns.rs)...");
-    let (sender, receiver) =
-        match join_iroh_gossip(endpoint, gossip, str_gossip_ticket.clone()).await {
-            Ok(res) => {
-                info!("join_iroh_gossip (iroh_fns.rs) successful.");
-                res
-            }
-            Err(e) => {
-                error!("join_iroh_gossip (iroh_fns.rs) failed: {}", e);
-                return Err(format!(
-                    "Failed to create Gossip Sender and Receiver: {}",
-                    e
-                ));
-            }
-        };
-
-    // Correctly store the sender in AppState's Mutex<Option<GossipSender>>
-    {
-        info!("Attempting to lock gossip_sender in AppState.");
-        let mut gossip_sender_guard = app_state.gossip_sender.lock().await;
-        *gossip_sender_guard = Some(sender);
-        info!("gossip_sender in AppState set and lock released.");
+    if let Err(e) = start_gossip(app_state, &str_gossip_ticket).await {
+        error!("start_gossip (iroh_fns.rs) failed: {}", e);
+        return Err(FastSyncError::classify(
+            "Failed to join the sync group",
+            e,
+            FastSyncError::Network,
+        ));
     }
 
-    // Spawn a task to handle incoming gossip messages
-    // Pass the AppHandle to subscribe_loop so it can emit events
-    let receiver_app_handle = app_handle.clone();
-    let blobs = app_state
-        .blobs
-        .clone()
-        .ok_or_else(|| "Iroh blobs client not initialized".to_string())?;
-    let sync_path = app_state.sync_folder.clone();
-    tauri::async_runtime::spawn(async move {
-        info!("Gossip receiver task (subscribe_loop) started.");
-        // Assuming subscribe_loop now takes AppHandle and GossipReceiver
-        if let Err(e) = subscribe_loop(receiver_app_handle, blobs, sync_path, receiver).await {
-            error!("Error in subscribe_loop: {:?}", e);
-        }
-        info!("Gossip receiver task (subscribe_loop) finished.");
-    });
-    info!("subscribe_loop task spawned.");
+    Ok(true)
+}
 
-    // Emit an event to the frontend indicating that gossip is ready
-    app_handle
-        .emit("gossip-ready", ())
-        .map_err(|e| format!("Failed to emit gossip-ready event: {}", e))?;
-    info!("Emitted gossip-ready event to frontend.");
+/// Pauses `target`, everything when it is left out. Local changes are
+/// queued and changes from peers wait until it is resumed.
+#[tauri::command]
+pub async fn pause_sync(node: State<'_, Node>, target: Option<PauseTarget>) -> FastSyncResult<()> {
+    pause::pause(node.app_state()?, target.unwrap_or(PauseTarget::All))
+        .map_err(|e| FastSyncError::classify("Failed to pause", e, FastSyncError::Io))
+}
 
-    Ok(true)
+/// Resumes `target`, everything when it is left out, and replays what was
+/// queued for it.
+#[tauri::command]
+pub async fn resume_sync(node: State<'_, Node>, target: Option<PauseTarget>) -> FastSyncResult<()> {
+    pause::resume(node.app_state()?, target.unwrap_or(PauseTarget::All))
+        .await
+        .map_err(|e| FastSyncError::classify("Failed to resume", e, FastSyncError::Io))
 }
 
-// Handle incoming events
 #[tauri::command]
-pub async fn setup_iroh_and_fs(app: AppHandle) -> Result<(), String> {
-    handle_setup(app)
+pub async fn pause_status(node: State<'_, Node>) -> FastSyncResult<PauseStatus> {
+    Ok(pause::pause_status(node.app_state()?))
+}
+
+/// Saves the auto-sync preference. Turning it off makes changes wait for
+/// `sync_now`; turning it on syncs what was waiting.
+#[tauri::command]
+pub async fn set_auto_sync(
+    app: AppHandle,
+    node: State<'_, Node>,
+    enabled: bool,
+) -> FastSyncResult<()> {
+    let state = node.app_state()?;
+    let store = app.store("store.json")?;
+    store.set(AUTO_SYNC_KEY, enabled);
+    store.save()?;
+    store.close_resource();
+
+    pause::set_manual(state, !enabled)
         .await
-        .map_err(|e| format!("Iroh & Fs Setup failed {}", e))?;
+        .map_err(|e| FastSyncError::classify("Failed to sync", e, FastSyncError::Io))
+}
+
+/// Sends and applies the changes waiting for a manual sync. Paused ones
+/// keep waiting.
+#[tauri::command]
+pub async fn sync_now(node: State<'_, Node>) -> FastSyncResult<()> {
+    pause::sync_now(node.app_state()?)
+        .await
+        .map_err(|e| FastSyncError::classify("Failed to sync", e, FastSyncError::Io))
+}
+
+#[tauri::command]
+pub async fn pending_changes(node: State<'_, Node>) -> FastSyncResult<Vec<PendingChange>> {
+    Ok(pause::pending_changes(node.app_state()?).await)
+}
 
+/// Shuts the node down gracefully and then exits the app.
+#[tauri::command]
+pub async fn shutdown(app: AppHandle, node: State<'_, Node>) -> FastSyncResult<()> {
+    if let Some(state) = node.started() {
+        shutdown_node(state)
+            .await
+            .map_err(|e| FastSyncError::classify("Shutdown failed", e, FastSyncError::Internal))?;
+    }
+    app.exit(0);
     Ok(())
 }
 
-pub async fn handle_setup(handle: AppHandle) -> Result<(), Error> {
+// Handle incoming events
+#[tauri::command]
+pub async fn setup_iroh_and_fs(app: AppHandle) -> FastSyncResult<()> {
+    handle_setup(app).await
+}
+
+pub async fn handle_setup(handle: AppHandle) -> FastSyncResult<()> {
     let store = handle.store("store.json")?;
-    let path_to_watch_str = store
+    let path_to_watch = store
         .get("sync-folder-path")
-        .expect("Failed to get path to watch from store");
-
-    let path_to_watch_str = path_to_watch_str.as_str().unwrap();
-    let path_to_watch = PathBuf::from(path_to_watch_str);
-    let path_to_watch_clone = PathBuf::from(path_to_watch_str);
+        .and_then(|value| value.as_str().map(PathBuf::from));
 
     // Remove the store from the resource table
     store.close_resource();
 
-    // Spawn the async Iroh setup task
-    let iroh_handle = handle.clone();
-    tauri::async_runtime::spawn(async move {
-        info!("Starting Iroh setup...");
-        // Clone handle again for use inside this task
-        match setup(iroh_handle.clone(), path_to_watch_clone).await {
-            // Pass cloned handle
-            Ok(()) => {
-                info!("Iroh Setup successful")
-            }
-            Err(err) => {
-                error!("❌❌❌ Iroh setup failed: {:?}", err);
-            }
-        }
-    });
+    let path_to_watch = path_to_watch.ok_or_else(|| {
+        FastSyncError::NotInitialized(
+            "No sync folder configured. Select a folder to sync first.".to_string(),
+        )
+    })?;
 
-    // --- Spawn Filesystem Watcher Task ---
-    let fs_handle = handle.clone(); // Clone handle for FS Watcher task
-    tauri::async_runtime::spawn(async move {
-        info!("Starting Filesystem Watcher setup...");
-
-        // Determine path to watch (e.g., Documents directory)
-        // In a real app, get this from config or user selection
-
-        // Ensure the directory exists (create if it doesn't)
-        if !path_to_watch.exists() {
-            info!("Creating watch directory: {:?}", path_to_watch);
-            if let Err(e) = std::fs::create_dir_all(&path_to_watch) {
-                error!(
-                    "Failed to create watch directory {:?}: {}",
-                    path_to_watch, e
-                );
-                return; // Cannot proceed without the directory
-            }
+    // The node is started at launch; this waits for it (or retries a failed
+    // start) and then attaches the folder.
+    let node = handle.state::<Node>();
+    let app_state = node.start(&handle).await?;
+    attach_sync_folder(app_state, Some(path_to_watch)).await
+}
+
+/// Validates `folder` and points the watcher at it, or stops watching when
+/// `folder` is `None`. The node is `Degraded` while the watcher is down.
+async fn attach_sync_folder(app_state: &AppState, folder: Option<PathBuf>) -> FastSyncResult<()> {
+    if let Some(folder) = &folder {
+        if !folder.is_absolute() {
+            return Err(FastSyncError::PathRejected(format!(
+                "Sync folder {:?} must be an absolute path",
+                folder
+            )));
+        }
+        if folder.exists() && !folder.is_dir() {
+            return Err(FastSyncError::PathRejected(format!(
+                "Sync folder {:?} is not a directory",
+                folder
+            )));
+        }
+    }
+    match switch_sync_folder(app_state, folder).await {
+        Ok(()) => {
+            app_state.lifecycle.recover();
+            Ok(())
         }
+        Err(e) => {
+            let err = FastSyncError::classify("Failed to switch sync folder", e, FastSyncError::Io);
+            app_state
+                .lifecycle
+                .set(NodeStatus::Degraded(err.to_string()));
+            Err(err)
+        }
+    }
+}
 
-        info!("Attempting to watch: {:?}", path_to_watch);
+#[tauri::command]
+pub async fn set_sync_folder(
+    app: AppHandle,
+    node: State<'_, Node>,
+    path: String,
+) -> FastSyncResult<()> {
+    let path = PathBuf::from(path);
+    attach_sync_folder(node.app_state()?, Some(path.clone())).await?;
+
+    let store = app.store("store.json")?;
+    store.set("sync-folder-path", path.to_string_lossy().into_owned());
+    store.save()?;
+    store.close_resource();
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_sync_folder(app: AppHandle, node: State<'_, Node>) -> FastSyncResult<()> {
+    attach_sync_folder(node.app_state()?, None).await?;
+
+    let store = app.store("store.json")?;
+    store.delete("sync-folder-path");
+    store.save()?;
+    store.close_resource();
 
-        // Start the watcher (which runs in its own std::thread)
-        match fs_watcher::start_watching(path_to_watch.clone()) {
-            Ok(receiver) => {
-                fs_watcher::handle_watcher(path_to_watch, fs_handle, receiver);
-            }
-            Err(err) => {
-                error!(
-                    "❌❌❌ Failed to start filesystem watcher for path {:?}: {:?}",
-                    path_to_watch, err
-                );
-            }
-        }
-    }); // End of FS Watcher spawn
     Ok(())
 }
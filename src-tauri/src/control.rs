@@ -0,0 +1,573 @@
+// src-tauri/src/control.rs
+
+//! Optional local control API so scripts and editor plugins can query and
+//! drive a running node.
+//!
+//! It is an HTTP server bound to a loopback address. Every request needs the
+//! token stored in `<data_dir>/control-token`, either as
+//! `Authorization: Bearer <token>` or as a `?token=` query parameter (for
+//! `EventSource`, which cannot set headers).
+//!
+//! | Method | Path                    | Body                  |
+//! |--------|-------------------------|-----------------------|
+//! | GET    | `/v1/node`              |                       |
+//! | GET    | `/v1/status`            |                       |
+//! | GET    | `/v1/peers`             |                       |
+//! | GET    | `/v1/devices`           |                       |
+//! | POST   | `/v1/pause`             | optionally `?folder=/abs/path` or `?peer=<node id>` |
+//! | POST   | `/v1/resume`            | optionally `?folder=/abs/path` or `?peer=<node id>` |
+//! | GET    | `/v1/pending`           |                       |
+//! | POST   | `/v1/sync`              |                       |
+//! | POST   | `/v1/share`             | `{ "path": "..." }`   |
+//! | POST   | `/v1/join`              | `{ "ticket": "..." }` |
+//! | GET    | `/v1/snapshots`         |                       |
+//! | POST   | `/v1/snapshots`         | `{ "name": "..." }`   |
+//! | POST   | `/v1/snapshots/restore` | `{ "id": "...", "entries": [...] }`, `entries` optional |
+//! | GET    | `/v1/versions`          | `?path=docs/a.txt`    |
+//! | POST   | `/v1/versions/restore`  | `{ "id": "..." }`     |
+//! | GET    | `/v1/trash`             |                       |
+//! | POST   | `/v1/trash/restore`     | `{ "id": "..." }`     |
+//! | POST   | `/v1/trash/empty`       |                       |
+//! | GET    | `/v1/storage`           |                       |
+//! | POST   | `/v1/gc`                | optionally `?release_legacy=true` |
+//! | GET    | `/v1/deferred`          |                       |
+//! | POST   | `/v1/deferred/fetch`    | `{ "path": "docs/a.iso" }` |
+//! | GET    | `/v1/events`            | server-sent events, optionally `?events=fs-event,gossip://message` |
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures::{stream, Stream};
+use iroh::NodeId;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::broadcast::error::RecvError};
+
+use crate::{
+    iroh_fns::{create_iroh_ticket, network_info, start_gossip, NetworkInfo},
+    pause::{
+        self as pausing, pause_status, pending_changes, PauseStatus, PauseTarget, PendingChange,
+    },
+    presence::{list_devices, DeviceInfo},
+    quota::{deferred_downloads, fetch_deferred, DeferredDownload},
+    snapshot::{create_snapshot, list_snapshots, restore_snapshot, SnapshotInfo},
+    state::{AppState, GossipStats},
+    storage::{collect_garbage, share_tag, storage_stats, GcReport, StorageStats},
+    trash::{empty_trash, list_trash, restore_from_trash, TrashEntry},
+    versions::{list_versions, restore_version, VersionInfo},
+};
+
+const TOKEN_FILE: &str = "control-token";
+
+#[derive(Clone)]
+struct ControlState {
+    app_state: AppState,
+    token: Arc<String>,
+}
+
+/// Starts the control API on `listen` and registers it in [`AppState`] so
+/// shutdown stops it. Only loopback addresses are accepted.
+pub async fn serve(app_state: &AppState, listen: SocketAddr, data_dir: &Path) -> Result<()> {
+    if !listen.ip().is_loopback() {
+        bail!(
+            "Control API must listen on a loopback address, got {}",
+            listen
+        );
+    }
+
+    let token = load_or_create_token(data_dir)?;
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to bind control API to {}", listen))?;
+
+    let ctl = ControlState {
+        app_state: app_state.clone(),
+        token: Arc::new(token),
+    };
+    let router = Router::new()
+        .route("/v1/node", get(node_info))
+        .route("/v1/status", get(status))
+        .route("/v1/peers", get(peers))
+        .route("/v1/devices", get(devices))
+        .route("/v1/pause", post(pause))
+        .route("/v1/resume", post(resume))
+        .route("/v1/pending", get(pending))
+        .route("/v1/sync", post(sync))
+        .route("/v1/share", post(share))
+        .route("/v1/join", post(join))
+        .route("/v1/snapshots", get(snapshots).post(snapshot))
+        .route("/v1/snapshots/restore", post(restore))
+        .route("/v1/versions", get(versions))
+        .route("/v1/versions/restore", post(restore_file_version))
+        .route("/v1/trash", get(trash))
+        .route("/v1/trash/restore", post(restore_trashed))
+        .route("/v1/trash/empty", post(empty))
+        .route("/v1/storage", get(storage))
+        .route("/v1/gc", post(gc))
+        .route("/v1/deferred", get(deferred))
+        .route("/v1/deferred/fetch", post(fetch))
+        .route("/v1/events", get(events))
+        .layer(middleware::from_fn_with_state(ctl.clone(), require_token))
+        .with_state(ctl);
+
+    let server_task = tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            error!("Control API server failed: {}", e);
+        }
+    });
+    if let Some(old_task) = app_state.control_task.lock().await.replace(server_task) {
+        old_task.abort();
+    }
+
+    info!(
+        "Control API listening on http://{} (token in {:?})",
+        listen,
+        data_dir.join(TOKEN_FILE)
+    );
+    Ok(())
+}
+
+/// Reads the API token, generating one on first use. On Unix the file is
+/// only readable by the current user.
+fn load_or_create_token(data_dir: &Path) -> Result<String> {
+    let token_path = data_dir.join(TOKEN_FILE);
+    if token_path.exists() {
+        return Ok(std::fs::read_to_string(&token_path)?.trim().to_string());
+    }
+
+    let token = data_encoding::HEXLOWER.encode(&rand::random::<[u8; 32]>());
+    std::fs::create_dir_all(data_dir)?;
+    std::fs::write(&token_path, &token)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&token_path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(token)
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+async fn require_token(
+    State(ctl): State<ControlState>,
+    Query(query): Query<TokenQuery>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let presented = bearer.or(query.token.as_deref());
+
+    match presented {
+        Some(token) if constant_time_eq(token.as_bytes(), ctl.token.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => ApiError::new(StatusCode::UNAUTHORIZED, "Missing or invalid token").into_response(),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": self.message });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct NodeInfoResponse {
+    node_id: NodeId,
+    #[serde(flatten)]
+    network: NetworkInfo,
+}
+
+async fn node_info(State(ctl): State<ControlState>) -> Json<NodeInfoResponse> {
+    Json(NodeInfoResponse {
+        node_id: ctl.app_state.endpoint.node_id(),
+        network: network_info(&ctl.app_state),
+    })
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    node_id: NodeId,
+    sync_folder: Option<PathBuf>,
+    topic: Option<String>,
+    paused: bool,
+    /// Folders and peers paused on their own, and the queued changes.
+    pause: PauseStatus,
+    peers: usize,
+    gossip: GossipStats,
+}
+
+async fn status(State(ctl): State<ControlState>) -> Json<StatusResponse> {
+    let app_state = &ctl.app_state;
+    Json(StatusResponse {
+        node_id: app_state.endpoint.node_id(),
+        sync_folder: app_state.sync_folder.lock().await.clone(),
+        topic: app_state
+            .gossip_topic
+            .lock()
+            .await
+            .map(|topic| topic.to_string()),
+        paused: pausing::is_paused(app_state),
+        pause: pause_status(app_state),
+        peers: app_state.neighbors.lock().await.len(),
+        gossip: app_state
+            .gossip_stats
+            .lock()
+            .expect("gossip stats poisoned")
+            .clone(),
+    })
+}
+
+async fn peers(State(ctl): State<ControlState>) -> Json<Vec<NodeId>> {
+    Json(
+        ctl.app_state
+            .neighbors
+            .lock()
+            .await
+            .iter()
+            .copied()
+            .collect(),
+    )
+}
+
+async fn devices(State(ctl): State<ControlState>) -> Json<Vec<DeviceInfo>> {
+    Json(list_devices(&ctl.app_state).await)
+}
+
+/// Everything unless one of `folder` or `peer` is given.
+#[derive(Deserialize)]
+struct PauseQuery {
+    folder: Option<PathBuf>,
+    peer: Option<NodeId>,
+}
+
+impl PauseQuery {
+    fn target(self) -> Result<PauseTarget, ApiError> {
+        match (self.folder, self.peer) {
+            (None, None) => Ok(PauseTarget::All),
+            (Some(path), None) if path.is_absolute() => Ok(PauseTarget::Folder { path }),
+            (Some(_), None) => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "folder must be absolute",
+            )),
+            (None, Some(node_id)) => Ok(PauseTarget::Peer { node_id }),
+            (Some(_), Some(_)) => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "give either folder or peer",
+            )),
+        }
+    }
+}
+
+async fn pause(
+    State(ctl): State<ControlState>,
+    Query(query): Query<PauseQuery>,
+) -> Result<StatusCode, ApiError> {
+    pausing::pause(&ctl.app_state, query.target()?)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn resume(
+    State(ctl): State<ControlState>,
+    Query(query): Query<PauseQuery>,
+) -> Result<StatusCode, ApiError> {
+    pausing::resume(&ctl.app_state, query.target()?)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pending(State(ctl): State<ControlState>) -> Json<Vec<PendingChange>> {
+    Json(pending_changes(&ctl.app_state).await)
+}
+
+async fn sync(State(ctl): State<ControlState>) -> Result<StatusCode, ApiError> {
+    pausing::sync_now(&ctl.app_state)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct ShareRequest {
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct ShareResponse {
+    ticket: String,
+}
+
+async fn share(
+    State(ctl): State<ControlState>,
+    Json(request): Json<ShareRequest>,
+) -> Result<Json<ShareResponse>, ApiError> {
+    if !request.path.is_absolute() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "path must be absolute",
+        ));
+    }
+    let ticket = create_iroh_ticket(
+        ctl.app_state.blobs.clone(),
+        ctl.app_state.endpoint.clone(),
+        request.path.clone(),
+        share_tag(&request.path),
+    )
+    .await
+    .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+    Ok(Json(ShareResponse { ticket }))
+}
+
+#[derive(Deserialize)]
+struct JoinRequest {
+    ticket: String,
+}
+
+async fn join(
+    State(ctl): State<ControlState>,
+    Json(request): Json<JoinRequest>,
+) -> Result<StatusCode, ApiError> {
+    start_gossip(&ctl.app_state, &request.ticket)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn snapshots(State(ctl): State<ControlState>) -> Result<Json<Vec<SnapshotInfo>>, ApiError> {
+    list_snapshots(&ctl.app_state)
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+#[derive(Deserialize)]
+struct SnapshotRequest {
+    name: String,
+}
+
+async fn snapshot(
+    State(ctl): State<ControlState>,
+    Json(request): Json<SnapshotRequest>,
+) -> Result<Json<SnapshotInfo>, ApiError> {
+    create_snapshot(&ctl.app_state, &request.name)
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))
+}
+
+#[derive(Deserialize)]
+struct RestoreRequest {
+    id: String,
+    entries: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct RestoreResponse {
+    restored: Vec<String>,
+}
+
+async fn restore(
+    State(ctl): State<ControlState>,
+    Json(request): Json<RestoreRequest>,
+) -> Result<Json<RestoreResponse>, ApiError> {
+    let restored = restore_snapshot(&ctl.app_state, &request.id, request.entries.as_deref())
+        .await
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+    Ok(Json(RestoreResponse { restored }))
+}
+
+#[derive(Deserialize)]
+struct VersionsQuery {
+    path: String,
+}
+
+async fn versions(
+    State(ctl): State<ControlState>,
+    Query(query): Query<VersionsQuery>,
+) -> Result<Json<Vec<VersionInfo>>, ApiError> {
+    list_versions(&ctl.app_state, &query.path)
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))
+}
+
+#[derive(Deserialize)]
+struct RestoreByIdRequest {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct RestoredPathResponse {
+    path: String,
+}
+
+async fn restore_file_version(
+    State(ctl): State<ControlState>,
+    Json(request): Json<RestoreByIdRequest>,
+) -> Result<Json<RestoredPathResponse>, ApiError> {
+    let path = restore_version(&ctl.app_state, &request.id)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+    Ok(Json(RestoredPathResponse { path }))
+}
+
+async fn trash(State(ctl): State<ControlState>) -> Result<Json<Vec<TrashEntry>>, ApiError> {
+    list_trash(&ctl.app_state)
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+async fn restore_trashed(
+    State(ctl): State<ControlState>,
+    Json(request): Json<RestoreByIdRequest>,
+) -> Result<Json<RestoredPathResponse>, ApiError> {
+    let path = restore_from_trash(&ctl.app_state, &request.id)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+    Ok(Json(RestoredPathResponse { path }))
+}
+
+async fn empty(State(ctl): State<ControlState>) -> Result<StatusCode, ApiError> {
+    empty_trash(&ctl.app_state)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn storage(State(ctl): State<ControlState>) -> Result<Json<StorageStats>, ApiError> {
+    storage_stats(&ctl.app_state)
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+#[derive(Deserialize)]
+struct GcQuery {
+    #[serde(default)]
+    release_legacy: bool,
+}
+
+async fn gc(
+    State(ctl): State<ControlState>,
+    Query(query): Query<GcQuery>,
+) -> Result<Json<GcReport>, ApiError> {
+    collect_garbage(&ctl.app_state, query.release_legacy)
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+async fn deferred(State(ctl): State<ControlState>) -> Json<Vec<DeferredDownload>> {
+    Json(deferred_downloads(&ctl.app_state))
+}
+
+#[derive(Deserialize)]
+struct FetchRequest {
+    /// Relative to the sync folder, as listed by `/v1/deferred`.
+    path: String,
+}
+
+async fn fetch(
+    State(ctl): State<ControlState>,
+    Json(request): Json<FetchRequest>,
+) -> Result<StatusCode, ApiError> {
+    fetch_deferred(&ctl.app_state, &request.path)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    /// Comma-separated event names. All events when absent.
+    events: Option<String>,
+}
+
+async fn events(
+    State(ctl): State<ControlState>,
+    Query(query): Query<EventsQuery>,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let wanted: Option<Vec<String>> = query.events.map(|names| {
+        names
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect()
+    });
+    let receiver = ctl.app_state.event_feed.subscribe();
+
+    let stream = stream::unfold(receiver, move |mut receiver| {
+        let wanted = wanted.clone();
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if wanted
+                            .as_ref()
+                            .is_some_and(|names| !names.contains(&event.event))
+                        {
+                            continue;
+                        }
+                        let sse_event = SseEvent::default()
+                            .event(event.event)
+                            .data(event.payload.to_string());
+                        return Some((Ok(sse_event), receiver));
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(
+                            "Control API event stream lagged, {} events dropped",
+                            skipped
+                        );
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
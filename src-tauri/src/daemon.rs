@@ -0,0 +1,247 @@
+// src-tauri/src/daemon.rs
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    control,
+    events::{EventSink, LogSink},
+    fs_watcher::switch_sync_folder,
+    iroh_fns::{
+        create_iroh_gossip_ticket, load_or_create_topic, setup, shutdown_node, start_gossip,
+        GossipTicket,
+    },
+    pause,
+    presence::set_device_profile,
+    quota::apply_quota_settings,
+    settings::{
+        BandwidthSettings, DeviceProfile, MetadataSettings, NetworkSettings, QuotaSettings,
+        SnapshotSettings, SymlinkPolicy, TrashSettings, VersionSettings,
+    },
+    snapshot::apply_snapshot_settings,
+    state::NodeLifecycle,
+    trash::apply_trash_settings,
+};
+
+/// Configuration of the headless daemon, read from a TOML file:
+///
+/// ```toml
+/// sync_folder = "/srv/share"
+/// data_dir = "/var/lib/fastsyncd"
+/// # Optional. Without it the daemon opens its own sync group.
+/// ticket = "..."
+/// # Optional local control API, see `control`.
+/// control_listen = "127.0.0.1:7878"
+/// # Optional. When false, changes wait for `POST /v1/sync`.
+/// auto_sync = true
+/// # Optional, see `SymlinkPolicy`. Links are followed by default.
+/// symlinks = "preserve"
+///
+/// # Optional, see `NetworkSettings`.
+/// [network]
+/// mode = "lanOnly"
+///
+/// # Optional. How this node is shown to peers.
+/// [device]
+/// name = "office-nas"
+/// color = "#3b82f6"
+///
+/// # Optional, see `SnapshotSettings`.
+/// [snapshots]
+/// schedule = "daily"
+/// keep = 14
+///
+/// # Optional, see `VersionSettings`.
+/// [versions]
+/// keep = 5
+/// keep_days = 30
+///
+/// # Optional, see `TrashSettings`.
+/// [trash]
+/// retention_days = 14
+///
+/// # Optional, see `QuotaSettings`.
+/// [quotas]
+/// store_max_bytes = 50_000_000_000
+/// auto_download_max_bytes = 2_000_000_000
+///
+/// # Optional, see `BandwidthSettings`.
+/// [bandwidth.limits]
+/// upload_bytes_per_sec = 1_000_000
+/// # Unlimited at night.
+/// [[bandwidth.schedule]]
+/// start = "22:00"
+/// end = "06:00"
+///
+/// # Optional, see `MetadataSettings`.
+/// [metadata]
+/// xattrs = true
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Folder to keep in sync.
+    pub sync_folder: PathBuf,
+    /// Where the node key and blob store are kept.
+    pub data_dir: PathBuf,
+    /// Gossip ticket of the group to join.
+    pub ticket: Option<String>,
+    /// Loopback address for the local control API. Disabled when absent.
+    pub control_listen: Option<SocketAddr>,
+    /// Sync changes as they happen. When off, they wait for a manual sync.
+    #[serde(default = "default_auto_sync")]
+    pub auto_sync: bool,
+    /// What happens to links in the sync folder.
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    /// Relay and discovery settings. Defaults to public relays and discovery.
+    #[serde(default)]
+    pub network: NetworkSettings,
+    /// Name and colour announced to peers.
+    pub device: Option<DeviceProfile>,
+    /// Automatic snapshots of the sync folder. Off by default.
+    #[serde(default)]
+    pub snapshots: SnapshotSettings,
+    /// Earlier versions of each file to keep. Defaults to the last 10.
+    #[serde(default)]
+    pub versions: VersionSettings,
+    /// How long files deleted by peers are kept. Defaults to 30 days.
+    #[serde(default)]
+    pub trash: TrashSettings,
+    /// Limits on what downloads may take. None by default.
+    #[serde(default)]
+    pub quotas: QuotaSettings,
+    /// Transfer limits. Unlimited by default.
+    #[serde(default)]
+    pub bandwidth: BandwidthSettings,
+    /// File attributes to sync. Modification time and permissions by default.
+    #[serde(default)]
+    pub metadata: MetadataSettings,
+}
+
+fn default_auto_sync() -> bool {
+    true
+}
+
+impl DaemonConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+        let config: Self =
+            toml::from_str(&text).with_context(|| format!("Invalid config file {:?}", path))?;
+        if let Some(device) = &config.device {
+            device
+                .validate()
+                .with_context(|| format!("Invalid [device] in {:?}", path))?;
+        }
+        config
+            .snapshots
+            .validate()
+            .with_context(|| format!("Invalid [snapshots] in {:?}", path))?;
+        config
+            .versions
+            .validate()
+            .with_context(|| format!("Invalid [versions] in {:?}", path))?;
+        config
+            .trash
+            .validate()
+            .with_context(|| format!("Invalid [trash] in {:?}", path))?;
+        config
+            .quotas
+            .validate()
+            .with_context(|| format!("Invalid [quotas] in {:?}", path))?;
+        config
+            .bandwidth
+            .validate()
+            .with_context(|| format!("Invalid [bandwidth] in {:?}", path))?;
+        Ok(config)
+    }
+}
+
+/// Runs the same watcher, gossip and blob pipeline as the desktop app until
+/// the process is asked to stop, then shuts the node down gracefully.
+pub async fn run(config: DaemonConfig) -> Result<()> {
+    let events: Arc<dyn EventSink> = Arc::new(LogSink);
+    let app_state = setup(
+        config.data_dir.clone(),
+        events.clone(),
+        NodeLifecycle::new(events),
+        &config.network,
+    )
+    .await?;
+    info!("Node id: {}", app_state.endpoint.node_id());
+    *app_state
+        .version_settings
+        .lock()
+        .expect("version settings poisoned") = config.versions;
+    apply_trash_settings(&app_state, config.trash).await?;
+    apply_quota_settings(&app_state, config.quotas);
+    app_state.bandwidth.apply(config.bandwidth);
+    *app_state
+        .metadata_settings
+        .lock()
+        .expect("metadata settings poisoned") = config.metadata;
+    *app_state
+        .symlink_policy
+        .lock()
+        .expect("symlink policy poisoned") = config.symlinks;
+    pause::set_manual(&app_state, !config.auto_sync).await?;
+    if let Some(device) = config.device {
+        set_device_profile(&app_state, device).await?;
+    }
+
+    if let Some(listen) = config.control_listen {
+        control::serve(&app_state, listen, &config.data_dir).await?;
+    }
+
+    switch_sync_folder(&app_state, Some(config.sync_folder)).await?;
+    apply_snapshot_settings(&app_state, config.snapshots).await;
+
+    let topic = match &config.ticket {
+        Some(ticket) => {
+            let topic = GossipTicket::from_str(ticket)?.topic;
+            start_gossip(&app_state, ticket).await?;
+            topic
+        }
+        None => {
+            let topic = load_or_create_topic(&config.data_dir)?;
+            let own_ticket = create_iroh_gossip_ticket(app_state.endpoint.clone(), topic).await?;
+            start_gossip(&app_state, &own_ticket).await?;
+            topic
+        }
+    };
+    // A ticket naming this node, so others can join through it.
+    let invite = create_iroh_gossip_ticket(app_state.endpoint.clone(), topic).await?;
+    info!("Syncing. Invite other peers with: {}", invite);
+
+    wait_for_shutdown_signal().await?;
+    info!("Shutdown signal received.");
+    shutdown_node(&app_state).await
+}
+
+/// Resolves once the process is asked to stop (Ctrl-C, or SIGTERM on Unix).
+pub async fn wait_for_shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        // SIGTERM is what service managers send on stop.
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
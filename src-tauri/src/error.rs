@@ -0,0 +1,78 @@
+// src-tauri/src/error.rs
+
+use serde::Serialize;
+
+/// Error returned to the frontend by every command.
+///
+/// Serializes as `{ "kind": "invalidTicket", "message": "..." }` so the UI can
+/// branch on `kind` and still show `message` as-is.
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "camelCase")]
+pub enum FastSyncError {
+    /// The node or the sync folder has not been set up yet.
+    #[error("{0}")]
+    NotInitialized(String),
+    /// A blob or gossip ticket could not be parsed.
+    #[error("{0}")]
+    InvalidTicket(String),
+    /// Reading or writing local files failed.
+    #[error("{0}")]
+    Io(String),
+    /// Talking to peers (download, gossip join, ...) failed.
+    #[error("{0}")]
+    Network(String),
+    /// The OS refused access to a file or folder.
+    #[error("{0}")]
+    PermissionDenied(String),
+    /// A path was refused before touching it, e.g. relative or not a folder.
+    #[error("{0}")]
+    PathRejected(String),
+    /// A setting has a value that cannot be used.
+    #[error("{0}")]
+    InvalidSetting(String),
+    /// Anything that does not fit the kinds above.
+    #[error("{0}")]
+    Internal(String),
+}
+
+pub type FastSyncResult<T> = Result<T, FastSyncError>;
+
+impl FastSyncError {
+    /// Classifies `err` by the first I/O error in its chain, falling back to
+    /// `fallback` when there is none. `context` is prefixed to the message.
+    pub fn classify(
+        context: &str,
+        err: anyhow::Error,
+        fallback: fn(String) -> FastSyncError,
+    ) -> Self {
+        let message = format!("{}: {:#}", context, err);
+        let io_kind = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .map(std::io::Error::kind);
+        match io_kind {
+            Some(std::io::ErrorKind::PermissionDenied) => Self::PermissionDenied(message),
+            Some(_) => Self::Io(message),
+            None => fallback(message),
+        }
+    }
+}
+
+impl From<anyhow::Error> for FastSyncError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::classify("Operation failed", err, Self::Internal)
+    }
+}
+
+impl From<std::io::Error> for FastSyncError {
+    fn from(err: std::io::Error) -> Self {
+        Self::classify("I/O error", err.into(), Self::Io)
+    }
+}
+
+#[cfg(feature = "desktop")]
+impl From<tauri_plugin_store::Error> for FastSyncError {
+    fn from(err: tauri_plugin_store::Error) -> Self {
+        Self::Io(format!("Settings store error: {}", err))
+    }
+}
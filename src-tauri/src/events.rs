@@ -0,0 +1,67 @@
+// src-tauri/src/events.rs
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::info;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Destination for the events the sync engine raises (`fs-event`,
+/// `gossip://message`, ...).
+///
+/// The desktop app forwards them to the webview; the headless daemon logs them.
+pub trait EventSink: Send + Sync + 'static {
+    fn emit_json(&self, event: &str, payload: serde_json::Value) -> Result<()>;
+}
+
+impl dyn EventSink {
+    /// Serializes `payload` and hands it to the sink.
+    pub fn emit<S: Serialize>(&self, event: &str, payload: S) -> Result<()> {
+        self.emit_json(event, serde_json::to_value(payload)?)
+    }
+}
+
+#[cfg(feature = "desktop")]
+impl<R: tauri::Runtime> EventSink for tauri::AppHandle<R> {
+    fn emit_json(&self, event: &str, payload: serde_json::Value) -> Result<()> {
+        tauri::Emitter::emit(self, event, payload).map_err(Into::into)
+    }
+}
+
+/// An event as seen by local subscribers such as the control API stream.
+#[derive(Clone, Debug, Serialize)]
+pub struct EngineEvent {
+    pub event: String,
+    pub payload: serde_json::Value,
+}
+
+/// Forwards every event to `inner` and also publishes it on a broadcast
+/// channel, so local tools can follow what the UI sees.
+pub struct BroadcastSink {
+    pub inner: Arc<dyn EventSink>,
+    pub feed: broadcast::Sender<EngineEvent>,
+}
+
+impl EventSink for BroadcastSink {
+    fn emit_json(&self, event: &str, payload: serde_json::Value) -> Result<()> {
+        // Having no subscribers is the normal case, not an error.
+        self.feed
+            .send(EngineEvent {
+                event: event.to_string(),
+                payload: payload.clone(),
+            })
+            .ok();
+        self.inner.emit_json(event, payload)
+    }
+}
+
+/// Sink used when there is no UI to notify: events only end up in the log.
+pub struct LogSink;
+
+impl EventSink for LogSink {
+    fn emit_json(&self, event: &str, payload: serde_json::Value) -> Result<()> {
+        info!("[event] {}: {}", event, payload);
+        Ok(())
+    }
+}
@@ -1,122 +1,157 @@
 // src/fs_watcher.rs
 
-use anyhow::Result;
+use crate::{iroh_fns::handle_fs_payload, state::AppState};
+use anyhow::{bail, Context, Result};
 use log::{error, info, warn};
 use notify::{
     event::{ModifyKind, RenameMode},
     Config, Error, Event, RecommendedWatcher, RecursiveMode, Result as NotifyResult, Watcher,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     path::PathBuf,
-    sync::mpsc::{self, Receiver}, // Use standard library channels
-    thread,
-    time::Duration,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender}, // Use standard library channels
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
-use tauri::{AppHandle, Emitter};
-
-use crate::iroh_fns::handle_fs_payload;
 
 // Define a type alias for the events we'll send over the channel
 // We send the whole Result to propagate potential watcher errors
 pub type FileEventResult = NotifyResult<Event>;
 pub type FileEventReceiver = Receiver<FileEventResult>;
 
-#[derive(Clone, Serialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum FsEventType {
     Create,
     Modify,
     Remove,
+    /// Moved within the sync folder from [`FsEventPayload::from`] to `path`.
+    Rename,
     Error,
     Other,
 }
 
 // Define a simple serializable struct for the event payload
-#[derive(Clone, Serialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct FsEventPayload {
     pub event_type: FsEventType, // e.g., "Create", "Modify", "Remove", "Error", "Other"
     pub path: PathBuf,           // Paths affected, converted to strings
+    /// Where a renamed path was before, for [`FsEventType::Rename`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<PathBuf>,
+}
+
+/// How long the "from" half of a rename waits for its "to" half before the
+/// path is treated as moved out of the folder.
+const RENAME_PAIRING_WINDOW: Duration = Duration::from_millis(250);
+
+/// The "from" half of a rename whose "to" half has not been seen yet.
+struct PendingRename {
+    path: PathBuf,
+    tracker: Option<usize>,
+    since: Instant,
+}
+
+impl PendingRename {
+    fn moved_out(self) -> FsEventPayload {
+        FsEventPayload {
+            event_type: FsEventType::Remove,
+            path: self.path,
+            from: None,
+        }
+    }
+}
+
+/// Handle to a running watcher thread.
+///
+/// Dropping the handle without calling [`WatcherHandle::stop`] also stops the
+/// watcher, but does not wait for its thread to exit.
+pub struct WatcherHandle {
+    pub path: PathBuf,
+    shutdown_tx: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatcherHandle {
+    /// Signals the watcher thread to stop and waits for it to exit.
+    ///
+    /// Once the watcher is gone its event sender is dropped, which in turn ends
+    /// the processing loop started by [`handle_watcher`].
+    pub fn stop(mut self) {
+        info!("Stopping filesystem watcher for {:?}", self.path);
+        // The thread may already be gone if the watcher errored out.
+        self.shutdown_tx.send(()).ok();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Watcher thread for {:?} panicked", self.path);
+            }
+        }
+    }
 }
 
 /// Starts watching a directory recursively in a separate thread.
 ///
-/// Returns a channel receiver to get filesystem events or errors.
-pub fn start_watching(path_to_watch: PathBuf) -> Result<FileEventReceiver> {
+/// Returns a channel receiver to get filesystem events or errors, and a
+/// [`WatcherHandle`] used to stop the watcher again.
+pub fn start_watching(path_to_watch: PathBuf) -> Result<(FileEventReceiver, WatcherHandle)> {
     // Create a channel for communication
     let (tx, rx) = mpsc::channel();
+    // Channel used only to tell the watcher thread to shut down
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>();
+
+    // Define the event handler closure.
+    // It sends received events/errors through the channel.
+    let handler = move |res: NotifyResult<Event>| {
+        if tx.send(res).is_err() {
+            // If sending fails, the receiver has been dropped.
+            warn!("[FS Watcher] Receiver dropped. Watcher thread may stop.");
+        }
+    };
+
+    // Create the watcher up front so that an invalid path is reported to the
+    // caller instead of only being logged from the watcher thread.
+    // RecommendedWatcher automatically selects the best backend for the OS.
+    let mut watcher = RecommendedWatcher::new(handler, Config::default())?;
+    watcher.watch(&path_to_watch, RecursiveMode::Recursive)?;
+
+    info!(
+        "[FS Watcher] Successfully watching {:?} recursively.",
+        path_to_watch
+    );
 
     // --- Watcher Thread ---
-    // Spawn a new thread to handle the filesystem watching.
-    // Move ownership of the sender `tx` and `path_to_watch` into the thread.
-    thread::spawn(move || {
-        println!(
+    // The thread owns the watcher and keeps it alive until a shutdown is
+    // requested (or the handle is dropped, which closes the channel).
+    let thread_path = path_to_watch.clone();
+    let thread = thread::spawn(move || {
+        info!(
             "[FS Watcher] Watcher thread started for path: {:?}",
-            path_to_watch
+            thread_path
         );
 
-        // Define the event handler closure.
-        // It clones the sender and sends received events/errors through the channel.
-        let handler = {
-            let tx = tx.clone(); // Clone sender for the closure
-            move |res: NotifyResult<Event>| {
-                if tx.send(res).is_err() {
-                    // If sending fails, the receiver has been dropped.
-                    // The watcher thread can probably exit gracefully.
-                    eprintln!("[FS Watcher] Receiver dropped. Watcher thread may stop.");
-                    // We could add logic here to signal the watcher loop to stop,
-                    // but often the watcher itself will error out or stop if the
-                    // handler fails repeatedly or under certain conditions.
-                }
-            }
-        };
-
-        // --- The core watcher logic ---
-        // This inner function helps manage the watcher's lifetime and error handling.
-        let run_watcher = || -> Result<()> {
-            // Create a new RecommendedWatcher. RecommendedWatcher automatically
-            // selects the best backend available for the OS.
-            let mut watcher = RecommendedWatcher::new(handler, Config::default())?;
-
-            // Add the path to the watcher. Watch recursively.
-            watcher.watch(&path_to_watch, RecursiveMode::Recursive)?;
-
-            println!(
-                "[FS Watcher] Successfully watching {:?} recursively.",
-                path_to_watch
-            );
-
-            // Keep the watcher alive. The watcher runs in the background.
-            // This thread just needs to stay alive to keep the `watcher` instance
-            // in scope. A simple loop suffices. Add a sleep to prevent busy-waiting
-            // if the underlying watcher mechanism doesn't block.
-            // You might add a shutdown signal check here in a real app.
-            loop {
-                thread::sleep(Duration::from_secs(5));
-                // In a real app, you might check an AtomicBool or another channel
-                // here to see if shutdown has been requested.
-            }
-        }; // End of run_watcher closure
-
-        // Execute the watcher logic. If it errors out, log it.
-        if let Err(e) = run_watcher() {
-            eprintln!("[FS Watcher] Watcher thread encountered an error: {:?}", e);
-            // Optionally send the final error over the channel if needed
-            // tx.send(Err(notify::Error::generic(format!("Watcher failed: {}", e)))).ok();
-        }
+        // Blocks until `stop` sends a signal or the sender is dropped.
+        shutdown_rx.recv().ok();
+        drop(watcher);
 
-        println!(
+        info!(
             "[FS Watcher] Watcher thread exiting for path: {:?}",
-            path_to_watch
+            thread_path
         );
     }); // End of thread::spawn
 
+    let handle = WatcherHandle {
+        path: path_to_watch,
+        shutdown_tx,
+        thread: Some(thread),
+    };
+
     // Return the receiver end of the channel to the caller
-    Ok(rx)
+    Ok((rx, handle))
 }
 
 pub fn handle_watcher(
     path_to_watch: PathBuf,
-    fs_handle: AppHandle,
+    app_state: AppState,
     receiver: Receiver<Result<Event, Error>>,
 ) {
     info!(
@@ -126,11 +161,26 @@ pub fn handle_watcher(
 
     // This task will now process events from the receiver channel.
     // We use spawn_blocking because receiver.recv() is blocking.
-    let blocking_task_handle = fs_handle.clone(); // Clone handle for spawn_blocking
     tokio::task::spawn_blocking(move || {
         info!("FS Event processing loop started.");
+        let dispatch = |payload: FsEventPayload| {
+            // handle iroh jobs to be performed based on the
+            handle_fs_payload(payload.clone(), &app_state);
+            // Emit event to frontend
+            if let Err(e) = app_state.events.emit("fs-event", payload) {
+                error!("Failed to emit event 'fs-event': {}", e);
+            }
+        };
+        let mut pending_rename: Option<PendingRename> = None;
         loop {
-            match receiver.recv() {
+            let received = receiver.recv_timeout(RENAME_PAIRING_WINDOW);
+            if pending_rename
+                .as_ref()
+                .is_some_and(|pending| pending.since.elapsed() >= RENAME_PAIRING_WINDOW)
+            {
+                dispatch(pending_rename.take().unwrap().moved_out());
+            }
+            match received {
                 Ok(event_result) => {
                     // Process the received event or error
                     let payload = match event_result {
@@ -142,7 +192,52 @@ pub fn handle_watcher(
 
                             // Get the first path, if any. Handle empty paths gracefully.
                             // Some events (like AccessMode::Close) might not have paths.
-                            let path = event.paths.get(0).cloned().unwrap_or_else(PathBuf::new);
+                            let path = event.paths.first().cloned().unwrap_or_else(PathBuf::new);
+
+                            // Pair the halves of a rename within the folder into one
+                            // `Rename`. Backends that report renames as a lone "from"
+                            // and "to" fall through to `Remove` and `Create` below.
+                            match event.kind {
+                                notify::EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                                    let moved_out = pending_rename.replace(PendingRename {
+                                        path,
+                                        tracker: event.attrs.tracker(),
+                                        since: Instant::now(),
+                                    });
+                                    if let Some(moved_out) = moved_out {
+                                        dispatch(moved_out.moved_out());
+                                    }
+                                    continue;
+                                }
+                                notify::EventKind::Modify(ModifyKind::Name(RenameMode::To))
+                                    if event.attrs.tracker().is_some()
+                                        && pending_rename.as_ref().is_some_and(|pending| {
+                                            pending.tracker == event.attrs.tracker()
+                                        }) =>
+                                {
+                                    // The matching `Both` follows.
+                                    continue;
+                                }
+                                notify::EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+                                    if event.paths.len() == 2 =>
+                                {
+                                    if pending_rename
+                                        .as_ref()
+                                        .is_some_and(|pending| pending.path == event.paths[0])
+                                    {
+                                        pending_rename = None;
+                                    }
+                                    let payload = FsEventPayload {
+                                        event_type: FsEventType::Rename,
+                                        path: event.paths[1].clone(),
+                                        from: Some(event.paths[0].clone()),
+                                    };
+                                    info!("Payload Emitted {:?}", payload);
+                                    dispatch(payload);
+                                    continue;
+                                }
+                                _ => {}
+                            }
 
                             // Determine FsEventType based on notify::EventKind
                             let event_type = match event.kind {
@@ -189,7 +284,11 @@ pub fn handle_watcher(
                             };
 
                             // Construct the payload to send to the frontend
-                            let payload = FsEventPayload { event_type, path };
+                            let payload = FsEventPayload {
+                                event_type,
+                                path,
+                                from: None,
+                            };
                             info!("Payload Emitted {:?}", payload);
 
                             payload
@@ -200,27 +299,28 @@ pub fn handle_watcher(
                             FsEventPayload {
                                 event_type: FsEventType::Error,
                                 path: PathBuf::new(), // No specific path for a watcher error
+                                from: None,
                             }
                         }
                     };
-                    // handle iroh jobs to be performed based on the
-                    handle_fs_payload(payload.clone(), blocking_task_handle.clone());
-                    // Emit event to frontend
-                    if let Err(e) = blocking_task_handle.emit("fs-event", payload) {
-                        error!("Failed to emit Tauri event 'fs-event': {}", e);
-                    }
+                    dispatch(payload);
                 }
-                Err(recv_error) => {
-                    error!(
-                        "FS Watcher channel error: {}. Watcher thread likely stopped.",
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(recv_error @ RecvTimeoutError::Disconnected) => {
+                    if let Some(pending) = pending_rename.take() {
+                        dispatch(pending.moved_out());
+                    }
+                    info!(
+                        "FS Watcher channel closed: {}. Watcher thread stopped.",
                         recv_error
                     );
                     // Emit a final error event?
                     let payload = FsEventPayload {
                         event_type: FsEventType::Other, // Or perhaps a specific Error type?
                         path: PathBuf::new(),
+                        from: None,
                     };
-                    blocking_task_handle.emit("fs-event", payload).ok(); // Best effort emit
+                    app_state.events.emit("fs-event", payload).ok(); // Best effort emit
                     break; // Exit the loop
                 }
             } // <-- Added missing semicolon
@@ -228,3 +328,45 @@ pub fn handle_watcher(
         info!("FS Event processing loop finished.");
     }); // <-- Added missing semicolon
 }
+
+/// Stops the watcher for the current sync folder (if any) and starts watching
+/// `folder` instead. Passing `None` leaves the node without a sync folder.
+///
+/// Incoming gossip downloads and outgoing announcements read the folder from
+/// [`AppState`] on every event, so they follow the switch without a restart.
+pub async fn switch_sync_folder(app_state: &AppState, folder: Option<PathBuf>) -> Result<()> {
+    // Held for the whole switch so concurrent calls cannot interleave.
+    let mut watcher_guard = app_state.sync_task_handle.lock().await;
+
+    if let Some(folder) = &folder {
+        // Ensure the directory exists (create if it doesn't)
+        if !folder.exists() {
+            info!("Creating watch directory: {:?}", folder);
+            std::fs::create_dir_all(folder)
+                .with_context(|| format!("Failed to create watch directory {:?}", folder))?;
+        }
+        if !folder.is_dir() {
+            bail!("Sync folder {:?} is not a directory", folder);
+        }
+    }
+
+    if let Some(old_watcher) = watcher_guard.take() {
+        // `stop` joins the watcher thread, so keep it off the async runtime.
+        tokio::task::spawn_blocking(move || old_watcher.stop()).await?;
+    }
+    *app_state.sync_folder.lock().await = None;
+
+    let Some(folder) = folder else {
+        info!("Sync folder removed.");
+        return Ok(());
+    };
+
+    info!("Attempting to watch: {:?}", folder);
+    let (receiver, watcher) = start_watching(folder.clone())
+        .with_context(|| format!("Failed to start filesystem watcher for path {:?}", folder))?;
+    handle_watcher(folder.clone(), app_state.clone(), receiver);
+
+    *watcher_guard = Some(watcher);
+    *app_state.sync_folder.lock().await = Some(folder);
+    Ok(())
+}
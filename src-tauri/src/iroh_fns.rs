@@ -1,17 +1,35 @@
 use crate::{
-    commands::GossipEventPayload, // Import the payload struct
+    bandwidth::{Bandwidth, ThrottledBlobs, UploadSlots},
+    events::{BroadcastSink, EventSink},
     fs_watcher::{FsEventPayload, FsEventType},
-    state::AppState,
+    metadata::{self, FileMetadata},
+    pause::{self, PauseState},
+    presence::{
+        announce_presence, default_profile, emit_device_changed, handle_presence, PRESENCE_INTERVAL,
+    },
+    protocol::{from_wire_path, to_wire_path, Envelope, Message},
+    quota,
+    settings::{
+        BandwidthSettings, MetadataSettings, NetworkSettings, SymlinkPolicy, TrashSettings,
+        VersionSettings,
+    },
+    state::{AppState, DiscoveryService, GossipStats, NodeLifecycle, NodeStatus},
+    storage, symlinks, trash, versions,
+};
+use anyhow::{bail, Context, Error, Result};
+use futures_util::{FutureExt, StreamExt}; // Added import for try_next
+use iroh::{
+    endpoint::{ConnectionType, DirectAddrType},
+    protocol::Router,
+    Endpoint, NodeAddr, NodeId, SecretKey,
 };
-use anyhow::{Error, Result};
-use futures_util::StreamExt; // Added import for try_next
-use iroh::{protocol::Router, Endpoint, NodeAddr, RelayMode, SecretKey};
 use iroh_blobs::{
     net_protocol::Blobs,
     rpc::client::blobs::WrapOption,
-    store::{fs::Store, ExportFormat, ExportMode},
+    store::{fs::Store, Store as _},
     ticket::BlobTicket,
-    util::SetTagOption,
+    util::{SetTagOption, Tag},
+    BlobFormat, Hash,
 };
 use iroh_gossip::{
     net::{Event as GossipNetEvent, Gossip, GossipEvent, GossipReceiver, GossipSender}, // Adjusted imports
@@ -19,12 +37,20 @@ use iroh_gossip::{
 };
 use log::{error, info, warn}; // Added warn
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
-use std::path::PathBuf; // Added import
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf}; // Added import
 use std::str::FromStr;
-use std::sync::Arc;
-use tauri::{AppHandle, Emitter, Manager, State};
-use tokio::sync::Mutex;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Mutex};
+use tokio_util::task::TaskTracker;
+use walkdir::WalkDir;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GossipTicket {
@@ -60,12 +86,22 @@ impl FromStr for GossipTicket {
     }
 }
 
-pub async fn setup<R: tauri::Runtime>(
-    handle: tauri::AppHandle<R>,
-    sync_path: PathBuf,
-) -> Result<()> {
-    let data_root = handle.path().app_data_dir()?;
-
+/// Builds the Iroh endpoint and protocol handlers, keeping the node key and
+/// blob store under `data_root`.
+///
+/// The sync folder is not part of this; it is attached afterwards (and can be
+/// swapped at runtime) through `fs_watcher::switch_sync_folder`.
+///
+/// Relays and discovery follow `network`. `lifecycle` moves to `Starting`
+/// and, once everything is up, to `Ready`. On error it is left to the caller
+/// to record the failure.
+pub async fn setup(
+    data_root: PathBuf,
+    events: Arc<dyn EventSink>,
+    lifecycle: NodeLifecycle,
+    network: &NetworkSettings,
+) -> Result<AppState> {
+    lifecycle.set(NodeStatus::Starting);
     let blobs_root = data_root.join("blob_data");
 
     let secret_key_path = data_root.join("secret_key");
@@ -97,43 +133,281 @@ pub async fn setup<R: tauri::Runtime>(
         }
     };
 
-    let endpoint = Endpoint::builder()
+    let discovery = network.discovery_services();
+    let mut endpoint_builder = Endpoint::builder()
         .secret_key(secret_key)
-        .discovery_n0()
-        .discovery_local_network()
-        .relay_mode(RelayMode::Default)
-        .bind()
-        .await?;
-    println!("> our node id: {}", endpoint.node_id());
+        .relay_mode(network.relay_mode()?);
+    for service in &discovery {
+        endpoint_builder = match service {
+            DiscoveryService::N0Dns => endpoint_builder.discovery_n0(),
+            DiscoveryService::LocalNetwork => endpoint_builder.discovery_local_network(),
+            DiscoveryService::Dht => endpoint_builder.discovery_dht(),
+        };
+    }
+    let endpoint = endpoint_builder.bind().await?;
+    info!(
+        "Network mode {:?}, relay {:?}, discovery {:?}",
+        network.mode, network.relay_url, discovery
+    );
+    info!("> our node id: {}", endpoint.node_id());
 
     let builder = Router::builder(endpoint.clone());
 
-    let blobs = Blobs::persistent(blobs_root)
+    // Limits are applied once the saved settings are read.
+    let bandwidth = Arc::new(Bandwidth::new(BandwidthSettings::default()));
+    let upload_slots = UploadSlots::new(bandwidth.clone());
+    let blobs = Blobs::persistent(&blobs_root)
         .await?
+        .events(upload_slots.clone().into())
         .build(builder.endpoint());
 
     let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
 
     let router = builder
-        .accept(iroh_blobs::ALPN, blobs.clone())
+        .accept(
+            iroh_blobs::ALPN,
+            ThrottledBlobs::new(blobs.clone(), bandwidth.clone(), upload_slots),
+        )
         .accept(iroh_gossip::ALPN, gossip.clone())
         .spawn();
 
+    // Everything the UI sees is also published for local subscribers.
+    let (event_feed, _) = broadcast::channel(EVENT_FEED_CAPACITY);
+    let events: Arc<dyn EventSink> = Arc::new(BroadcastSink {
+        inner: events,
+        feed: event_feed.clone(),
+    });
+    // So the control API's event stream sees status changes too.
+    lifecycle.emit_to(events.clone());
+
+    let device = default_profile(endpoint.node_id());
+    let pause = PauseState::load(&data_root).unwrap_or_else(|e| {
+        error!("{:#}. Nothing is paused.", e);
+        PauseState::default()
+    });
     let app_state = AppState {
-        endpoint: Some(endpoint),
-        blobs: Some(blobs),
-        gossip: Some(gossip),
-        router: Some(router),
+        endpoint,
+        blobs,
+        blobs_root,
+        last_gc_ms: Arc::new(AtomicU64::new(0)),
+        gossip,
+        discovery,
+        router,
+        gossip_receiver_task: Arc::new(Mutex::new(None)),
+        gossip_stats: Arc::new(std::sync::Mutex::new(GossipStats::default())),
+        tasks: TaskTracker::new(),
+        shutting_down: Arc::new(AtomicBool::new(false)),
+        control_task: Arc::new(Mutex::new(None)),
+        snapshot_task: Arc::new(Mutex::new(None)),
+        neighbors: Arc::new(Mutex::new(BTreeSet::new())),
+        device: Arc::new(Mutex::new(device)),
+        devices: Arc::new(Mutex::new(BTreeMap::new())),
+        pause: Arc::new(std::sync::Mutex::new(pause)),
+        data_root,
         gossip_topic: Arc::new(Mutex::new(None)),
         gossip_sender: Arc::new(Mutex::new(None)), // Ensure Mutex is from tokio::sync
-        sync_folder: sync_path,
-        sync_task_handle: None,
+        sync_folder: Arc::new(Mutex::new(None)),
+        sync_task_handle: Arc::new(Mutex::new(None)),
+        version_settings: Arc::new(std::sync::Mutex::new(VersionSettings::default())),
+        trash_settings: Arc::new(std::sync::Mutex::new(TrashSettings::default())),
+        bandwidth,
+        quotas: Arc::default(),
+        history: Arc::default(),
+        metadata_settings: Arc::new(std::sync::Mutex::new(MetadataSettings::default())),
+        symlink_policy: Arc::new(std::sync::Mutex::new(SymlinkPolicy::default())),
+        deferred: Arc::new(std::sync::Mutex::new(BTreeMap::new())),
+        rehash_pending: Arc::new(std::sync::Mutex::new(BTreeSet::new())),
+        lifecycle,
+        events,
+        event_feed,
     };
 
-    handle.manage(app_state);
+    storage::start_gc(&app_state)?;
+
+    app_state.lifecycle.set(NodeStatus::Ready);
+    Ok(app_state)
+}
+
+/// How this node can be reached and how it currently reaches its peers.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkInfo {
+    pub direct_addresses: Vec<DirectAddress>,
+    /// `None` until a relay server has been picked.
+    pub home_relay: Option<String>,
+    pub discovery: Vec<DiscoveryService>,
+    pub peers: Vec<PeerConnection>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectAddress {
+    pub addr: SocketAddr,
+    /// How the address was found: `local`, `stun`, `portmap`, ...
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerConnection {
+    pub node_id: NodeId,
+    #[serde(flatten)]
+    pub path: PeerPath,
+    /// Round-trip time of the current path, when known.
+    pub latency_ms: Option<f64>,
+}
+
+/// Network path used to talk to a peer.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "connection", rename_all = "camelCase")]
+pub enum PeerPath {
+    Direct {
+        addr: SocketAddr,
+    },
+    Relay {
+        relay_url: String,
+    },
+    /// A direct address is known but not yet confirmed, so the relay is
+    /// used as well.
+    Mixed {
+        addr: SocketAddr,
+        relay_url: String,
+    },
+}
+
+/// Collects [`NetworkInfo`] from the endpoint. Peers without a verified path
+/// are left out.
+pub fn network_info(app_state: &AppState) -> NetworkInfo {
+    let endpoint = &app_state.endpoint;
+    let direct_addresses = endpoint
+        .direct_addresses()
+        .get()
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|direct| DirectAddress {
+            addr: direct.addr,
+            source: match direct.typ {
+                DirectAddrType::Unknown => "unknown".to_string(),
+                typ => typ.to_string(),
+            },
+        })
+        .collect();
+    let home_relay = endpoint
+        .home_relay()
+        .get()
+        .ok()
+        .flatten()
+        .map(|url| url.to_string());
+
+    let peers = endpoint
+        .remote_info_iter()
+        .filter_map(|remote| {
+            let path = match remote.conn_type {
+                ConnectionType::Direct(addr) => PeerPath::Direct { addr },
+                ConnectionType::Relay(url) => PeerPath::Relay {
+                    relay_url: url.to_string(),
+                },
+                ConnectionType::Mixed(addr, url) => PeerPath::Mixed {
+                    addr,
+                    relay_url: url.to_string(),
+                },
+                ConnectionType::None => return None,
+            };
+            Some(PeerConnection {
+                node_id: remote.node_id,
+                path,
+                latency_ms: remote.latency.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            })
+        })
+        .collect();
+
+    NetworkInfo {
+        direct_addresses,
+        home_relay,
+        discovery: app_state.discovery.clone(),
+        peers,
+    }
+}
+
+/// Events buffered per local subscriber before the slowest one starts missing some.
+const EVENT_FEED_CAPACITY: usize = 256;
+
+/// How long shutdown waits for in-flight broadcasts and downloads to finish.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shuts the node down in an order that does not lose data:
+/// stop the watcher and gossip receiver so no new work is queued, drain the
+/// broadcasts and downloads already running, flush the blob store and finally
+/// shut down the router (which closes the endpoint and the protocol handlers).
+///
+/// Calling this more than once is a no-op.
+pub async fn shutdown_node(app_state: &AppState) -> Result<()> {
+    if app_state.shutting_down.swap(true, Ordering::SeqCst) {
+        info!("Shutdown already in progress.");
+        return Ok(());
+    }
+    info!("Shutting down...");
+    app_state.lifecycle.set(NodeStatus::ShuttingDown);
+
+    if let Some(control_task) = app_state.control_task.lock().await.take() {
+        control_task.abort();
+    }
+    if let Some(snapshot_task) = app_state.snapshot_task.lock().await.take() {
+        snapshot_task.abort();
+    }
+
+    if let Some(watcher) = app_state.sync_task_handle.lock().await.take() {
+        // `stop` joins the watcher thread, so keep it off the async runtime.
+        // Keep going on failure: the router below must always be shut down.
+        if let Err(e) = tokio::task::spawn_blocking(move || watcher.stop()).await {
+            error!("Failed to stop filesystem watcher: {}", e);
+        }
+    }
+    if let Some(receiver_task) = app_state.gossip_receiver_task.lock().await.take() {
+        receiver_task.abort();
+    }
+
+    app_state.tasks.close();
+    if tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, app_state.tasks.wait())
+        .await
+        .is_err()
+    {
+        warn!(
+            "{} sync tasks still running after {:?}. Shutting down anyway.",
+            app_state.tasks.len(),
+            SHUTDOWN_DRAIN_TIMEOUT
+        );
+    }
+    // Nothing can broadcast any more; leave the topic.
+    app_state.gossip_sender.lock().await.take();
+
+    if let Err(e) = app_state.blobs.store().sync().await {
+        error!("Failed to flush blob store: {}", e);
+    }
+
+    // Also shuts down the blobs store through the protocol handler.
+    app_state.router.shutdown().await?;
+
+    info!("Shutdown complete.");
     Ok(())
 }
 
+/// Topic of the group this node opened, kept in `data_dir` so restarts keep
+/// the same group.
+pub fn load_or_create_topic(data_dir: &Path) -> Result<TopicId> {
+    let topic_path = data_dir.join("topic-id");
+    if topic_path.exists() {
+        let text = std::fs::read_to_string(&topic_path)?;
+        return TopicId::from_str(text.trim())
+            .with_context(|| format!("Invalid topic id in {:?}", topic_path));
+    }
+
+    let topic = TopicId::from_bytes(rand::random());
+    std::fs::create_dir_all(data_dir)?;
+    std::fs::write(&topic_path, topic.to_string())?;
+    info!("Created new sync group {}", topic);
+    Ok(topic)
+}
+
 pub async fn create_iroh_gossip_ticket(
     endpoint: Endpoint,
     topic_id: TopicId,
@@ -148,14 +422,21 @@ pub async fn create_iroh_gossip_ticket(
     Ok(str_gossip_ticket)
 }
 
+/// Imports `path` under `tag` and returns a ticket for it. A folder is
+/// imported as a collection of its files, see [`crate::collection`]. Whatever
+/// `tag` held before is released, see [`crate::storage`].
 pub async fn create_iroh_ticket(
     blobs: Blobs<Store>,
     endpoint: Endpoint,
     path: PathBuf,
+    tag: Tag,
 ) -> Result<String, Error> {
+    if !path.is_file() && !path.is_dir() {
+        bail!("{:?} is neither a file nor a folder", path);
+    }
     let blobs_client = blobs.client();
     let add_progress = blobs_client
-        .add_from_path(path, true, SetTagOption::Auto, WrapOption::NoWrap)
+        .add_from_path(path, true, SetTagOption::Named(tag), WrapOption::NoWrap)
         .await?;
     let blob = add_progress.finish().await?;
     let node_id = endpoint.node_id();
@@ -222,169 +503,431 @@ pub async fn join_iroh_gossip(
     }
 }
 
+/// Joins the topic in `str_gossip_ticket`, keeps the sender in [`AppState`] and
+/// spawns `subscribe_loop` for the receiver, replacing any previous one.
+pub async fn start_gossip(app_state: &AppState, str_gossip_ticket: &str) -> Result<()> {
+    let GossipTicket { topic, nodes } = GossipTicket::from_str(str_gossip_ticket)?;
+    let me = app_state.endpoint.node_id();
+    let bootstrap: Vec<NodeId> = nodes
+        .iter()
+        .map(|node_addr| node_addr.node_id)
+        .filter(|node_id| *node_id != me)
+        .collect();
+
+    // Scope the lock for gossip_topic to release it before the await
+    {
+        let mut gossip_topic_guard = app_state.gossip_topic.lock().await;
+        *gossip_topic_guard = Some(topic);
+        info!("gossip_topic in AppState set and lock released.");
+    } // gossip_topic_guard is dropped here, and the lock is released.
+
+    info!("Calling join_iroh_gossip (iroh_fns.rs)...");
+    let (sender, receiver) = join_iroh_gossip(
+        app_state.endpoint.clone(),
+        app_state.gossip.clone(),
+        str_gossip_ticket.to_string(),
+    )
+    .await?;
+
+    // Correctly store the sender in AppState's Mutex<Option<GossipSender>>
+    {
+        let mut gossip_sender_guard = app_state.gossip_sender.lock().await;
+        *gossip_sender_guard = Some(sender);
+        info!("gossip_sender in AppState set and lock released.");
+    }
+
+    // Spawn a task to handle incoming gossip messages
+    let receiver_task = tokio::spawn(supervise_gossip(
+        app_state.clone(),
+        topic,
+        bootstrap,
+        receiver,
+    ));
+    // Joining again replaces the previous receiver.
+    if let Some(old_task) = app_state
+        .gossip_receiver_task
+        .lock()
+        .await
+        .replace(receiver_task)
+    {
+        old_task.abort();
+    }
+    info!("subscribe_loop task spawned.");
+
+    // Let the frontend know that gossip is ready
+    app_state.events.emit("gossip-ready", ())?;
+    info!("Emitted gossip-ready event.");
+    Ok(())
+}
+
+/// Shortest wait before subscribing again after the receiver stopped.
+const RESUBSCRIBE_BACKOFF_MIN: Duration = Duration::from_secs(1);
+/// Longest wait between attempts to subscribe again.
+const RESUBSCRIBE_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Runs `subscribe_loop` until shutdown. Whenever it ends, because the stream
+/// closed, failed or the loop panicked, the node is marked degraded and the
+/// topic is subscribed again with exponential backoff.
+async fn supervise_gossip(
+    app_state: AppState,
+    topic: TopicId,
+    bootstrap: Vec<NodeId>,
+    mut receiver: GossipReceiver,
+) {
+    let mut backoff = RESUBSCRIBE_BACKOFF_MIN;
+    loop {
+        info!("Gossip receiver task (subscribe_loop) started.");
+        let started = Instant::now();
+        let outcome = AssertUnwindSafe(subscribe_loop(app_state.clone(), receiver))
+            .catch_unwind()
+            .await;
+        if app_state.shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
+        let reason = match outcome {
+            Ok(Ok(())) => "gossip stream closed".to_string(),
+            Ok(Err(e)) => format!("gossip stream failed: {}", e),
+            Err(_) => "gossip receiver panicked".to_string(),
+        };
+        error!("Stopped receiving updates from peers: {}", reason);
+        app_state.lifecycle.set(NodeStatus::Degraded(format!(
+            "Reconnecting to peers: {}",
+            reason
+        )));
+
+        // The neighbors went away with the subscription, without NeighborDown.
+        let lost = std::mem::take(&mut *app_state.neighbors.lock().await);
+        for node_id in lost {
+            emit_device_changed(&app_state, node_id).await;
+        }
+
+        if started.elapsed() > RESUBSCRIBE_BACKOFF_MAX {
+            backoff = RESUBSCRIBE_BACKOFF_MIN;
+        }
+        receiver = loop {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RESUBSCRIBE_BACKOFF_MAX);
+            if app_state.shutting_down.load(Ordering::SeqCst) {
+                return;
+            }
+            match resubscribe(&app_state, topic, &bootstrap).await {
+                Ok(receiver) => break receiver,
+                Err(e) => warn!(
+                    "Failed to subscribe to {} again: {}. Retrying in {:?}",
+                    topic, e, backoff
+                ),
+            }
+        };
+        app_state
+            .gossip_stats
+            .lock()
+            .expect("gossip stats poisoned")
+            .restarts += 1;
+        info!("Subscribed to {} again.", topic);
+        app_state.lifecycle.recover();
+    }
+}
+
+/// Subscribes to `topic` again, bootstrapping from the ticket's peers and
+/// every peer that has announced itself, and replaces the sender.
+async fn resubscribe(
+    app_state: &AppState,
+    topic: TopicId,
+    bootstrap: &[NodeId],
+) -> Result<GossipReceiver> {
+    let mut peers: BTreeSet<NodeId> = bootstrap.iter().copied().collect();
+    peers.extend(app_state.devices.lock().await.keys().copied());
+    let (sender, receiver) = app_state
+        .gossip
+        .subscribe(topic, peers.into_iter().collect())?
+        .split();
+    *app_state.gossip_sender.lock().await = Some(sender);
+    Ok(receiver)
+}
+
+/// Downloads the blob of `str_ticket` and writes it to `dest_path`. The
+/// content stays in the store under `tag`, or is released once written when
+/// `tag` is `None`. Returns how the file was written.
 pub async fn get_iroh_blob(
-    blobs: Blobs<Store>,
+    app_state: &AppState,
     str_ticket: String,
     dest_path: PathBuf,
-) -> Result<(), Error> {
-    let blobs_client = blobs.client();
+    tag: Option<Tag>,
+    max_size: Option<u64>,
+) -> Result<storage::Exported, Error> {
     let ticket: BlobTicket = str_ticket.parse()?;
-    let download_req = blobs_client
-        .download(ticket.hash(), ticket.node_addr().clone())
-        .await?;
-    download_req.finish().await?;
-
-    blobs_client
-        .export(
-            ticket.hash(),
-            dest_path,
-            ExportFormat::Blob,
-            ExportMode::Copy,
-        )
-        .await?
-        .finish()
-        .await?;
-    Ok(())
+    let keep = tag.is_some();
+    storage::download_blob(
+        app_state,
+        ticket.hash(),
+        ticket.node_addr().clone(),
+        tag,
+        max_size,
+    )
+    .await?;
+
+    let exported = storage::export_blob(app_state, ticket.hash(), dest_path).await;
+    if !keep {
+        storage::release_download(&app_state.blobs, ticket.hash()).await?;
+    }
+    exported
 }
 
-pub fn handle_fs_payload(payload: FsEventPayload, handle: AppHandle) {
-    let app_state: State<'_, AppState> = handle.state();
-
-    match payload.event_type {
-        FsEventType::Create => {
-            let blobs_opt = app_state.blobs.clone();
-            let endpoint_opt = app_state.endpoint.clone();
-            let file_path = payload.path.clone();
-            let sync_folder_path = app_state.sync_folder.clone();
-            let gossip_sender_mutex = app_state.gossip_sender.clone();
-            let gossip_topic_mutex = app_state.gossip_topic.clone();
-
-            tauri::async_runtime::spawn(async move {
-                let blobs = match blobs_opt {
-                    Some(b) => b,
-                    None => {
-                        error!(
-                            "Blobs client not initialized for ticket creation for {:?}.",
-                            file_path
-                        );
-                        return;
-                    }
-                };
-                let endpoint = match endpoint_opt {
-                    Some(e) => e,
-                    None => {
-                        error!(
-                            "Endpoint not initialized for ticket creation for {:?}.",
-                            file_path
-                        );
-                        return;
-                    }
-                };
-
-                match create_iroh_ticket(blobs, endpoint.clone(), file_path.clone()).await {
-                    Ok(iroh_ticket) => {
-                        info!(
-                            "Created Iroh Ticket Successfully for {:?}: {}",
-                            file_path, iroh_ticket
-                        );
-
-                        info!(
-                            "Attempting to lock gossip_topic_mutex for file: {:?}",
-                            file_path
-                        );
-                        let gossip_topic_guard = gossip_topic_mutex.lock().await;
-                        let topic_id = if let Some(topic) = &*gossip_topic_guard {
-                            info!(
-                                "Successfully locked and read gossip_topic_mutex for file: {:?}, topic: {:?}",
-                                file_path, topic
-                            );
-                            topic.clone() // Clone the TopicId for use
-                        } else {
-                            warn!(
-                                "Gossip topic not set in AppState. Ticket {} for file {:?} created but cannot be gossiped.",
-                                iroh_ticket, file_path
-                            );
-                            return; // Exit if topic is not set
-                        };
-                        // Drop the guard for gossip_topic_mutex explicitly if needed, or let it go out of scope
-                        drop(gossip_topic_guard);
-
-                        info!(
-                            "Attempting to lock gossip_sender_mutex for file: {:?}",
-                            file_path
-                        );
-                        let sender_guard = gossip_sender_mutex.lock().await;
-                        info!(
-                            "Successfully locked gossip_sender_mutex for file: {:?}",
-                            file_path
-                        );
-
-                        if let Some(sender) = &*sender_guard {
-                            let relative_path = match file_path.strip_prefix(&sync_folder_path) {
-                                Ok(p) => p.to_path_buf(),
-                                Err(e) => {
-                                    error!(
-                                        "Failed to create relative path for {:?} from base {:?}: {}",
-                                        file_path, sync_folder_path, e
-                                    );
-                                    // If we can't determine the relative path, we can't form a meaningful gossip message.
-                                    return;
-                                }
-                            }.to_string_lossy().into_owned();
-
-                            let file_name = match file_path.file_name() {
-                                Some(name_os_str) => name_os_str.to_string_lossy().into_owned(),
-                                None => {
-                                    error!("Failed to get file name from path: {:?}", file_path);
-                                    // If there's no file name, we can't form a meaningful gossip message.
-                                    return;
-                                }
-                            };
-
-                            let gossip_message = GossipEventPayload {
-                                from: endpoint.node_id(),
-                                topic: topic_id, // Use the cloned and verified topic_id
-                                message_content: iroh_ticket.clone(),
-                                file_name,     // This is now a String, shorthand is fine
-                                relative_path, // This is now a PathBuf, shorthand is fine
-                            };
-                            info!("gossip message created {:?}", gossip_message);
-                            match sender.broadcast(gossip_message.to_vec().into()).await {
-                                Ok(_) => info!("Gossiped ticket: {}", iroh_ticket),
-                                Err(e) => {
-                                    error!("Failed to gossip ticket {}: {:?}", iroh_ticket, e);
-                                }
-                            }
-                        } else {
-                            warn!(
-                                "Gossip sender not available. Ticket {} for file {:?} created but not gossiped.",
-                                iroh_ticket, file_path
-                            );
-                        }
-                    }
-                    Err(err) => {
-                        error!("Ticket Creation failed for {:?}: {}", file_path, err);
-                    }
-                }
-            });
+/// Sends `message` to the joined topic. Returns `false` when no topic has
+/// been joined yet.
+pub async fn broadcast_message(app_state: &AppState, message: Message) -> Result<bool> {
+    let envelope = Envelope::new(app_state.endpoint.node_id(), message);
+    match &*app_state.gossip_sender.lock().await {
+        Some(sender) => {
+            sender.broadcast(envelope.to_vec().into()).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+pub fn handle_fs_payload(payload: FsEventPayload, app_state: &AppState) {
+    // Edits are imported and announced once they settle. The store follows
+    // them even while paused so it never serves a file's old hash with its
+    // new bytes.
+    if matches!(payload.event_type, FsEventType::Modify) {
+        storage::schedule_rehash(app_state, payload.path);
+        return;
+    }
+    if !matches!(
+        payload.event_type,
+        FsEventType::Create | FsEventType::Remove | FsEventType::Rename
+    ) {
+        return;
+    }
+    if pause::hold_outgoing(app_state, &payload) {
+        return;
+    }
+
+    let app_state = app_state.clone();
+    let tasks = app_state.tasks.clone();
+    // Tracked so shutdown can wait for the broadcast to go out.
+    tokio::spawn(tasks.track_future(async move {
+        if let Err(e) = announce_fs_change(&app_state, &payload).await {
+            error!(
+                "Failed to announce {:?} of {:?}: {:#}",
+                payload.event_type, payload.path, e
+            );
         }
-        FsEventType::Remove => {
-            info!("File system event: Remove for path {:?}", payload.path);
-            // Consider sending a gossip message about the removal.
+    }));
+}
+
+/// Tells peers about a change in the sync folder.
+pub(crate) async fn announce_fs_change(
+    app_state: &AppState,
+    payload: &FsEventPayload,
+) -> Result<()> {
+    // The folder may have been switched or removed since the event fired.
+    let Some(sync_folder) = app_state.sync_folder.lock().await.clone() else {
+        warn!(
+            "No sync folder set. Ignoring {:?} event for {:?}.",
+            payload.event_type, payload.path
+        );
+        return Ok(());
+    };
+
+    let message = match (&payload.event_type, &payload.from) {
+        (FsEventType::Remove, _) => Message::FileDeleted {
+            relative_path: relative_wire_path(&sync_folder, &payload.path)?,
+        },
+        (FsEventType::Rename, Some(from)) => Message::FileRenamed {
+            old_path: relative_wire_path(&sync_folder, from)?,
+            new_path: relative_wire_path(&sync_folder, &payload.path)?,
+        },
+        _ => return announce_tree(app_state, &sync_folder, &payload.path).await,
+    };
+    match &message {
+        Message::FileDeleted { relative_path } => {
+            storage::release_file_tags(app_state, relative_path).await?
+        }
+        Message::FileRenamed { old_path, new_path } => {
+            storage::rename_file_tags(app_state, old_path, new_path).await?
         }
         _ => {}
     }
+    announce(app_state, message).await
 }
 
-// Updated subscribe_loop to accept AppHandle and emit events
-pub async fn subscribe_loop<R: tauri::Runtime>(
-    app_handle: AppHandle<R>,
-    blobs: Blobs<Store>,
-    sync_path: PathBuf,
-    mut receiver: GossipReceiver,
-) -> Result<()> {
-    while let Some(result) = receiver.next().await {
-        // Changed from try_next to next for typical stream handling
+/// Announces `path` and, for a folder, everything inside it, parents first.
+/// Folders go out as [`Message::DirCreated`] so empty ones are synced too,
+/// and a tree moved in as a whole arrives complete even though the watcher
+/// only reports its root. Links are handled by the [`SymlinkPolicy`].
+async fn announce_tree(app_state: &AppState, sync_folder: &Path, path: &Path) -> Result<()> {
+    let policy = *app_state
+        .symlink_policy
+        .lock()
+        .expect("symlink policy poisoned");
+    let follow = policy == SymlinkPolicy::Follow;
+    // Following also reports links back into their own parents as errors.
+    for entry in WalkDir::new(path)
+        .follow_links(follow)
+        .follow_root_links(follow)
+    {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // Entries can disappear while the tree is walked.
+            Err(e) => {
+                warn!("Skipping entry while announcing {:?}: {}", path, e);
+                continue;
+            }
+        };
+        let relative_path = relative_wire_path(sync_folder, entry.path())?;
+        let message = if entry.path_is_symlink() && !follow {
+            if policy == SymlinkPolicy::Ignore {
+                info!("Not announcing link {:?}", entry.path());
+                continue;
+            }
+            match symlinks::link_target(sync_folder, entry.path())? {
+                Some(target) => Message::SymlinkChanged {
+                    relative_path,
+                    target,
+                },
+                None => {
+                    info!(
+                        "Not announcing {:?}: it points outside the sync folder",
+                        entry.path()
+                    );
+                    continue;
+                }
+            }
+        } else if entry.file_type().is_dir() {
+            Message::DirCreated { relative_path }
+        } else if entry.file_type().is_file() {
+            let previous = storage::file_content(app_state, &relative_path).await?;
+            let ticket = match create_iroh_ticket(
+                app_state.blobs.clone(),
+                app_state.endpoint.clone(),
+                entry.path().to_path_buf(),
+                storage::file_tag(&relative_path),
+            )
+            .await
+            {
+                Ok(ticket) => ticket,
+                Err(err) => {
+                    error!("Ticket Creation failed for {:?}: {}", entry.path(), err);
+                    continue;
+                }
+            };
+            info!("Created Iroh Ticket Successfully for {:?}", entry.path());
+            let hash = BlobTicket::from_str(&ticket)?.hash();
+            if let Some(previous) = previous.filter(|previous| *previous != hash) {
+                // The file may have been the only copy of what it held before.
+                storage::drop_if_stale(&app_state.blobs, previous).await?;
+            }
+            file_changed(app_state, relative_path, entry.path(), ticket)
+        } else {
+            info!("Not announcing {:?}: not a regular file", entry.path());
+            continue;
+        };
+        announce(app_state, message).await?;
+    }
+    Ok(())
+}
+
+/// Announces an edited file already imported as `hash`, see
+/// `storage::schedule_rehash`. A held edit is queued instead, so syncing
+/// later sends the new content.
+pub(crate) async fn announce_edit(app_state: &AppState, path: &Path, hash: Hash) -> Result<()> {
+    let payload = FsEventPayload {
+        event_type: FsEventType::Modify,
+        path: path.to_path_buf(),
+        from: None,
+    };
+    if pause::hold_outgoing(app_state, &payload) {
+        return Ok(());
+    }
+    let Some(sync_folder) = app_state.sync_folder.lock().await.clone() else {
+        return Ok(());
+    };
+    let relative_path = relative_wire_path(&sync_folder, path)?;
+    let ticket = BlobTicket::new(app_state.endpoint.node_id().into(), hash, BlobFormat::Raw)?;
+    let message = file_changed(app_state, relative_path, path, ticket.to_string());
+    announce(app_state, message).await
+}
+
+/// The [`Message::FileChanged`] for `path`, shared as `ticket`.
+fn file_changed(
+    app_state: &AppState,
+    relative_path: String,
+    path: &Path,
+    ticket: String,
+) -> Message {
+    let settings = *app_state
+        .metadata_settings
+        .lock()
+        .expect("metadata settings poisoned");
+    Message::FileChanged {
+        relative_path,
+        ticket,
+        size: std::fs::metadata(path).ok().map(|metadata| metadata.len()),
+        metadata: metadata::read(&settings, path),
+    }
+}
+
+/// Announces files the node itself wrote into the sync folder, such as a
+/// restore. Overwriting a file is not an event the watcher announces.
+pub async fn announce_local_paths(app_state: &AppState, paths: &[PathBuf]) -> Result<()> {
+    let Some(sync_folder) = app_state.sync_folder.lock().await.clone() else {
+        bail!("No sync folder set");
+    };
+    for path in paths {
+        let payload = FsEventPayload {
+            event_type: FsEventType::Create,
+            path: path.clone(),
+            from: None,
+        };
+        if pause::hold_outgoing(app_state, &payload) {
+            continue;
+        }
+        announce_tree(app_state, &sync_folder, path).await?;
+    }
+    Ok(())
+}
+
+/// Broadcasts `message`, only warning when no topic has been joined yet.
+async fn announce(app_state: &AppState, message: Message) -> Result<()> {
+    let description = format!("{:?}", message);
+    if broadcast_message(app_state, message).await? {
+        info!("Announced {}", description);
+    } else {
+        warn!("No topic joined. Not announcing {}", description);
+    }
+    Ok(())
+}
+
+/// `path` relative to the sync folder, in wire form.
+fn relative_wire_path(sync_folder: &Path, path: &Path) -> Result<String> {
+    let relative = path
+        .strip_prefix(sync_folder)
+        .with_context(|| format!("{:?} is not inside {:?}", path, sync_folder))?;
+    if relative.as_os_str().is_empty() {
+        bail!("{:?} is the sync folder itself", path);
+    }
+    Ok(to_wire_path(relative))
+}
+
+/// Handles messages from the topic and sends the presence heartbeat until the
+/// receiver closes or fails. Undecodable messages are counted and skipped.
+pub async fn subscribe_loop(app_state: AppState, mut receiver: GossipReceiver) -> Result<()> {
+    let mut heartbeat = tokio::time::interval(PRESENCE_INTERVAL);
+    loop {
+        let result = tokio::select! {
+            _ = heartbeat.tick() => {
+                if let Err(e) = announce_presence(&app_state).await {
+                    warn!("Failed to announce presence: {}", e);
+                }
+                continue;
+            }
+            result = receiver.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+        };
         match result {
             Ok(event) => {
                 if let GossipNetEvent::Gossip(GossipEvent::Received(msg)) = event {
@@ -395,64 +938,348 @@ pub async fn subscribe_loop<R: tauri::Runtime>(
                         msg.content.len()
                     );
 
-                    // Emit the raw message (or a structured version) to the frontend
-                    let payload = GossipEventPayload::from_bytes(&msg.content).unwrap();
-                    let sync_path_clone = sync_path.clone();
-                    info!("GossipEventPayload: {:?}", payload);
-                    let payload_clone = payload.clone();
-                    let blobs_clone = blobs.clone();
-                    tauri::async_runtime::spawn(async move {
-                        let str_iroh_ticket = payload_clone.message_content;
-                        let dest_path = sync_path_clone.join(&payload_clone.relative_path);
-                        match get_iroh_blob(blobs_clone, str_iroh_ticket, dest_path).await {
-                            Ok(_) => {
-                                info!("Fetching Iroh blob from the ticket");
-                            }
-                            Err(e) => {
-                                error!("Error fetching iroh blob from the ticket {}", e);
-                            }
-                        }
-                    });
-
-                    if let Err(e) = app_handle.emit("gossip://message", payload) {
-                        error!("Failed to emit gossip message to frontend: {}", e);
+                    app_state
+                        .gossip_stats
+                        .lock()
+                        .expect("gossip stats poisoned")
+                        .received += 1;
+                    match Envelope::from_bytes(&msg.content) {
+                        Ok(envelope) => handle_message(&app_state, envelope).await,
+                        Err(e) => record_decode_failure(&app_state, msg.delivered_from, e),
                     }
-
-                    // If you had a specific message protocol like the example:
-                    // match Message::from_bytes(&msg.content) {
-                    //     Ok(Message::AboutMe { from, name }) => {
-                    //         names.insert(from, name.clone());
-                    //         info!("> {} is now known as {}", from.fmt_short(), name);
-                    //     }
-                    //     Ok(Message::Message { from, text }) => {
-                    //         let name_display = names
-                    //             .get(&from)
-                    //             .map_or_else(|| from.fmt_short(), String::to_string);
-                    //         info!("{}: {}", name_display, text);
-                    //     }
-                    //     Err(e) => {
-                    //         warn!("Failed to deserialize gossip message: {:?}", e);
-                    //     }
-                    // }
-                } else if let GossipNetEvent::Gossip(GossipEvent::NeighborUp(node_id)) = event {
-                    info!("Neighbor up: {:?}", node_id);
-                    // Optionally emit this event to the frontend too
-                    if let Err(e) = app_handle.emit("gossip://neighbor-up", node_id.to_string()) {
-                        error!("Failed to emit neighbor-up event: {}", e);
+                } else if let GossipNetEvent::Gossip(GossipEvent::Joined(node_ids)) = event {
+                    // The first neighbors arrive here rather than as `NeighborUp`.
+                    for node_id in node_ids {
+                        handle_neighbor_up(&app_state, node_id).await;
                     }
+                } else if let GossipNetEvent::Gossip(GossipEvent::NeighborUp(node_id)) = event {
+                    handle_neighbor_up(&app_state, node_id).await;
                 } else if let GossipNetEvent::Gossip(GossipEvent::NeighborDown(node_id)) = event {
                     info!("Neighbor down: {:?}", node_id);
-                    if let Err(e) = app_handle.emit("gossip://neighbor-down", node_id.to_string()) {
+                    app_state.neighbors.lock().await.remove(&node_id);
+                    if let Err(e) = app_state
+                        .events
+                        .emit("gossip://neighbor-down", node_id.to_string())
+                    {
                         error!("Failed to emit neighbor-down event: {}", e);
                     }
+                    emit_device_changed(&app_state, node_id).await;
+                } else if let GossipNetEvent::Lagged = event {
+                    warn!("Gossip receiver lagged behind; some messages were missed.");
                 }
-                // Handle other GossipNetEvent variants as needed
             }
-            Err(e) => {
-                error!("Gossip receiver stream error: {:?}", e);
-                // Depending on the error, you might want to break or implement retry logic
+            // `supervise_gossip` subscribes again.
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Why a gossip message was dropped, sent as `gossip://decode-error`.
+#[derive(Debug, Serialize)]
+struct DecodeFailure {
+    delivered_from: NodeId,
+    error: String,
+    /// Failures so far, including this one.
+    decode_failures: u64,
+}
+
+/// Counts and reports a message that could not be decoded. The message is
+/// dropped and the receive loop carries on.
+fn record_decode_failure(app_state: &AppState, delivered_from: NodeId, error: Error) {
+    let error = format!("{:#}", error);
+    let decode_failures = {
+        let mut stats = app_state
+            .gossip_stats
+            .lock()
+            .expect("gossip stats poisoned");
+        stats.decode_failures += 1;
+        stats.last_decode_error = Some(error.clone());
+        stats.decode_failures
+    };
+    warn!(
+        "Ignoring gossip message from {} that could not be decoded ({} so far): {}",
+        delivered_from, decode_failures, error
+    );
+    let failure = DecodeFailure {
+        delivered_from,
+        error,
+        decode_failures,
+    };
+    if let Err(e) = app_state.events.emit("gossip://decode-error", failure) {
+        error!("Failed to emit decode error: {}", e);
+    }
+}
+
+/// Acts on one decoded message and forwards it to the UI as
+/// `gossip://message`. Changes that are paused or waiting for a manual sync
+/// are queued in `pause` instead.
+async fn handle_message(app_state: &AppState, envelope: Envelope) {
+    if pause::hold_incoming(app_state, &envelope).await {
+        return;
+    }
+    apply_message(app_state, envelope).await;
+}
+
+/// Acts on a message without checking whether it is held back; `pause` calls
+/// this to replay its queue.
+pub(crate) async fn apply_message(app_state: &AppState, envelope: Envelope) {
+    match &envelope.message {
+        Message::Presence { name, color } => {
+            handle_presence(app_state, envelope.from, name.clone(), color.clone()).await;
+            return;
+        }
+        Message::FileChanged {
+            relative_path,
+            ticket,
+            size,
+            metadata,
+        } => handle_file_changed(
+            app_state,
+            IncomingFile {
+                from: envelope.from,
+                relative_path: relative_path.clone(),
+                ticket: ticket.clone(),
+                size: *size,
+                metadata: metadata.clone(),
+            },
+        ),
+        Message::FileDeleted { .. }
+        | Message::FileRenamed { .. }
+        | Message::DirCreated { .. }
+        | Message::SymlinkChanged { .. } => {
+            if let Err(e) = apply_fs_operation(app_state, envelope.from, &envelope.message).await {
+                error!(
+                    "Failed to apply {:?} from {}: {:#}",
+                    envelope.message, envelope.from, e
+                );
+            }
+        }
+        Message::ManifestSummary { .. } => {
+            info!("Received {:?} from {}", envelope.message, envelope.from);
+        }
+        Message::Unknown => {
+            info!(
+                "Skipping message from {} that this version does not understand (protocol v{})",
+                envelope.from, envelope.version
+            );
+            return;
+        }
+    }
+
+    if let Err(e) = app_state.events.emit("gossip://message", envelope) {
+        error!("Failed to emit gossip message to frontend: {}", e);
+    }
+}
+
+/// Applies a folder operation from peer `from` to the sync folder. Runs in
+/// order with the other messages so a folder exists before it is renamed.
+/// Deleted files go to the trash first.
+async fn apply_fs_operation(app_state: &AppState, from: NodeId, message: &Message) -> Result<()> {
+    let Some(sync_folder) = app_state.sync_folder.lock().await.clone() else {
+        warn!("No sync folder set. Not applying {:?}", message);
+        return Ok(());
+    };
+    let policy = *app_state
+        .symlink_policy
+        .lock()
+        .expect("symlink policy poisoned");
+    let resolve = |wire: &str| {
+        let relative = from_wire_path(wire)
+            .with_context(|| format!("{:?} points outside the sync folder", wire))?;
+        symlinks::resolve(&sync_folder, &relative)
+    };
+
+    // Each operation is a no-op when the folder already looks like the
+    // result, so our own watcher echoing it back ends there.
+    match message {
+        Message::DirCreated { relative_path } => {
+            let path = resolve(relative_path)?;
+            if !path.is_dir() {
+                tokio::fs::create_dir_all(&path)
+                    .await
+                    .with_context(|| format!("Failed to create folder {:?}", path))?;
+                info!("Created folder {:?}", path);
+            }
+        }
+        Message::FileDeleted { relative_path } => {
+            quota::forget(app_state, relative_path);
+            let path = resolve(relative_path)?;
+            let metadata = match tokio::fs::symlink_metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            trash::move_to_trash(app_state, &sync_folder, &path, from).await?;
+            if metadata.is_dir() {
+                tokio::fs::remove_dir_all(&path).await
+            } else {
+                tokio::fs::remove_file(&path).await
             }
+            .with_context(|| format!("Failed to remove {:?}", path))?;
+            storage::release_file_tags(app_state, relative_path).await?;
+            info!("Removed {:?}", path);
         }
+        Message::FileRenamed { old_path, new_path } => {
+            quota::rename(app_state, old_path, new_path);
+            let (from, to) = (resolve(old_path)?, resolve(new_path)?);
+            // Links move as links, even when they dangle.
+            let exists = |path: &Path| std::fs::symlink_metadata(path).is_ok();
+            if !exists(&from) || exists(&to) {
+                info!(
+                    "Not moving {:?} to {:?}: source missing or target exists",
+                    from, to
+                );
+                return Ok(());
+            }
+            if let Some(parent) = to.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(&from, &to)
+                .await
+                .with_context(|| format!("Failed to move {:?} to {:?}", from, to))?;
+            storage::rename_file_tags(app_state, old_path, new_path).await?;
+            info!("Moved {:?} to {:?}", from, to);
+        }
+        Message::SymlinkChanged {
+            relative_path,
+            target,
+        } => {
+            if policy != SymlinkPolicy::Preserve {
+                info!(
+                    "Not creating link {:?}: links are not preserved",
+                    relative_path
+                );
+                return Ok(());
+            }
+            quota::forget(app_state, relative_path);
+            let relative = from_wire_path(relative_path)
+                .with_context(|| format!("{:?} points outside the sync folder", relative_path))?;
+            symlinks::create_link(&sync_folder, &relative, target).await?;
+        }
+        _ => {}
     }
     Ok(())
 }
+
+/// A file a peer announced, with what it takes to download it.
+#[derive(Debug, Clone)]
+pub(crate) struct IncomingFile {
+    pub from: NodeId,
+    pub relative_path: String,
+    pub ticket: String,
+    pub size: Option<u64>,
+    pub metadata: Option<FileMetadata>,
+}
+
+/// Downloads an announced file into the sync folder in the background.
+fn handle_file_changed(app_state: &AppState, mut file: IncomingFile) {
+    let Some(relative) = from_wire_path(&file.relative_path) else {
+        warn!(
+            "Ignoring file outside the sync folder: {:?}",
+            file.relative_path
+        );
+        return;
+    };
+    if let Err(e) = BlobTicket::from_str(&file.ticket) {
+        warn!(
+            "Ignoring {:?} with invalid ticket: {}",
+            file.relative_path, e
+        );
+        return;
+    }
+    file.relative_path = to_wire_path(&relative);
+    let app_state = app_state.clone();
+    let tasks = app_state.tasks.clone();
+    // Tracked so shutdown does not cut an export short.
+    tokio::spawn(tasks.track_future(async move {
+        let relative_path = file.relative_path.clone();
+        if let Err(e) = receive_file(&app_state, file, false).await {
+            error!("Failed to download {:?}: {:#}", relative_path, e);
+        }
+    }));
+}
+
+/// Downloads `file`, keeping the content it replaces as a version, and
+/// applies its attributes. Returns `false` if [`quota::admit`] deferred it
+/// instead; `on_demand` fetches skip the size policy.
+pub(crate) async fn receive_file(
+    app_state: &AppState,
+    file: IncomingFile,
+    on_demand: bool,
+) -> Result<bool> {
+    let relative_path = file.relative_path.as_str();
+    let relative = from_wire_path(relative_path)
+        .with_context(|| format!("{:?} points outside the sync folder", relative_path))?;
+    let hash = BlobTicket::from_str(&file.ticket)?.hash();
+    // Resolve the folder per message so a runtime switch is picked up.
+    let Some(sync_path) = app_state.sync_folder.lock().await.clone() else {
+        bail!("No sync folder set");
+    };
+    let Some(reservation) = quota::admit(app_state, &sync_path, &file, on_demand).await? else {
+        return Ok(false);
+    };
+
+    let dest_path = symlinks::prepare_destination(&sync_path, &relative).await?;
+    if let Err(e) = versions::preserve_current(app_state, relative_path, &dest_path).await {
+        // Losing history is better than not syncing at all.
+        warn!("{:#}", e);
+    }
+    let previous = match storage::file_content(app_state, relative_path).await {
+        Ok(previous) => previous.filter(|previous| *previous != hash),
+        Err(e) => {
+            warn!("{:#}", e);
+            None
+        }
+    };
+    let tag = storage::file_tag(relative_path);
+    info!("Fetching Iroh blob from the ticket");
+    let exported = get_iroh_blob(
+        app_state,
+        file.ticket.clone(),
+        dest_path.clone(),
+        Some(tag),
+        reservation.max_size(),
+    )
+    .await?;
+    reservation.settle();
+    if let Some(metadata) = &file.metadata {
+        let settings = *app_state
+            .metadata_settings
+            .lock()
+            .expect("metadata settings poisoned");
+        if let Err(e) = metadata::apply(&settings, &dest_path, metadata) {
+            warn!("{:#}", e);
+        }
+    }
+    if let Some(previous) = previous {
+        if let Err(e) = storage::drop_if_stale(&app_state.blobs, previous).await {
+            warn!("{:#}", e);
+        }
+    }
+    // A moved file is the only copy of its content, which an edit here
+    // would change under the version. It is kept once a peer's change is
+    // about to overwrite it, see `preserve_current` above.
+    if exported == storage::Exported::Copied {
+        if let Err(e) = versions::record_version(app_state, relative_path, hash, file.from).await {
+            warn!("Failed to record version of {:?}: {:#}", relative_path, e);
+        }
+    }
+    Ok(true)
+}
+
+async fn handle_neighbor_up(app_state: &AppState, node_id: NodeId) {
+    info!("Neighbor up: {:?}", node_id);
+    app_state.neighbors.lock().await.insert(node_id);
+    if let Err(e) = app_state
+        .events
+        .emit("gossip://neighbor-up", node_id.to_string())
+    {
+        error!("Failed to emit neighbor-up event: {}", e);
+    }
+    emit_device_changed(app_state, node_id).await;
+    // Let the newcomer know who we are without waiting for the heartbeat.
+    if let Err(e) = announce_presence(app_state).await {
+        warn!("Failed to announce presence: {}", e);
+    }
+}
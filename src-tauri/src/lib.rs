@@ -3,22 +3,62 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+// Tauri-specific code lives behind the `desktop` feature; the rest is the sync
+// engine shared with the headless `fastsyncd` daemon.
+pub mod bandwidth;
+pub mod collection;
+#[cfg(feature = "desktop")]
 mod commands;
-mod fs_watcher;
-mod iroh_fns;
-mod state;
+pub mod control;
+pub mod daemon;
+pub mod error;
+pub mod events;
+pub mod fs_watcher;
+pub mod iroh_fns;
+pub mod metadata;
+pub mod pause;
+pub mod presence;
+pub mod protocol;
+pub mod quota;
+pub mod settings;
+pub mod snapshot;
+pub mod state;
+pub mod storage;
+pub mod symlinks;
+pub mod trash;
+pub mod versions;
 
+#[cfg(feature = "desktop")]
 use commands::{
-    create_gossip_ticket, create_ticket, get_blob, get_node_info, join_gossip, setup_iroh_and_fs,
+    collect_garbage, compare_snapshots, create_gossip_ticket, create_snapshot, create_ticket,
+    deferred_downloads, delete_snapshot, empty_trash, fetch_deferred, get_bandwidth_settings,
+    get_blob, get_device_profile, get_metadata_settings, get_network_settings, get_node_info,
+    get_quota_settings, get_snapshot_settings, get_symlink_policy, get_trash_settings,
+    get_version_settings, join_gossip, list_collection, list_devices, list_snapshots, list_trash,
+    list_versions, node_status, pause_status, pause_sync, pending_changes, remove_sync_folder,
+    restore_from_trash, restore_snapshot, restore_version, resume_sync, set_auto_sync,
+    set_bandwidth_settings, set_device_profile, set_metadata_settings, set_network_settings,
+    set_quota_settings, set_snapshot_settings, set_symlink_policy, set_sync_folder,
+    set_trash_settings, set_version_settings, setup_iroh_and_fs, shutdown, storage_stats, sync_now,
+    Node,
 };
-use log::LevelFilter;
+#[cfg(feature = "desktop")]
+use iroh_fns::shutdown_node;
+#[cfg(feature = "desktop")]
+use log::{error, LevelFilter};
+#[cfg(feature = "desktop")]
+use std::sync::atomic::Ordering;
+#[cfg(feature = "desktop")]
+use tauri::{Manager, RunEvent};
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+#[cfg(feature = "desktop")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // --- Logger Setup --- (Recommended)
@@ -30,7 +70,18 @@ pub fn run() {
 
     tauri::Builder::default()
         .plugin(log_plugin) // Add logger first
-        .setup(|_| {
+        .setup(|app| {
+            // Managed right away so commands can report the node's status
+            // while it starts in the background.
+            app.manage(Node::new(app.handle()));
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let node = handle.state::<Node>();
+                if let Err(e) = node.start(&handle).await {
+                    error!("Node failed to start: {}", e);
+                }
+            });
+
             // let handle = app.handle().clone(); // Clone handle for async task
 
             // #[cfg(debug_assertions)]
@@ -53,11 +104,85 @@ pub fn run() {
             greet,
             setup_iroh_and_fs,
             get_blob,
+            list_collection,
             create_ticket,
             create_gossip_ticket,
             join_gossip,
-            get_node_info
+            get_node_info,
+            node_status,
+            get_network_settings,
+            set_network_settings,
+            get_device_profile,
+            set_device_profile,
+            list_devices,
+            create_snapshot,
+            list_snapshots,
+            compare_snapshots,
+            restore_snapshot,
+            delete_snapshot,
+            get_snapshot_settings,
+            set_snapshot_settings,
+            list_versions,
+            restore_version,
+            get_version_settings,
+            set_version_settings,
+            list_trash,
+            restore_from_trash,
+            empty_trash,
+            get_trash_settings,
+            set_trash_settings,
+            storage_stats,
+            collect_garbage,
+            get_quota_settings,
+            set_quota_settings,
+            deferred_downloads,
+            fetch_deferred,
+            get_bandwidth_settings,
+            set_bandwidth_settings,
+            get_metadata_settings,
+            set_metadata_settings,
+            get_symlink_policy,
+            set_symlink_policy,
+            set_sync_folder,
+            remove_sync_folder,
+            pause_sync,
+            resume_sync,
+            pause_status,
+            set_auto_sync,
+            sync_now,
+            pending_changes,
+            shutdown
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::ExitRequested { api, .. } = event {
+                // Nothing to clean up if the node was never set up or is already down.
+                let Some(node) = app.try_state::<Node>() else {
+                    return;
+                };
+                let Some(state) = node.started() else {
+                    return;
+                };
+                if state.router.is_shutdown() {
+                    return;
+                }
+
+                // Keep the process alive until the node has shut down, then exit again.
+                api.prevent_exit();
+                if state.shutting_down.load(Ordering::SeqCst) {
+                    return;
+                }
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    let node = app.state::<Node>();
+                    if let Some(state) = node.started() {
+                        if let Err(e) = shutdown_node(state).await {
+                            error!("Graceful shutdown failed: {:?}", e);
+                        }
+                    }
+                    app.exit(0);
+                });
+            }
+        });
 }
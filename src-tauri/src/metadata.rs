@@ -0,0 +1,157 @@
+// src-tauri/src/metadata.rs
+
+//! File attributes synced alongside the content, see [`MetadataSettings`].
+//!
+//! The sender reads the attributes it has enabled when it announces a file,
+//! and the receiver applies the ones it has enabled once the content is in
+//! place. Modes are the Unix permission bits only, without setuid, setgid or
+//! sticky, and are neither read nor applied on other platforms. The owner
+//! always keeps read and write access, as a file from a peer is also the
+//! store's copy of it. Extended attributes in the `security.`, `system.` and
+//! `trusted.` namespaces belong to the local system rather than the file and
+//! are left alone.
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{presence::unix_ms, settings::MetadataSettings};
+
+/// Attributes of an announced file. Each is absent when the sender does not
+/// sync it or could not read it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileMetadata {
+    /// Modification time, in milliseconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime_ms: Option<u64>,
+    /// Unix permission bits, e.g. `0o755`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Extended attributes by name, values in base64. `None` when the
+    /// sender does not sync them, as opposed to a file without any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xattrs: Option<BTreeMap<String, String>>,
+}
+
+/// The attributes of `path` that `settings` sync, or `None` if there are
+/// none to send.
+pub fn read(settings: &MetadataSettings, path: &Path) -> Option<FileMetadata> {
+    let file = std::fs::metadata(path).ok()?;
+    let metadata = FileMetadata {
+        mtime_ms: settings
+            .mtime
+            .then(|| file.modified().ok())
+            .flatten()
+            .and_then(unix_ms),
+        mode: settings.permissions.then(|| mode(&file)).flatten(),
+        xattrs: settings.xattrs.then(|| read_xattrs(path)).flatten(),
+    };
+    (metadata != FileMetadata::default()).then_some(metadata)
+}
+
+/// Applies the attributes `settings` sync to the file at `path`. The mode
+/// goes last, as it may take away the write access the others need.
+pub fn apply(settings: &MetadataSettings, path: &Path, metadata: &FileMetadata) -> Result<()> {
+    if let Some(xattrs) = metadata.xattrs.as_ref().filter(|_| settings.xattrs) {
+        write_xattrs(path, xattrs)?;
+    }
+    if let Some(mtime_ms) = metadata.mtime_ms.filter(|_| settings.mtime) {
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_millis(mtime_ms);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(modified))
+            .with_context(|| format!("Failed to set the modification time of {:?}", path))?;
+    }
+    if let Some(mode) = metadata.mode.filter(|_| settings.permissions) {
+        set_mode(path, mode)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode(file: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(file.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn mode(_file: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = (mode & 0o777) | 0o600;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set the permissions of {:?}", path))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// `None` if they cannot be listed.
+#[cfg(unix)]
+fn read_xattrs(path: &Path) -> Option<BTreeMap<String, String>> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return None;
+    }
+    let names = xattr::list(path).ok()?;
+    let xattrs = names
+        .filter_map(|name| name.into_string().ok())
+        .filter(|name| is_synced_xattr(name))
+        .filter_map(|name| {
+            let value = xattr::get(path, &name).ok()??;
+            Some((name, data_encoding::BASE64.encode(&value)))
+        })
+        .collect();
+    Some(xattrs)
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(_path: &Path) -> Option<BTreeMap<String, String>> {
+    None
+}
+
+/// Makes the synced extended attributes of `path` match `xattrs`.
+#[cfg(unix)]
+fn write_xattrs(path: &Path, xattrs: &BTreeMap<String, String>) -> Result<()> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(());
+    }
+    let existing = xattr::list(path)
+        .with_context(|| format!("Failed to list extended attributes of {:?}", path))?;
+    for name in existing.filter_map(|name| name.into_string().ok()) {
+        if is_synced_xattr(&name) && !xattrs.contains_key(&name) {
+            xattr::remove(path, &name)
+                .with_context(|| format!("Failed to remove {} from {:?}", name, path))?;
+        }
+    }
+    for (name, value) in xattrs.iter().filter(|(name, _)| is_synced_xattr(name)) {
+        let value = data_encoding::BASE64
+            .decode(value.as_bytes())
+            .with_context(|| format!("Invalid value for {}", name))?;
+        xattr::set(path, name, &value)
+            .with_context(|| format!("Failed to set {} on {:?}", name, path))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_xattrs(_path: &Path, _xattrs: &BTreeMap<String, String>) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_synced_xattr(name: &str) -> bool {
+    !["security.", "system.", "trusted."]
+        .iter()
+        .any(|namespace| name.starts_with(namespace))
+}
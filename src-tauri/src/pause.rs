@@ -0,0 +1,686 @@
+// src-tauri/src/pause.rs
+
+//! Pausing sync for the whole node, one sync folder or one peer.
+//!
+//! While something is paused, the local changes it covers are queued instead
+//! of announced, and the changes from peers it covers are queued instead of
+//! applied or downloaded. Resuming replays whatever is no longer paused in
+//! the order it happened: local changes are announced with the folder's
+//! content at that point, peers' changes are applied as if just received.
+//!
+//! Gossip reaches every peer on the topic, so pausing a peer only holds back
+//! what it sends us; our own changes still reach it.
+//!
+//! Pauses and both queues are saved to `<data_dir>/pause.json` so they
+//! survive a restart: at once when a pause changes, and shortly after a
+//! change is queued, so a burst of changes is written once.
+//!
+//! Manual sync (auto-sync off) uses the same queues: every change waits as
+//! if everything were paused, until [`sync_now`] sends and applies what is
+//! not paused on its own. [`pending_changes`] lists what is waiting.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use iroh::NodeId;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fs_watcher::{FsEventPayload, FsEventType},
+    iroh_fns::{announce_fs_change, apply_message},
+    protocol::{Envelope, Message},
+    state::AppState,
+    storage::disk_usage,
+};
+
+const PAUSE_FILE: &str = "pause.json";
+
+/// How long a queued change waits to be saved, so changes arriving together
+/// are saved together.
+const SAVE_DELAY: Duration = Duration::from_millis(500);
+
+/// What to pause or resume.
+///
+/// Serializes as `{ "kind": "folder", "path": "/home/me/Sync" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum PauseTarget {
+    All,
+    /// A sync folder by absolute path, also while another one is active.
+    Folder {
+        path: PathBuf,
+    },
+    /// Changes sent by one peer.
+    Peer {
+        node_id: NodeId,
+    },
+}
+
+/// Saved pause state, see the module docs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PauseState {
+    all: bool,
+    folders: BTreeSet<PathBuf>,
+    peers: BTreeSet<NodeId>,
+    /// Local changes waiting to be announced, oldest first.
+    outgoing: Vec<FsEventPayload>,
+    /// Messages from peers waiting to be applied, oldest first.
+    incoming: Vec<Envelope>,
+    /// Index in `outgoing` of the latest change of each path.
+    #[serde(skip)]
+    latest: BTreeMap<PathBuf, usize>,
+    /// Auto-sync is off. Comes from the settings, so it is not saved here.
+    #[serde(skip)]
+    manual: bool,
+    /// A save of the queues is scheduled, see `schedule_save`.
+    #[serde(skip)]
+    save_pending: bool,
+    /// Number of the latest snapshot taken.
+    #[serde(skip)]
+    revision: u64,
+    /// Number of the latest snapshot written, so an older one that is
+    /// written late never replaces it.
+    #[serde(skip)]
+    written: Arc<Mutex<u64>>,
+}
+
+/// The state as it was when taken, written without holding the state lock.
+struct Snapshot {
+    revision: u64,
+    bytes: Vec<u8>,
+    written: Arc<Mutex<u64>>,
+}
+
+impl Snapshot {
+    fn write(self, data_root: &Path) -> Result<()> {
+        let mut written = self.written.lock().expect("pause file poisoned");
+        if *written >= self.revision {
+            return Ok(());
+        }
+        let path = data_root.join(PAUSE_FILE);
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, &self.bytes)
+            .with_context(|| format!("Failed to write {:?}", partial))?;
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to replace {:?}", path))?;
+        *written = self.revision;
+        Ok(())
+    }
+}
+
+/// What is paused and how much is waiting. Returned by `pause_status` and
+/// sent as `sync://paused` on every change.
+#[derive(Debug, Clone, Serialize)]
+pub struct PauseStatus {
+    pub all: bool,
+    pub folders: Vec<PathBuf>,
+    pub peers: Vec<NodeId>,
+    /// Auto-sync is off; changes wait for `sync_now`.
+    pub manual: bool,
+    pub queued_outgoing: usize,
+    pub queued_incoming: usize,
+}
+
+impl PauseState {
+    /// Reads the state saved in `data_root`; nothing is paused without one.
+    pub fn load(data_root: &Path) -> Result<Self> {
+        let path = data_root.join(PAUSE_FILE);
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                let mut state: Self = serde_json::from_slice(&bytes)
+                    .with_context(|| format!("Invalid pause state in {:?}", path))?;
+                let outgoing = std::mem::take(&mut state.outgoing);
+                state.set_outgoing(outgoing);
+                Ok(state)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        }
+    }
+
+    fn snapshot(&mut self) -> Result<Snapshot> {
+        self.revision += 1;
+        Ok(Snapshot {
+            revision: self.revision,
+            bytes: serde_json::to_vec(self)?,
+            written: self.written.clone(),
+        })
+    }
+
+    /// Queues a local change unless it repeats one already queued. Returns
+    /// whether it was added.
+    fn queue_outgoing(&mut self, payload: &FsEventPayload) -> bool {
+        // Editors often report the same change several times in a row, and an
+        // edit of a file already queued is sent with it.
+        let repeated = self.outgoing.last() == Some(payload)
+            || (payload.event_type == FsEventType::Modify
+                && self.latest.get(&payload.path).is_some_and(|&index| {
+                    matches!(
+                        self.outgoing[index].event_type,
+                        FsEventType::Create | FsEventType::Modify
+                    )
+                }));
+        if repeated {
+            return false;
+        }
+        self.latest
+            .insert(payload.path.clone(), self.outgoing.len());
+        self.outgoing.push(payload.clone());
+        true
+    }
+
+    fn set_outgoing(&mut self, outgoing: Vec<FsEventPayload>) {
+        self.latest = outgoing
+            .iter()
+            .enumerate()
+            .map(|(index, payload)| (payload.path.clone(), index))
+            .collect();
+        self.outgoing = outgoing;
+    }
+
+    fn holds_outgoing(&self, path: &Path) -> bool {
+        self.manual || self.pauses_outgoing(path)
+    }
+
+    fn holds_incoming(&self, sync_folder: Option<&Path>, from: NodeId) -> bool {
+        self.manual || self.pauses_incoming(sync_folder, from)
+    }
+
+    fn pauses_outgoing(&self, path: &Path) -> bool {
+        self.all || self.folders.iter().any(|folder| path.starts_with(folder))
+    }
+
+    fn pauses_incoming(&self, sync_folder: Option<&Path>, from: NodeId) -> bool {
+        self.all
+            || self.peers.contains(&from)
+            || sync_folder.is_some_and(|folder| self.folders.contains(folder))
+    }
+
+    fn status(&self) -> PauseStatus {
+        PauseStatus {
+            all: self.all,
+            folders: self.folders.iter().cloned().collect(),
+            peers: self.peers.iter().copied().collect(),
+            manual: self.manual,
+            queued_outgoing: self.outgoing.len(),
+            queued_incoming: self.incoming.len(),
+        }
+    }
+}
+
+/// Whether the whole node is paused.
+pub fn is_paused(app_state: &AppState) -> bool {
+    app_state.pause.lock().expect("pause state poisoned").all
+}
+
+pub fn pause_status(app_state: &AppState) -> PauseStatus {
+    app_state
+        .pause
+        .lock()
+        .expect("pause state poisoned")
+        .status()
+}
+
+/// Pauses `target`. Pausing something already paused changes nothing.
+pub fn pause(app_state: &AppState, target: PauseTarget) -> Result<()> {
+    update(app_state, |state| match &target {
+        PauseTarget::All => state.all = true,
+        PauseTarget::Folder { path } => {
+            state.folders.insert(path.clone());
+        }
+        PauseTarget::Peer { node_id } => {
+            state.peers.insert(*node_id);
+        }
+    })?;
+    info!("Sync paused: {:?}", target);
+    Ok(())
+}
+
+/// Resumes `target` and replays the queued changes nothing holds back any
+/// more. Something paused on its own stays paused when everything is
+/// resumed.
+pub async fn resume(app_state: &AppState, target: PauseTarget) -> Result<()> {
+    update(app_state, |state| match &target {
+        PauseTarget::All => state.all = false,
+        PauseTarget::Folder { path } => {
+            state.folders.remove(path);
+        }
+        PauseTarget::Peer { node_id } => {
+            state.peers.remove(node_id);
+        }
+    })?;
+    info!("Sync resumed: {:?}", target);
+    flush(app_state, false).await
+}
+
+/// Turns auto-sync on or off. Turning it on syncs whatever was waiting for
+/// it.
+pub async fn set_manual(app_state: &AppState, manual: bool) -> Result<()> {
+    {
+        let mut state = app_state.pause.lock().expect("pause state poisoned");
+        if state.manual == manual {
+            return Ok(());
+        }
+        state.manual = manual;
+    }
+    info!("Auto-sync {}", if manual { "off" } else { "on" });
+    flush(app_state, false).await
+}
+
+/// Announces and applies every waiting change that is not paused.
+pub async fn sync_now(app_state: &AppState) -> Result<()> {
+    flush(app_state, true).await
+}
+
+/// Which way a pending change goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PendingDirection {
+    /// A local change peers have not been told about.
+    Outgoing,
+    /// A peer's change not applied here yet.
+    Incoming,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PendingKind {
+    Changed,
+    Deleted,
+    Renamed,
+    DirCreated,
+    Symlink,
+}
+
+/// A change waiting for `sync_now` or a resume.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingChange {
+    pub direction: PendingDirection,
+    pub kind: PendingKind,
+    /// Relative to the sync folder, or absolute for a local change outside
+    /// the current one.
+    pub path: PathBuf,
+    /// Where a renamed path was before.
+    pub old_path: Option<PathBuf>,
+    /// Bytes to send or download, if known. Local sizes are read when listed.
+    pub size: Option<u64>,
+    /// The peer an incoming change came from.
+    pub from: Option<NodeId>,
+    /// Held back by a pause, so `sync_now` leaves it waiting.
+    pub paused: bool,
+}
+
+/// The waiting changes, oldest first; local changes first.
+pub async fn pending_changes(app_state: &AppState) -> Vec<PendingChange> {
+    let sync_folder = app_state.sync_folder.lock().await.clone();
+    let (outgoing, incoming) = {
+        let state = app_state.pause.lock().expect("pause state poisoned");
+        let outgoing: Vec<_> = state
+            .outgoing
+            .iter()
+            .map(|payload| (payload.clone(), state.pauses_outgoing(&payload.path)))
+            .collect();
+        let incoming: Vec<_> = state
+            .incoming
+            .iter()
+            .map(|envelope| {
+                let paused = state.pauses_incoming(sync_folder.as_deref(), envelope.from);
+                (envelope.clone(), paused)
+            })
+            .collect();
+        (outgoing, incoming)
+    };
+    let relative = |path: &Path| {
+        sync_folder
+            .as_deref()
+            .and_then(|folder| path.strip_prefix(folder).ok())
+            .map_or_else(|| path.to_path_buf(), Path::to_path_buf)
+    };
+
+    let mut pending = Vec::new();
+    for (payload, paused) in outgoing {
+        let kind = match payload.event_type {
+            FsEventType::Remove => PendingKind::Deleted,
+            FsEventType::Rename => PendingKind::Renamed,
+            _ => PendingKind::Changed,
+        };
+        let size = match kind {
+            PendingKind::Deleted => None,
+            _ => disk_usage(&payload.path).ok(),
+        };
+        pending.push(PendingChange {
+            direction: PendingDirection::Outgoing,
+            kind,
+            path: relative(&payload.path),
+            old_path: payload.from.as_deref().map(relative),
+            size,
+            from: None,
+            paused,
+        });
+    }
+    for (envelope, paused) in incoming {
+        let (kind, path, old_path, size) = match envelope.message {
+            Message::FileChanged {
+                relative_path,
+                size,
+                ..
+            } => (PendingKind::Changed, relative_path, None, size),
+            Message::FileDeleted { relative_path } => {
+                (PendingKind::Deleted, relative_path, None, None)
+            }
+            Message::FileRenamed { old_path, new_path } => {
+                (PendingKind::Renamed, new_path, Some(old_path), None)
+            }
+            Message::DirCreated { relative_path } => {
+                (PendingKind::DirCreated, relative_path, None, None)
+            }
+            Message::SymlinkChanged { relative_path, .. } => {
+                (PendingKind::Symlink, relative_path, None, None)
+            }
+            _ => continue,
+        };
+        pending.push(PendingChange {
+            direction: PendingDirection::Incoming,
+            kind,
+            path: path.into(),
+            old_path: old_path.map(PathBuf::from),
+            size,
+            from: Some(envelope.from),
+            paused,
+        });
+    }
+    pending
+}
+
+/// Queues a local change if it is paused. Returns whether it was queued.
+pub fn hold_outgoing(app_state: &AppState, payload: &FsEventPayload) -> bool {
+    let mut state = app_state.pause.lock().expect("pause state poisoned");
+    if !state.holds_outgoing(&payload.path) {
+        return false;
+    }
+    if state.queue_outgoing(payload) {
+        schedule_save(app_state, &mut state);
+    }
+    info!("Queued {:?} of {:?}", payload.event_type, payload.path);
+    true
+}
+
+/// Queues a change from a peer if it is paused. Returns whether it was
+/// queued.
+pub async fn hold_incoming(app_state: &AppState, envelope: &Envelope) -> bool {
+    if !matches!(
+        envelope.message,
+        Message::FileChanged { .. }
+            | Message::FileDeleted { .. }
+            | Message::FileRenamed { .. }
+            | Message::DirCreated { .. }
+            | Message::SymlinkChanged { .. }
+    ) {
+        return false;
+    }
+    let sync_folder = app_state.sync_folder.lock().await.clone();
+    let mut state = app_state.pause.lock().expect("pause state poisoned");
+    if !state.holds_incoming(sync_folder.as_deref(), envelope.from) {
+        return false;
+    }
+    state.incoming.push(envelope.clone());
+    schedule_save(app_state, &mut state);
+    info!("Queued {:?}", envelope.message);
+    true
+}
+
+/// Announces and applies the queued changes that are no longer held back,
+/// in the order they were queued; local changes first. `sync_now` sends
+/// what only manual sync holds back, too.
+async fn flush(app_state: &AppState, sync_now: bool) -> Result<()> {
+    let sync_folder = app_state.sync_folder.lock().await.clone();
+    let (outgoing, incoming, snapshot) = {
+        let mut state = app_state.pause.lock().expect("pause state poisoned");
+        let (held, outgoing) =
+            std::mem::take(&mut state.outgoing)
+                .into_iter()
+                .partition(|payload| {
+                    if sync_now {
+                        state.pauses_outgoing(&payload.path)
+                    } else {
+                        state.holds_outgoing(&payload.path)
+                    }
+                });
+        state.set_outgoing(held);
+        let (held, incoming) =
+            std::mem::take(&mut state.incoming)
+                .into_iter()
+                .partition(|envelope| {
+                    if sync_now {
+                        state.pauses_incoming(sync_folder.as_deref(), envelope.from)
+                    } else {
+                        state.holds_incoming(sync_folder.as_deref(), envelope.from)
+                    }
+                });
+        state.incoming = held;
+        (outgoing, incoming, state.snapshot()?)
+    };
+    snapshot.write(&app_state.data_root)?;
+    emit_status(app_state);
+    if !outgoing.is_empty() || !incoming.is_empty() {
+        info!(
+            "Replaying {} local and {} remote changes",
+            outgoing.len(),
+            incoming.len()
+        );
+    }
+
+    for payload in compact(outgoing) {
+        if let Err(e) = announce_fs_change(app_state, &payload).await {
+            error!(
+                "Failed to announce queued {:?} of {:?}: {:#}",
+                payload.event_type, payload.path, e
+            );
+        }
+    }
+    for envelope in incoming {
+        apply_message(app_state, envelope).await;
+    }
+    Ok(())
+}
+
+/// Turns renames of paths created while paused into creates of the new
+/// path: peers never saw the old one, so there is nothing for them to move.
+fn compact(outgoing: Vec<FsEventPayload>) -> Vec<FsEventPayload> {
+    let mut created: Vec<PathBuf> = Vec::new();
+    outgoing
+        .into_iter()
+        .map(|payload| {
+            let unseen = match (&payload.event_type, &payload.from) {
+                (FsEventType::Create, _) => true,
+                (FsEventType::Rename, Some(from)) => {
+                    created.iter().any(|path| from.starts_with(path))
+                }
+                _ => false,
+            };
+            if !unseen {
+                return payload;
+            }
+            created.push(payload.path.clone());
+            FsEventPayload {
+                event_type: FsEventType::Create,
+                path: payload.path,
+                from: None,
+            }
+        })
+        .collect()
+}
+
+fn update(app_state: &AppState, change: impl FnOnce(&mut PauseState)) -> Result<()> {
+    let snapshot = {
+        let mut state = app_state.pause.lock().expect("pause state poisoned");
+        change(&mut state);
+        state.snapshot()?
+    };
+    snapshot.write(&app_state.data_root)?;
+    emit_status(app_state);
+    Ok(())
+}
+
+/// Saves the queues [`SAVE_DELAY`] after a change is queued, once for all
+/// changes queued meanwhile, and off the thread that queued it. Failing to
+/// save only costs the queue on a restart, so it is logged rather than
+/// holding up the change.
+fn schedule_save(app_state: &AppState, state: &mut PauseState) {
+    if std::mem::replace(&mut state.save_pending, true) {
+        return;
+    }
+    let app_state = app_state.clone();
+    let tasks = app_state.tasks.clone();
+    tokio::spawn(tasks.track_future(async move {
+        tokio::time::sleep(SAVE_DELAY).await;
+        let snapshot = {
+            let mut state = app_state.pause.lock().expect("pause state poisoned");
+            state.save_pending = false;
+            state.snapshot()
+        };
+        let data_root = app_state.data_root.clone();
+        let saved = match snapshot {
+            Ok(snapshot) => tokio::task::spawn_blocking(move || snapshot.write(&data_root))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|saved| saved),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = saved {
+            warn!("{:#}", e);
+        }
+    }));
+}
+
+fn emit_status(app_state: &AppState) {
+    if let Err(e) = app_state
+        .events
+        .emit("sync://paused", pause_status(app_state))
+    {
+        error!("Failed to emit sync://paused event: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: FsEventType, path: &str) -> FsEventPayload {
+        FsEventPayload {
+            event_type,
+            path: path.into(),
+            from: None,
+        }
+    }
+
+    fn rename(from: &str, to: &str) -> FsEventPayload {
+        FsEventPayload {
+            event_type: FsEventType::Rename,
+            path: to.into(),
+            from: Some(from.into()),
+        }
+    }
+
+    #[test]
+    fn compact_turns_renames_of_new_paths_into_creates() {
+        let outgoing = vec![
+            event(FsEventType::Create, "/s/new"),
+            rename("/s/new", "/s/moved"),
+            rename("/s/new/inner", "/s/inner"),
+            rename("/s/old", "/s/elsewhere"),
+        ];
+        assert_eq!(
+            compact(outgoing),
+            vec![
+                event(FsEventType::Create, "/s/new"),
+                event(FsEventType::Create, "/s/moved"),
+                event(FsEventType::Create, "/s/inner"),
+                rename("/s/old", "/s/elsewhere"),
+            ]
+        );
+    }
+
+    #[test]
+    fn compact_follows_a_chain_of_renames() {
+        let outgoing = vec![
+            event(FsEventType::Create, "/s/a"),
+            rename("/s/a", "/s/b"),
+            rename("/s/b", "/s/c"),
+        ];
+        assert_eq!(
+            compact(outgoing).last(),
+            Some(&event(FsEventType::Create, "/s/c"))
+        );
+    }
+
+    #[test]
+    fn queue_skips_repeats_and_edits_of_queued_files() {
+        let mut state = PauseState::default();
+        assert!(state.queue_outgoing(&event(FsEventType::Create, "/s/a")));
+        assert!(!state.queue_outgoing(&event(FsEventType::Create, "/s/a")));
+        assert!(!state.queue_outgoing(&event(FsEventType::Modify, "/s/a")));
+        assert!(state.queue_outgoing(&event(FsEventType::Modify, "/s/b")));
+        assert!(!state.queue_outgoing(&event(FsEventType::Modify, "/s/b")));
+        // An edit after a removal brings the file back.
+        assert!(state.queue_outgoing(&event(FsEventType::Remove, "/s/a")));
+        assert!(state.queue_outgoing(&event(FsEventType::Modify, "/s/a")));
+        assert_eq!(state.outgoing.len(), 4);
+    }
+
+    #[test]
+    fn queue_index_follows_the_kept_changes() {
+        let mut state = PauseState::default();
+        state.queue_outgoing(&event(FsEventType::Create, "/s/a"));
+        state.queue_outgoing(&event(FsEventType::Remove, "/s/b"));
+        // What a flush keeps back of the queue.
+        state.set_outgoing(vec![event(FsEventType::Remove, "/s/b")]);
+        assert!(state.queue_outgoing(&event(FsEventType::Modify, "/s/a")));
+        assert!(!state.queue_outgoing(&event(FsEventType::Modify, "/s/a")));
+        assert_eq!(state.latest.get(Path::new("/s/a")), Some(&1));
+    }
+
+    #[test]
+    fn only_pauses_hold_back_what_sync_now_sends() {
+        let mut state = PauseState {
+            manual: true,
+            ..Default::default()
+        };
+        state.folders.insert("/s".into());
+        assert!(state.holds_outgoing(Path::new("/other/a")));
+        assert!(!state.pauses_outgoing(Path::new("/other/a")));
+        assert!(state.pauses_outgoing(Path::new("/s/a")));
+        assert!(!state.pauses_outgoing(Path::new("/sync/a")));
+    }
+
+    #[test]
+    fn saved_state_loads_with_its_queue() {
+        let data_root = std::env::temp_dir().join(format!("fastsync-pause-{}", std::process::id()));
+        std::fs::create_dir_all(&data_root).unwrap();
+        assert!(PauseState::load(&data_root).unwrap().outgoing.is_empty());
+
+        let mut state = PauseState {
+            all: true,
+            manual: true,
+            ..Default::default()
+        };
+        state.queue_outgoing(&event(FsEventType::Create, "/s/a"));
+        state.queue_outgoing(&rename("/s/a", "/s/b"));
+        state.snapshot().unwrap().write(&data_root).unwrap();
+
+        let mut loaded = PauseState::load(&data_root).unwrap();
+        std::fs::remove_dir_all(&data_root).unwrap();
+        assert!(loaded.all);
+        // Auto-sync comes from the settings.
+        assert!(!loaded.manual);
+        assert_eq!(loaded.outgoing, state.outgoing);
+        assert!(!loaded.queue_outgoing(&event(FsEventType::Modify, "/s/a")));
+    }
+}
@@ -0,0 +1,140 @@
+// src-tauri/src/presence.rs
+
+//! Device names and online state.
+//!
+//! Every node announces its [`DeviceProfile`] on the joined topic when it
+//! joins, when a neighbor comes up and then every [`PRESENCE_INTERVAL`] as a
+//! heartbeat. A peer counts as online while it is a direct neighbor or has
+//! been heard from within [`PRESENCE_TIMEOUT`].
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use iroh::NodeId;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    iroh_fns::broadcast_message, protocol::Message, settings::DeviceProfile, state::AppState,
+};
+
+/// How often presence is re-announced.
+pub const PRESENCE_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a peer stays online after its last announcement: three missed
+/// heartbeats.
+pub const PRESENCE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// What is known about a peer from its announcements.
+#[derive(Debug, Clone)]
+pub struct DeviceRecord {
+    pub name: String,
+    pub color: Option<String>,
+    pub last_seen: SystemTime,
+}
+
+/// A peer as reported to the UI by `list_devices` and `devices://changed`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub node_id: NodeId,
+    /// `None` until the peer has announced itself.
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub online: bool,
+    /// Unix time in milliseconds of the last announcement.
+    pub last_seen_ms: Option<u64>,
+}
+
+/// Profile used until the user picks a name.
+pub fn default_profile(node_id: NodeId) -> DeviceProfile {
+    DeviceProfile {
+        name: format!("Device {}", node_id.fmt_short()),
+        color: None,
+    }
+}
+
+/// Broadcasts this node's profile. Does nothing before a topic is joined.
+pub async fn announce_presence(app_state: &AppState) -> Result<()> {
+    let profile = app_state.device.lock().await.clone();
+    let message = Message::Presence {
+        name: profile.name,
+        color: profile.color,
+    };
+    broadcast_message(app_state, message).await.map(|_| ())
+}
+
+/// Replaces this node's profile and announces it right away.
+pub async fn set_device_profile(app_state: &AppState, profile: DeviceProfile) -> Result<()> {
+    info!("Device profile set to {:?}", profile);
+    *app_state.device.lock().await = profile;
+    announce_presence(app_state).await
+}
+
+/// Records a peer's announcement. A profile that fails
+/// [`DeviceProfile::validate`] is shown as the default one, but still counts
+/// as a heartbeat.
+pub async fn handle_presence(
+    app_state: &AppState,
+    from: NodeId,
+    name: String,
+    color: Option<String>,
+) {
+    if from == app_state.endpoint.node_id() {
+        return;
+    }
+    // Held to the same rules as our own profile, as it ends up in the UI.
+    let mut profile = DeviceProfile { name, color };
+    if let Err(e) = profile.validate() {
+        warn!("Invalid profile from {}: {:#}", from.fmt_short(), e);
+        profile = default_profile(from);
+    }
+    let record = DeviceRecord {
+        name: profile.name,
+        color: profile.color,
+        last_seen: SystemTime::now(),
+    };
+    app_state.devices.lock().await.insert(from, record);
+    emit_device_changed(app_state, from).await;
+}
+
+/// Emits `devices://changed` with the current view of `node_id`.
+pub async fn emit_device_changed(app_state: &AppState, node_id: NodeId) {
+    let is_neighbor = app_state.neighbors.lock().await.contains(&node_id);
+    let record = app_state.devices.lock().await.get(&node_id).cloned();
+    let device = device_info(node_id, record.as_ref(), is_neighbor);
+    if let Err(e) = app_state.events.emit("devices://changed", device) {
+        warn!("Failed to emit devices://changed: {}", e);
+    }
+}
+
+/// All peers that are neighbors now or have announced themselves before.
+pub async fn list_devices(app_state: &AppState) -> Vec<DeviceInfo> {
+    let neighbors = app_state.neighbors.lock().await.clone();
+    let devices = app_state.devices.lock().await;
+
+    let mut node_ids: Vec<NodeId> = devices.keys().chain(neighbors.iter()).copied().collect();
+    node_ids.sort();
+    node_ids.dedup();
+    node_ids
+        .into_iter()
+        .map(|node_id| device_info(node_id, devices.get(&node_id), neighbors.contains(&node_id)))
+        .collect()
+}
+
+fn device_info(node_id: NodeId, record: Option<&DeviceRecord>, is_neighbor: bool) -> DeviceInfo {
+    let recently_seen = record
+        .and_then(|record| record.last_seen.elapsed().ok())
+        .is_some_and(|elapsed| elapsed < PRESENCE_TIMEOUT);
+    DeviceInfo {
+        node_id,
+        name: record.map(|record| record.name.clone()),
+        color: record.and_then(|record| record.color.clone()),
+        online: is_neighbor || recently_seen,
+        last_seen_ms: record.and_then(|record| unix_ms(record.last_seen)),
+    }
+}
+
+pub(crate) fn unix_ms(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+}
@@ -0,0 +1,295 @@
+// src-tauri/src/protocol.rs
+
+//! Messages exchanged on the gossip topic.
+//!
+//! Every message is a JSON [`Envelope`] carrying the protocol version and a
+//! [`Message`] tagged by `type`:
+//!
+//! ```json
+//! { "v": 1, "from": "<node id>", "sent_at_ms": 1700000000000,
+//!   "type": "fileChanged", "relative_path": "docs/a.txt", "ticket": "blob..." }
+//! ```
+//!
+//! Changes must stay compatible in both directions: new fields are optional,
+//! receivers ignore fields they do not know, and messages of an unknown
+//! `type` decode to [`Message::Unknown`] and are skipped. Bump
+//! [`PROTOCOL_VERSION`] only for changes older peers cannot safely ignore.
+//! Messages without `v` are the original, pre-versioning file announcement
+//! and are still accepted.
+
+use std::{
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use iroh::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::FileMetadata;
+
+/// Version this node speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    #[serde(rename = "v")]
+    pub version: u32,
+    pub from: NodeId,
+    /// Unix time in milliseconds. Gossip drops repeated identical messages,
+    /// so this also keeps re-announcements distinct.
+    pub sent_at_ms: u64,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+/// Paths are relative to the sync folder and always use `/` as separator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Message {
+    /// A file was created or modified; its content is behind `ticket`.
+    FileChanged {
+        relative_path: String,
+        ticket: String,
+        /// Size in bytes, so receivers can apply quotas before downloading.
+        /// Not sent by older peers.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+        /// Attributes to apply once the content is written. Not sent by
+        /// older peers, or when the sender syncs none.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<FileMetadata>,
+    },
+    /// The file or folder at `relative_path` is gone, with everything in it.
+    FileDeleted { relative_path: String },
+    /// A file or folder was moved within the sync folder.
+    FileRenamed { old_path: String, new_path: String },
+    /// A folder, possibly empty, was created. Sent before its contents.
+    DirCreated { relative_path: String },
+    /// A link was created at `relative_path`. `target` is relative to the
+    /// folder holding it, in wire form, and stays inside the sync folder.
+    SymlinkChanged {
+        relative_path: String,
+        target: String,
+    },
+    /// Who the sender is, see `presence`.
+    Presence { name: String, color: Option<String> },
+    /// Size of the sender's folder, so peers can tell they have diverged.
+    ManifestSummary { file_count: u64, total_bytes: u64 },
+    /// A message type from a newer peer.
+    #[serde(other)]
+    Unknown,
+}
+
+impl Envelope {
+    pub fn new(from: NodeId, message: Message) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            from,
+            sent_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_millis() as u64)
+                .unwrap_or_default(),
+            message,
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("serde_json::to_vec is infallible")
+    }
+
+    /// Decodes a message from any protocol version. Fails only for bytes
+    /// that are not a FastSync message at all.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_slice(bytes)?;
+        if value.get("v").is_none() {
+            let legacy: LegacyFileAnnouncement = serde_json::from_value(value)?;
+            return Ok(legacy.into());
+        }
+        match serde_json::from_value::<Envelope>(value.clone()) {
+            Ok(envelope) => Ok(envelope),
+            // A newer peer may have changed a known message in a way this
+            // version cannot read; keep the envelope and skip the body.
+            Err(e) => {
+                let header: Header = serde_json::from_value(value).map_err(|_| e)?;
+                if header.version <= PROTOCOL_VERSION {
+                    bail!("Malformed v{} message from {}", header.version, header.from);
+                }
+                Ok(Envelope {
+                    version: header.version,
+                    from: header.from,
+                    sent_at_ms: header.sent_at_ms,
+                    message: Message::Unknown,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    #[serde(rename = "v")]
+    version: u32,
+    from: NodeId,
+    #[serde(default)]
+    sent_at_ms: u64,
+}
+
+/// The only message before versioning: a file announcement.
+#[derive(Deserialize)]
+struct LegacyFileAnnouncement {
+    from: NodeId,
+    relative_path: String,
+    message_content: String,
+}
+
+impl From<LegacyFileAnnouncement> for Envelope {
+    fn from(legacy: LegacyFileAnnouncement) -> Self {
+        Envelope {
+            version: 0,
+            from: legacy.from,
+            sent_at_ms: 0,
+            message: Message::FileChanged {
+                relative_path: legacy.relative_path,
+                ticket: legacy.message_content,
+                size: None,
+                metadata: None,
+            },
+        }
+    }
+}
+
+/// Turns a path relative to the sync folder into its wire form.
+pub fn to_wire_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Parses a wire path, refusing anything that could point outside the sync
+/// folder (absolute paths, `..`, drive prefixes).
+pub fn from_wire_path(wire: &str) -> Option<PathBuf> {
+    if wire.starts_with('/') {
+        return None;
+    }
+    let path = PathBuf::from_iter(wire.split('/').filter(|part| !part.is_empty()));
+    let only_normal = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    (only_normal && path.components().next().is_some()).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_id() -> NodeId {
+        iroh::SecretKey::from_bytes(&[7; 32]).public()
+    }
+
+    #[test]
+    fn reads_legacy_announcement() {
+        let bytes = serde_json::to_vec(&serde_json::json!({
+            "from": node_id(),
+            "relative_path": "docs/a.txt",
+            "message_content": "blobticket",
+        }))
+        .unwrap();
+        let envelope = Envelope::from_bytes(&bytes).unwrap();
+        assert_eq!(envelope.version, 0);
+        assert_eq!(envelope.from, node_id());
+        assert!(matches!(
+            envelope.message,
+            Message::FileChanged { relative_path, ticket, size: None, metadata: None }
+                if relative_path == "docs/a.txt" && ticket == "blobticket"
+        ));
+    }
+
+    #[test]
+    fn round_trips_current_version() {
+        let sent = Envelope::new(
+            node_id(),
+            Message::FileRenamed {
+                old_path: "a".into(),
+                new_path: "b".into(),
+            },
+        );
+        let received = Envelope::from_bytes(&sent.to_vec()).unwrap();
+        assert_eq!(received.version, PROTOCOL_VERSION);
+        assert_eq!(received.sent_at_ms, sent.sent_at_ms);
+        assert!(matches!(
+            received.message,
+            Message::FileRenamed { old_path, new_path } if old_path == "a" && new_path == "b"
+        ));
+    }
+
+    #[test]
+    fn unknown_type_decodes_to_unknown() {
+        let bytes = serde_json::to_vec(&serde_json::json!({
+            "v": PROTOCOL_VERSION,
+            "from": node_id(),
+            "sent_at_ms": 5,
+            "type": "somethingNew",
+            "extra": [1, 2, 3],
+        }))
+        .unwrap();
+        let envelope = Envelope::from_bytes(&bytes).unwrap();
+        assert_eq!(envelope.sent_at_ms, 5);
+        assert!(matches!(envelope.message, Message::Unknown));
+    }
+
+    #[test]
+    fn newer_body_of_known_type_decodes_to_unknown() {
+        let bytes = serde_json::to_vec(&serde_json::json!({
+            "v": PROTOCOL_VERSION + 1,
+            "from": node_id(),
+            "sent_at_ms": 9,
+            "type": "fileDeleted",
+            "relative_path": { "segments": ["a", "b"] },
+        }))
+        .unwrap();
+        let envelope = Envelope::from_bytes(&bytes).unwrap();
+        assert_eq!(envelope.version, PROTOCOL_VERSION + 1);
+        assert_eq!(envelope.from, node_id());
+        assert_eq!(envelope.sent_at_ms, 9);
+        assert!(matches!(envelope.message, Message::Unknown));
+    }
+
+    #[test]
+    fn malformed_current_body_is_an_error() {
+        let bytes = serde_json::to_vec(&serde_json::json!({
+            "v": PROTOCOL_VERSION,
+            "from": node_id(),
+            "type": "fileDeleted",
+        }))
+        .unwrap();
+        assert!(Envelope::from_bytes(&bytes).is_err());
+        assert!(Envelope::from_bytes(b"not json").is_err());
+        assert!(Envelope::from_bytes(b"{\"v\": 1}").is_err());
+    }
+
+    #[test]
+    fn wire_paths_stay_relative() {
+        assert_eq!(
+            from_wire_path("a/b.txt"),
+            Some(PathBuf::from("a").join("b.txt"))
+        );
+        assert_eq!(from_wire_path("a//b/"), Some(PathBuf::from("a").join("b")));
+        assert_eq!(from_wire_path(""), None);
+        assert_eq!(from_wire_path("/"), None);
+        assert_eq!(from_wire_path("/etc/passwd"), None);
+        assert_eq!(from_wire_path(".."), None);
+        assert_eq!(from_wire_path("a/../../b"), None);
+        assert_eq!(from_wire_path("."), None);
+    }
+
+    #[test]
+    fn wire_path_round_trips() {
+        let relative = PathBuf::from("a").join("b c").join("d.txt");
+        let wire = to_wire_path(&relative);
+        assert_eq!(wire, "a/b c/d.txt");
+        assert_eq!(from_wire_path(&wire), Some(relative));
+    }
+}
@@ -0,0 +1,497 @@
+// src-tauri/src/quota.rs
+
+//! Limits on the disk space downloads from peers may take.
+//!
+//! Before a file announced by a peer is downloaded, its size is checked
+//! against [`QuotaSettings`]. A file that would take the blob store or the
+//! sync folder over its quota, or is larger than `auto_download_max_bytes`,
+//! is deferred instead: it is listed by [`deferred_downloads`] until it is
+//! fetched on demand with [`fetch_deferred`], replaced by a newer
+//! announcement, or deleted by a peer. On-demand fetches skip the size
+//! policy but not the quotas.
+//!
+//! Deferrals are reported as `sync://quota-exceeded` with every file held
+//! back by that quota, or as `sync://download-deferred` for a single file
+//! over the size policy.
+//!
+//! An admitted file holds a [`Reservation`] for its size until it is written,
+//! so downloads running side by side cannot overshoot a quota together, and
+//! the download is aborted if the blob turns out larger than announced. Disk
+//! usage is measured at most every [`USAGE_TTL`], with the downloads that
+//! finished since added on top.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use iroh::NodeId;
+use iroh_blobs::ticket::BlobTicket;
+use log::{info, warn};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    iroh_fns::{receive_file, IncomingFile},
+    metadata::FileMetadata,
+    presence::unix_ms,
+    settings::QuotaSettings,
+    state::AppState,
+    storage::disk_usage,
+};
+
+/// How long a disk usage measurement is trusted.
+pub const USAGE_TTL: Duration = Duration::from_secs(60);
+
+/// The quotas in force and what running downloads may still add.
+#[derive(Debug, Default)]
+pub struct Quotas {
+    settings: QuotaSettings,
+    /// Bytes admitted downloads may add to the store and the sync folder.
+    reserved: u64,
+    store_usage: Option<Usage>,
+    folder_usage: Option<(PathBuf, Usage)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    bytes: u64,
+    measured: Instant,
+}
+
+/// Room an admitted download holds under the quotas. Released when dropped,
+/// or counted as used with [`Reservation::settle`] once the file is written.
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    quotas: Arc<Mutex<Quotas>>,
+    /// The announced size, if the peer could tell.
+    size: Option<u64>,
+    reserved: u64,
+}
+
+/// Why a file was not downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeferReason {
+    /// Larger than `auto_download_max_bytes`.
+    TooLarge,
+    /// Would take the blob store over `store_max_bytes`.
+    StoreQuota,
+    /// Would take the sync folder over its limit.
+    FolderQuota,
+}
+
+/// A file a peer announced that was not downloaded.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeferredDownload {
+    pub relative_path: String,
+    /// In bytes, if the peer could tell.
+    pub size: Option<u64>,
+    pub from: NodeId,
+    pub reason: DeferReason,
+    /// Unix time in milliseconds the file was deferred.
+    pub deferred_ms: u64,
+    #[serde(skip)]
+    ticket: String,
+    #[serde(skip)]
+    metadata: Option<FileMetadata>,
+}
+
+/// Payload of `sync://quota-exceeded`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaExceeded {
+    pub reason: DeferReason,
+    pub limit_bytes: u64,
+    pub used_bytes: u64,
+    /// Every file currently held back by this quota.
+    pub files: Vec<DeferredDownload>,
+}
+
+/// Decides whether the file a peer announced may be downloaded now, and
+/// reserves room for it if so. If not, it is deferred and reported.
+/// `on_demand` skips the size policy.
+pub(crate) async fn admit(
+    app_state: &AppState,
+    sync_folder: &Path,
+    file: &IncomingFile,
+    on_demand: bool,
+) -> Result<Option<Reservation>> {
+    let settings = quota_settings(app_state);
+    let relative_path = file.relative_path.as_str();
+    let ticket = file.ticket.as_str();
+    let size = match file.size {
+        Some(size) => Some(size),
+        None if settings != QuotaSettings::default() => remote_size(app_state, ticket).await,
+        None => None,
+    };
+    let Some(size) = size else {
+        // Nothing to check against; downloading is the best guess.
+        forget(app_state, relative_path);
+        return Ok(Some(Reservation::new(app_state, None)));
+    };
+
+    let mut exceeded = None;
+    let reason = if !on_demand
+        && settings
+            .auto_download_max_bytes
+            .is_some_and(|max| size > max)
+    {
+        DeferReason::TooLarge
+    } else {
+        match reserve(app_state, sync_folder, relative_path, size).await? {
+            Ok(reservation) => {
+                forget(app_state, relative_path);
+                return Ok(Some(reservation));
+            }
+            Err(over) => {
+                exceeded = Some(over);
+                over.0
+            }
+        }
+    };
+
+    let deferred = DeferredDownload {
+        relative_path: relative_path.to_string(),
+        size: Some(size),
+        from: file.from,
+        reason,
+        deferred_ms: unix_ms(SystemTime::now()).unwrap_or_default(),
+        ticket: ticket.to_string(),
+        metadata: file.metadata.clone(),
+    };
+    info!(
+        "Deferring download of {} ({} bytes): {:?}",
+        relative_path, size, reason
+    );
+    app_state
+        .deferred
+        .lock()
+        .expect("deferred downloads poisoned")
+        .insert(relative_path.to_string(), deferred.clone());
+
+    match exceeded {
+        Some((reason, limit_bytes, used_bytes)) => {
+            let files = deferred_downloads(app_state)
+                .into_iter()
+                .filter(|deferred| deferred.reason == reason)
+                .collect();
+            let payload = QuotaExceeded {
+                reason,
+                limit_bytes,
+                used_bytes,
+                files,
+            };
+            app_state
+                .events
+                .emit("sync://quota-exceeded", &payload)
+                .ok();
+        }
+        None => {
+            app_state
+                .events
+                .emit("sync://download-deferred", &deferred)
+                .ok();
+        }
+    }
+    Ok(None)
+}
+
+/// Files waiting to be fetched, by path.
+pub fn deferred_downloads(app_state: &AppState) -> Vec<DeferredDownload> {
+    app_state
+        .deferred
+        .lock()
+        .expect("deferred downloads poisoned")
+        .values()
+        .cloned()
+        .collect()
+}
+
+/// Downloads the deferred file at `relative_path`, regardless of the size
+/// policy. Fails if it would still go over a quota.
+pub async fn fetch_deferred(app_state: &AppState, relative_path: &str) -> Result<()> {
+    let deferred = app_state
+        .deferred
+        .lock()
+        .expect("deferred downloads poisoned")
+        .get(relative_path)
+        .cloned()
+        .with_context(|| format!("No deferred download at {:?}", relative_path))?;
+    let file = IncomingFile {
+        from: deferred.from,
+        relative_path: deferred.relative_path,
+        ticket: deferred.ticket,
+        size: deferred.size,
+        metadata: deferred.metadata,
+    };
+    let downloaded = receive_file(app_state, file, true).await?;
+    if !downloaded {
+        bail!("{} would still go over its quota", relative_path);
+    }
+    Ok(())
+}
+
+/// Drops deferred downloads at or under `relative_path`, which a peer
+/// deleted or announced again.
+pub fn forget(app_state: &AppState, relative_path: &str) {
+    app_state
+        .deferred
+        .lock()
+        .expect("deferred downloads poisoned")
+        .retain(|path, _| !is_at_or_under(path, relative_path));
+}
+
+/// Moves deferred downloads at or under `old_path` to `new_path`.
+pub fn rename(app_state: &AppState, old_path: &str, new_path: &str) {
+    let mut deferred = app_state
+        .deferred
+        .lock()
+        .expect("deferred downloads poisoned");
+    let moved: Vec<(String, String)> = deferred
+        .keys()
+        .filter_map(|path| Some((path.clone(), renamed(path, old_path, new_path)?)))
+        .collect();
+    for (path, renamed) in moved {
+        if let Some(mut entry) = deferred.remove(&path) {
+            entry.relative_path = renamed;
+            deferred.insert(entry.relative_path.clone(), entry);
+        }
+    }
+}
+
+/// Replaces the quotas. Files already deferred stay deferred until fetched.
+pub fn apply_quota_settings(app_state: &AppState, settings: QuotaSettings) {
+    info!("Quotas: {:?}", settings);
+    app_state.quotas.lock().expect("quotas poisoned").settings = settings;
+}
+
+/// The quotas in force.
+pub fn quota_settings(app_state: &AppState) -> QuotaSettings {
+    app_state
+        .quotas
+        .lock()
+        .expect("quotas poisoned")
+        .settings
+        .clone()
+}
+
+/// Reserves room for `size` bytes at `relative_path`, or returns the quota
+/// that would be exceeded, with its limit and current use.
+async fn reserve(
+    app_state: &AppState,
+    sync_folder: &Path,
+    relative_path: &str,
+    size: u64,
+) -> Result<std::result::Result<Reservation, (DeferReason, u64, u64)>> {
+    let settings = quota_settings(app_state);
+    // Measured before locking, as it may walk the disk.
+    let store_used = match settings.store_max_bytes {
+        Some(_) => Some(store_usage(app_state).await?),
+        None => None,
+    };
+    let folder_used = match settings.folder_limit(sync_folder) {
+        Some(_) => Some(folder_usage(app_state, sync_folder).await?),
+        None => None,
+    };
+    let replaced = std::fs::metadata(sync_folder.join(relative_path))
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+
+    let mut quotas = app_state.quotas.lock().expect("quotas poisoned");
+    // Downloads land in the store before they are written out.
+    if let (Some(limit), Some(used)) = (quotas.settings.store_max_bytes, store_used) {
+        let used = used + quotas.reserved;
+        if used + size > limit {
+            return Ok(Err((DeferReason::StoreQuota, limit, used)));
+        }
+    }
+    if let (Some(limit), Some(used)) = (quotas.settings.folder_limit(sync_folder), folder_used) {
+        let used = used + quotas.reserved;
+        if used.saturating_sub(replaced) + size > limit {
+            return Ok(Err((DeferReason::FolderQuota, limit, used)));
+        }
+    }
+    quotas.reserved += size;
+    drop(quotas);
+    Ok(Ok(Reservation {
+        quotas: app_state.quotas.clone(),
+        size: Some(size),
+        reserved: size,
+    }))
+}
+
+/// Space the blob store takes, measured at most every [`USAGE_TTL`] and
+/// off the async runtime.
+async fn store_usage(app_state: &AppState) -> Result<u64> {
+    let cached = app_state
+        .quotas
+        .lock()
+        .expect("quotas poisoned")
+        .store_usage;
+    if let Some(usage) = cached.filter(Usage::is_fresh) {
+        return Ok(usage.bytes);
+    }
+    let blobs_root = app_state.blobs_root.clone();
+    let bytes = tokio::task::spawn_blocking(move || disk_usage(&blobs_root)).await??;
+    app_state
+        .quotas
+        .lock()
+        .expect("quotas poisoned")
+        .store_usage = Some(Usage::now(bytes));
+    Ok(bytes)
+}
+
+/// Space the files in `sync_folder` take, measured at most every
+/// [`USAGE_TTL`] and off the async runtime.
+async fn folder_usage(app_state: &AppState, sync_folder: &Path) -> Result<u64> {
+    let cached = app_state
+        .quotas
+        .lock()
+        .expect("quotas poisoned")
+        .folder_usage
+        .clone();
+    if let Some((_, usage)) =
+        cached.filter(|(folder, usage)| folder == sync_folder && usage.is_fresh())
+    {
+        return Ok(usage.bytes);
+    }
+    let folder = sync_folder.to_path_buf();
+    let bytes = tokio::task::spawn_blocking(move || measure_folder(&folder)).await?;
+    app_state
+        .quotas
+        .lock()
+        .expect("quotas poisoned")
+        .folder_usage = Some((sync_folder.to_path_buf(), Usage::now(bytes)));
+    Ok(bytes)
+}
+
+/// Bytes of the files in `sync_folder`. Entries that vanish while walking
+/// are skipped.
+fn measure_folder(sync_folder: &Path) -> u64 {
+    WalkDir::new(sync_folder)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Asks the announcing peer for the size of `ticket`, for peers that do not
+/// announce it.
+async fn remote_size(app_state: &AppState, ticket: &str) -> Option<u64> {
+    let result = async {
+        let ticket = BlobTicket::from_str(ticket)?;
+        let connection = app_state
+            .endpoint
+            .connect(ticket.node_addr().clone(), iroh_blobs::ALPN)
+            .await?;
+        let (size, _) =
+            iroh_blobs::get::request::get_unverified_size(&connection, &ticket.hash()).await?;
+        anyhow::Ok(size)
+    }
+    .await;
+    match result {
+        Ok(size) => Some(size),
+        Err(e) => {
+            warn!("Could not ask the size of an announced file: {:#}", e);
+            None
+        }
+    }
+}
+
+fn is_at_or_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Where `path` is once `old_path` moved to `new_path`. `None` if the move
+/// does not touch it.
+fn renamed(path: &str, old_path: &str, new_path: &str) -> Option<String> {
+    is_at_or_under(path, old_path).then(|| format!("{}{}", new_path, &path[old_path.len()..]))
+}
+
+impl Usage {
+    fn now(bytes: u64) -> Self {
+        Self {
+            bytes,
+            measured: Instant::now(),
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.measured.elapsed() < USAGE_TTL
+    }
+}
+
+impl Reservation {
+    /// Holds nothing, for a download no quota applies to.
+    fn new(app_state: &AppState, size: Option<u64>) -> Self {
+        Self {
+            quotas: app_state.quotas.clone(),
+            size,
+            reserved: 0,
+        }
+    }
+
+    /// The most the download may fetch: the size the peer announced.
+    pub fn max_size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Counts the reserved bytes as used, once the file is written, until
+    /// usage is measured again.
+    pub fn settle(mut self) {
+        let mut quotas = self.quotas.lock().expect("quotas poisoned");
+        let bytes = std::mem::take(&mut self.reserved);
+        quotas.reserved -= bytes;
+        if let Some(usage) = &mut quotas.store_usage {
+            usage.bytes += bytes;
+        }
+        if let Some((_, usage)) = &mut quotas.folder_usage {
+            usage.bytes += bytes;
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.reserved > 0 {
+            self.quotas.lock().expect("quotas poisoned").reserved -= self.reserved;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_or_under_matches_whole_components() {
+        assert!(is_at_or_under("a", "a"));
+        assert!(is_at_or_under("a/b", "a"));
+        assert!(is_at_or_under("a/b/c", "a/b"));
+        assert!(!is_at_or_under("ab", "a"));
+        assert!(!is_at_or_under("ab/c", "a"));
+        assert!(!is_at_or_under("a", "a/b"));
+        assert!(!is_at_or_under("b/a", "a"));
+    }
+
+    #[test]
+    fn rename_moves_the_path_and_what_is_under_it() {
+        assert_eq!(renamed("a", "a", "x").as_deref(), Some("x"));
+        assert_eq!(renamed("a/b/c", "a", "x/y").as_deref(), Some("x/y/b/c"));
+        assert_eq!(renamed("a/b/c", "a/b", "d").as_deref(), Some("d/c"));
+    }
+
+    #[test]
+    fn rename_leaves_paths_sharing_a_prefix() {
+        assert_eq!(renamed("ab", "a", "x"), None);
+        assert_eq!(renamed("ab/c", "a", "x"), None);
+        assert_eq!(renamed("b", "a", "x"), None);
+    }
+}
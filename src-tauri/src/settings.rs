@@ -0,0 +1,525 @@
+// src-tauri/src/settings.rs
+
+//! User-tunable settings of the sync engine.
+//!
+//! The desktop app keeps them as JSON in `store.json`; the daemon reads them
+//! from its TOML config. Missing fields fall back to their defaults, so older
+//! stores and partial configs keep working.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveTime;
+use iroh::{RelayMap, RelayMode, RelayUrl};
+use serde::{Deserialize, Serialize};
+
+use crate::state::DiscoveryService;
+
+/// Store key holding [`NetworkSettings`].
+pub const NETWORK_SETTINGS_KEY: &str = "network-settings";
+/// Store key holding [`DeviceProfile`].
+pub const DEVICE_PROFILE_KEY: &str = "device-profile";
+/// Store key holding [`SnapshotSettings`].
+pub const SNAPSHOT_SETTINGS_KEY: &str = "snapshot-settings";
+/// Store key holding [`VersionSettings`].
+pub const VERSION_SETTINGS_KEY: &str = "version-settings";
+/// Store key holding [`TrashSettings`].
+pub const TRASH_SETTINGS_KEY: &str = "trash-settings";
+/// Store key holding [`QuotaSettings`].
+pub const QUOTA_SETTINGS_KEY: &str = "quota-settings";
+/// Store key holding [`BandwidthSettings`].
+pub const BANDWIDTH_SETTINGS_KEY: &str = "bandwidth-settings";
+/// Store key holding [`MetadataSettings`].
+pub const METADATA_SETTINGS_KEY: &str = "metadata-settings";
+/// Store key holding the [`SymlinkPolicy`].
+pub const SYMLINK_POLICY_KEY: &str = "symlink-policy";
+/// Store key holding whether changes sync as they happen. On when unset.
+pub const AUTO_SYNC_KEY: &str = "auto-sync";
+
+/// How the endpoint reaches and is found by peers. Applied when the endpoint
+/// is built, so changes take effect the next time the node starts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    pub mode: NetworkMode,
+    /// Self-hosted relay used instead of the public n0 relays.
+    pub relay_url: Option<String>,
+    /// Publish and resolve addresses through the n0 DNS server.
+    pub discovery_n0: bool,
+    /// Find peers on the local network.
+    pub discovery_local_network: bool,
+    /// Publish and resolve addresses through the mainline DHT.
+    pub discovery_dht: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NetworkMode {
+    /// Relays and discovery as configured.
+    #[default]
+    Default,
+    /// No relays and no public discovery, for air-gapped networks. Only
+    /// local network discovery is left, if enabled.
+    LanOnly,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            mode: NetworkMode::Default,
+            relay_url: None,
+            discovery_n0: true,
+            discovery_local_network: true,
+            discovery_dht: false,
+        }
+    }
+}
+
+impl NetworkSettings {
+    /// Checks the settings without building anything.
+    pub fn validate(&self) -> Result<()> {
+        self.relay_mode().map(|_| ())
+    }
+
+    pub fn relay_mode(&self) -> Result<RelayMode> {
+        if self.mode == NetworkMode::LanOnly {
+            return Ok(RelayMode::Disabled);
+        }
+        match &self.relay_url {
+            Some(url) => {
+                let url: RelayUrl = url
+                    .parse()
+                    .with_context(|| format!("Invalid relay URL {:?}", url))?;
+                Ok(RelayMode::Custom(RelayMap::from(url)))
+            }
+            None => Ok(RelayMode::Default),
+        }
+    }
+
+    /// Discovery services to enable, after applying the mode.
+    pub fn discovery_services(&self) -> Vec<DiscoveryService> {
+        let public = self.mode != NetworkMode::LanOnly;
+        let mut services = Vec::new();
+        if public && self.discovery_n0 {
+            services.push(DiscoveryService::N0Dns);
+        }
+        if self.discovery_local_network {
+            services.push(DiscoveryService::LocalNetwork);
+        }
+        if public && self.discovery_dht {
+            services.push(DiscoveryService::Dht);
+        }
+        services
+    }
+}
+
+/// How this device is shown to its peers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    pub name: String,
+    /// Avatar colour as `#rrggbb`.
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+const MAX_DEVICE_NAME_LEN: usize = 64;
+
+impl DeviceProfile {
+    pub fn validate(&self) -> Result<()> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            bail!("Device name cannot be empty");
+        }
+        if name.chars().count() > MAX_DEVICE_NAME_LEN {
+            bail!(
+                "Device name is longer than {} characters",
+                MAX_DEVICE_NAME_LEN
+            );
+        }
+        if let Some(color) = &self.color {
+            let hex = color.strip_prefix('#').unwrap_or_default();
+            if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("Avatar colour {:?} is not of the form #rrggbb", color);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Automatic snapshots of the sync folder. Applied as soon as they are set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotSettings {
+    pub schedule: SnapshotSchedule,
+    /// Automatic snapshots to keep; older ones are deleted. Snapshots taken
+    /// by hand are never deleted automatically.
+    pub keep: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SnapshotSchedule {
+    #[default]
+    Off,
+    Hourly,
+    Daily,
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        Self {
+            schedule: SnapshotSchedule::Off,
+            keep: 24,
+        }
+    }
+}
+
+impl SnapshotSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.keep == 0 {
+            bail!("At least one automatic snapshot must be kept");
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotSchedule {
+    /// Time between automatic snapshots, `None` when they are off.
+    pub fn period(self) -> Option<Duration> {
+        match self {
+            SnapshotSchedule::Off => None,
+            SnapshotSchedule::Hourly => Some(Duration::from_secs(60 * 60)),
+            SnapshotSchedule::Daily => Some(Duration::from_secs(24 * 60 * 60)),
+        }
+    }
+}
+
+/// How many earlier versions of each file are kept, see `versions`.
+///
+/// A version is kept while it is one of the newest `keep`, or, with
+/// `keep_days`, younger than that many days. `keep = 0` without `keep_days`
+/// turns history off.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VersionSettings {
+    pub keep: u32,
+    pub keep_days: Option<u32>,
+}
+
+impl Default for VersionSettings {
+    fn default() -> Self {
+        Self {
+            keep: 10,
+            keep_days: None,
+        }
+    }
+}
+
+impl VersionSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.keep_days == Some(0) {
+            bail!("keep_days must be at least 1, or unset");
+        }
+        Ok(())
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.keep > 0 || self.keep_days.is_some()
+    }
+
+    /// Versions younger than this are kept regardless of `keep`.
+    pub fn retention_window(&self) -> Option<Duration> {
+        self.keep_days
+            .map(|days| Duration::from_secs(u64::from(days) * 24 * 60 * 60))
+    }
+}
+
+/// How long files deleted by peers stay in the trash, see `trash`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrashSettings {
+    pub retention_days: u32,
+}
+
+impl Default for TrashSettings {
+    fn default() -> Self {
+        Self { retention_days: 30 }
+    }
+}
+
+impl TrashSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.retention_days == 0 {
+            bail!("Trash retention must be at least one day");
+        }
+        Ok(())
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(u64::from(self.retention_days) * 24 * 60 * 60)
+    }
+}
+
+/// Limits on the disk space downloads from peers may take, see `quota`.
+/// Every limit is in bytes and off when unset.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuotaSettings {
+    /// The blob store, where downloads land before they are written out.
+    pub store_max_bytes: Option<u64>,
+    /// Any sync folder without an entry in `folders`.
+    pub folder_max_bytes: Option<u64>,
+    /// Sync folders by absolute path.
+    pub folders: BTreeMap<PathBuf, u64>,
+    /// Larger files are not downloaded until they are fetched on demand.
+    pub auto_download_max_bytes: Option<u64>,
+}
+
+impl QuotaSettings {
+    pub fn validate(&self) -> Result<()> {
+        let limits = [
+            self.store_max_bytes,
+            self.folder_max_bytes,
+            self.auto_download_max_bytes,
+        ];
+        if limits.contains(&Some(0)) || self.folders.values().any(|limit| *limit == 0) {
+            bail!("Quotas must be at least one byte, or unset");
+        }
+        if let Some(folder) = self.folders.keys().find(|folder| !folder.is_absolute()) {
+            bail!("Quota folder {:?} must be an absolute path", folder);
+        }
+        Ok(())
+    }
+
+    /// Limit for `folder`: its own entry, else `folder_max_bytes`.
+    pub fn folder_limit(&self, folder: &Path) -> Option<u64> {
+        self.folders.get(folder).copied().or(self.folder_max_bytes)
+    }
+}
+
+/// Which file attributes are synced with the content, see `metadata`.
+/// Covers both what is sent with a file and what is applied on receipt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataSettings {
+    /// Modification time.
+    pub mtime: bool,
+    /// Unix permission bits, including the executable bit.
+    pub permissions: bool,
+    /// Extended attributes.
+    pub xattrs: bool,
+}
+
+impl Default for MetadataSettings {
+    fn default() -> Self {
+        Self {
+            mtime: true,
+            permissions: true,
+            xattrs: false,
+        }
+    }
+}
+
+/// What happens to symbolic links in the sync folder, see `symlinks`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SymlinkPolicy {
+    /// Sync links as links, if they point inside the sync folder.
+    Preserve,
+    /// Send what links point to as if it were in the folder. Changes from
+    /// peers still never go through a link. What earlier versions did.
+    #[default]
+    Follow,
+    /// Leave links out.
+    Ignore,
+}
+
+/// Limits on blob transfers, see `bandwidth`. Applied as soon as they are set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthSettings {
+    /// Limits outside every window in `schedule`.
+    pub limits: TransferLimits,
+    /// Time-of-day windows with their own limits, e.g. unlimited at night.
+    /// The first window containing the current local time wins.
+    pub schedule: Vec<ScheduledLimits>,
+}
+
+/// Every limit is off when unset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransferLimits {
+    /// Bytes per second served to peers.
+    pub upload_bytes_per_sec: Option<u64>,
+    /// Bytes per second fetched from peers.
+    pub download_bytes_per_sec: Option<u64>,
+    /// Blob requests served at once.
+    pub max_concurrent_uploads: Option<u32>,
+    /// Blobs fetched at once.
+    pub max_concurrent_downloads: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledLimits {
+    /// Local time the window opens, as `"HH:MM"`.
+    pub start: String,
+    /// Local time the window closes, as `"HH:MM"`. Windows with `end` before
+    /// `start` run past midnight.
+    pub end: String,
+    /// Unlimited when left out.
+    #[serde(default)]
+    pub limits: TransferLimits,
+}
+
+impl BandwidthSettings {
+    pub fn validate(&self) -> Result<()> {
+        for limits in std::iter::once(&self.limits).chain(self.schedule.iter().map(|w| &w.limits)) {
+            limits.validate()?;
+        }
+        for window in &self.schedule {
+            let (start, end) = window.bounds()?;
+            if start == end {
+                bail!("Schedule window {}-{} is empty", window.start, window.end);
+            }
+        }
+        Ok(())
+    }
+
+    /// Limits in force at local time `now`.
+    pub fn limits_at(&self, now: NaiveTime) -> TransferLimits {
+        self.schedule
+            .iter()
+            .find(|window| window.contains(now))
+            .map_or(self.limits, |window| window.limits)
+    }
+}
+
+impl TransferLimits {
+    pub fn validate(&self) -> Result<()> {
+        if self.upload_bytes_per_sec == Some(0) || self.download_bytes_per_sec == Some(0) {
+            bail!("Rate limits must be at least one byte per second, or unset");
+        }
+        if self.max_concurrent_uploads == Some(0) || self.max_concurrent_downloads == Some(0) {
+            bail!("Concurrency limits must be at least 1, or unset");
+        }
+        Ok(())
+    }
+}
+
+impl ScheduledLimits {
+    fn bounds(&self) -> Result<(NaiveTime, NaiveTime)> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .with_context(|| format!("Invalid time {:?}, expected HH:MM", time))
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    fn contains(&self, now: NaiveTime) -> bool {
+        // Validated before use; a malformed window never matches.
+        let Ok((start, end)) = self.bounds() else {
+            return false;
+        };
+        if start <= end {
+            start <= now && now < end
+        } else {
+            now >= start || now < end
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: &str, end: &str) -> ScheduledLimits {
+        ScheduledLimits {
+            start: start.into(),
+            end: end.into(),
+            limits: TransferLimits::default(),
+        }
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn device_profile_limits() {
+        let profile = |name: &str, color: Option<&str>| DeviceProfile {
+            name: name.into(),
+            color: color.map(String::from),
+        };
+        assert!(profile("office-nas", Some("#3b82F6")).validate().is_ok());
+        assert!(profile(&"é".repeat(MAX_DEVICE_NAME_LEN), None)
+            .validate()
+            .is_ok());
+        assert!(profile(&"x".repeat(MAX_DEVICE_NAME_LEN + 1), None)
+            .validate()
+            .is_err());
+        assert!(profile("  ", None).validate().is_err());
+        assert!(profile("nas", Some("3b82f6")).validate().is_err());
+        assert!(profile("nas", Some("#3b82f")).validate().is_err());
+        assert!(profile("nas", Some("#3b82fg")).validate().is_err());
+        assert!(profile("nas", Some("red; background: url(x)"))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let office = window("09:00", "17:30");
+        assert!(!office.contains(at("08:59")));
+        assert!(office.contains(at("09:00")));
+        assert!(office.contains(at("17:29")));
+        assert!(!office.contains(at("17:30")));
+        assert!(!office.contains(at("00:00")));
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let night = window("22:00", "06:00");
+        assert!(!night.contains(at("21:59")));
+        assert!(night.contains(at("22:00")));
+        assert!(night.contains(at("23:59")));
+        assert!(night.contains(at("00:00")));
+        assert!(night.contains(at("05:59")));
+        assert!(!night.contains(at("06:00")));
+        assert!(!night.contains(at("12:00")));
+    }
+
+    #[test]
+    fn malformed_window_never_matches() {
+        assert!(!window("25:00", "06:00").contains(at("01:00")));
+        assert!(!window("late", "06:00").contains(at("01:00")));
+    }
+
+    #[test]
+    fn first_matching_window_wins() {
+        let limited = |rate| TransferLimits {
+            upload_bytes_per_sec: Some(rate),
+            ..TransferLimits::default()
+        };
+        let settings = BandwidthSettings {
+            limits: limited(1),
+            schedule: vec![
+                ScheduledLimits {
+                    limits: limited(2),
+                    ..window("22:00", "06:00")
+                },
+                ScheduledLimits {
+                    limits: limited(3),
+                    ..window("00:00", "12:00")
+                },
+            ],
+        };
+        assert_eq!(settings.limits_at(at("23:00")), limited(2));
+        assert_eq!(settings.limits_at(at("03:00")), limited(2));
+        assert_eq!(settings.limits_at(at("09:00")), limited(3));
+        assert_eq!(settings.limits_at(at("15:00")), limited(1));
+    }
+}
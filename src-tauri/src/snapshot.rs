@@ -0,0 +1,383 @@
+// src-tauri/src/snapshot.rs
+
+//! Point-in-time snapshots of the sync folder.
+//!
+//! A snapshot is the folder imported as a collection (see
+//! [`crate::collection`]) and kept alive by a tag in the blob store:
+//!
+//! ```text
+//! snapshot/<manual|auto>/<unix ms, 13 digits>/<name>
+//! ```
+//!
+//! The tag name is the snapshot's id. Files are stored by hash, so content
+//! shared with earlier snapshots or with the folder itself is stored once and
+//! a snapshot of a mostly unchanged folder costs little more than its file
+//! list.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use futures_util::StreamExt;
+use iroh_blobs::{
+    format::collection::Collection,
+    rpc::client::blobs::WrapOption,
+    util::{SetTagOption, Tag},
+    Hash,
+};
+use log::{info, warn};
+use serde::Serialize;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::{
+    collection::is_selected, iroh_fns::announce_local_paths, presence::unix_ms,
+    settings::SnapshotSettings, state::AppState, storage, versions::restore_blob,
+};
+
+/// Prefix of every snapshot tag.
+pub(crate) const TAG_PREFIX: &str = "snapshot/";
+/// Longest name accepted for a snapshot.
+pub const MAX_SNAPSHOT_NAME_LEN: usize = 64;
+
+/// A snapshot as reported by `list_snapshots` and `snapshots://created`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    /// Tag the snapshot is kept under; pass it back to compare or restore.
+    pub id: String,
+    pub name: String,
+    /// Taken by the schedule in `SnapshotSettings` rather than by hand.
+    pub automatic: bool,
+    /// Unix time in milliseconds.
+    pub created_ms: u64,
+    /// Hash of the collection.
+    pub hash: Hash,
+    pub file_count: usize,
+}
+
+/// Files that differ between two snapshots, by path relative to the folder.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SnapshotDiff {
+    /// Only in the newer snapshot.
+    pub added: Vec<String>,
+    /// Only in the older snapshot.
+    pub removed: Vec<String>,
+    /// In both, with different content.
+    pub modified: Vec<String>,
+}
+
+/// Captures the sync folder as it is now under `name`.
+pub async fn create_snapshot(app_state: &AppState, name: &str) -> Result<SnapshotInfo> {
+    validate_name(name)?;
+    capture(app_state, name, false).await
+}
+
+/// Every snapshot in the store, oldest first.
+pub async fn list_snapshots(app_state: &AppState) -> Result<Vec<SnapshotInfo>> {
+    let blobs_client = app_state.blobs.client();
+    let mut tags = blobs_client.tags().list_prefix(TAG_PREFIX).await?;
+    let mut snapshots = Vec::new();
+    while let Some(tag) = tags.next().await {
+        let tag = tag?;
+        let Some((automatic, created_ms, name)) = parse_tag(&tag.name) else {
+            warn!("Ignoring malformed snapshot tag {}", tag.name);
+            continue;
+        };
+        let collection = blobs_client.get_collection(tag.hash).await?;
+        snapshots.push(SnapshotInfo {
+            id: tag_string(&tag.name),
+            name,
+            automatic,
+            created_ms,
+            hash: tag.hash,
+            file_count: collection.len(),
+        });
+    }
+    snapshots.sort_by_key(|snapshot| snapshot.created_ms);
+    Ok(snapshots)
+}
+
+/// What changed going from snapshot `from` to snapshot `to`.
+pub async fn compare_snapshots(app_state: &AppState, from: &str, to: &str) -> Result<SnapshotDiff> {
+    let from = file_map(&load(app_state, from).await?);
+    let to = file_map(&load(app_state, to).await?);
+
+    let mut diff = SnapshotDiff::default();
+    for (name, hash) in &to {
+        match from.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(old_hash) if old_hash != hash => diff.modified.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = from
+        .keys()
+        .filter(|name| !to.contains_key(*name))
+        .cloned()
+        .collect();
+    Ok(diff)
+}
+
+/// Writes the files of snapshot `id` back into the sync folder and announces
+/// them to peers. With `selection`, only entries named in it, or lying in a
+/// folder named in it, are restored. Files that are not in the snapshot are
+/// left alone, and the ones it overwrites are kept as versions. Returns the
+/// names written.
+pub async fn restore_snapshot(
+    app_state: &AppState,
+    id: &str,
+    selection: Option<&[String]>,
+) -> Result<Vec<String>> {
+    let Some(sync_folder) = app_state.sync_folder.lock().await.clone() else {
+        bail!("No sync folder set");
+    };
+    let collection = load(app_state, id).await?;
+    if let Some(selection) = selection {
+        for wanted in selection {
+            if !collection
+                .iter()
+                .any(|(name, _)| is_selected(name, std::slice::from_ref(wanted)))
+            {
+                bail!("No entry {:?} in snapshot {}", wanted, id);
+            }
+        }
+    }
+
+    let mut restored = Vec::new();
+    let mut paths = Vec::new();
+    for (name, hash) in collection.iter() {
+        if selection.is_some_and(|selection| !is_selected(name, selection)) {
+            continue;
+        }
+        // What is overwritten stays available as a version, so restoring the
+        // wrong snapshot can be undone.
+        let path = restore_blob(app_state, name, *hash)
+            .await
+            .with_context(|| format!("Failed to restore {:?} from snapshot {}", name, id))?;
+        restored.push(name.clone());
+        paths.push(path);
+    }
+    info!(
+        "Restored {} files from snapshot {} into {:?}",
+        restored.len(),
+        id,
+        sync_folder
+    );
+
+    announce_local_paths(app_state, &paths).await?;
+    Ok(restored)
+}
+
+/// Removes the tag of snapshot `id`. Its content stays in the store as long
+/// as anything else refers to it.
+pub async fn delete_snapshot(app_state: &AppState, id: &str) -> Result<()> {
+    load(app_state, id).await?;
+    app_state.blobs.client().tags().delete(id).await?;
+    storage::refresh_history(app_state, id).await?;
+    info!("Deleted snapshot {}", id);
+    Ok(())
+}
+
+/// Replaces the automatic snapshot schedule. The first automatic snapshot is
+/// due one period after the latest existing one, so restarts do not reset it.
+pub async fn apply_snapshot_settings(app_state: &AppState, settings: SnapshotSettings) {
+    let old_task = app_state.snapshot_task.lock().await.take();
+    if let Some(old_task) = old_task {
+        old_task.abort();
+    }
+    let Some(period) = settings.schedule.period() else {
+        info!("Automatic snapshots off");
+        return;
+    };
+
+    let since_latest = match list_snapshots(app_state).await {
+        Ok(snapshots) => snapshots
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.automatic)
+            .and_then(|snapshot| unix_ms(SystemTime::now())?.checked_sub(snapshot.created_ms))
+            .map(Duration::from_millis),
+        Err(e) => {
+            warn!("Failed to list snapshots: {}", e);
+            None
+        }
+    };
+    let first = period.saturating_sub(since_latest.unwrap_or(period));
+    info!(
+        "Automatic snapshots {:?}, next in {:?}, keeping {}",
+        settings.schedule, first, settings.keep
+    );
+
+    let state = app_state.clone();
+    let task = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval_at(Instant::now() + first, period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = take_automatic_snapshot(&state, settings.keep).await {
+                warn!("Automatic snapshot failed: {:#}", e);
+            }
+        }
+    });
+    *app_state.snapshot_task.lock().await = Some(task);
+}
+
+/// Takes a scheduled snapshot, dropping it again if nothing changed since the
+/// latest snapshot, and prunes automatic snapshots beyond `keep`.
+async fn take_automatic_snapshot(app_state: &AppState, keep: u32) -> Result<()> {
+    if app_state.sync_folder.lock().await.is_none() {
+        info!("No sync folder set. Skipping automatic snapshot.");
+        return Ok(());
+    }
+    let latest = list_snapshots(app_state).await?.pop();
+    let snapshot = capture(app_state, "scheduled", true).await?;
+    let tags = app_state.blobs.client().tags();
+    if latest.is_some_and(|latest| latest.hash == snapshot.hash) {
+        info!(
+            "Sync folder unchanged since the latest snapshot. Dropping {}",
+            snapshot.id
+        );
+        tags.delete(&snapshot.id).await?;
+        return storage::refresh_history(app_state, &snapshot.id).await;
+    }
+
+    let automatic: Vec<SnapshotInfo> = list_snapshots(app_state)
+        .await?
+        .into_iter()
+        .filter(|snapshot| snapshot.automatic)
+        .collect();
+    let excess = automatic.len().saturating_sub(keep as usize);
+    for old in &automatic[..excess] {
+        tags.delete(&old.id).await?;
+        storage::refresh_history(app_state, &old.id).await?;
+        info!("Pruned automatic snapshot {}", old.id);
+    }
+    Ok(())
+}
+
+async fn capture(app_state: &AppState, name: &str, automatic: bool) -> Result<SnapshotInfo> {
+    let Some(sync_folder) = app_state.sync_folder.lock().await.clone() else {
+        bail!("No sync folder set");
+    };
+    let created_ms = unix_ms(SystemTime::now()).unwrap_or_default();
+    let id = format!(
+        "{}{}/{:013}/{}",
+        TAG_PREFIX,
+        if automatic { "auto" } else { "manual" },
+        created_ms,
+        name
+    );
+
+    let blobs_client = app_state.blobs.client();
+    // Copied rather than referenced in place: the files keep changing, the
+    // snapshot must not.
+    let outcome = blobs_client
+        .add_from_path(
+            sync_folder.clone(),
+            false,
+            SetTagOption::Named(Tag::from(id.as_str())),
+            WrapOption::NoWrap,
+        )
+        .await?
+        .finish()
+        .await
+        .with_context(|| format!("Failed to snapshot {:?}", sync_folder))?;
+    storage::refresh_history(app_state, &id).await?;
+    let collection = blobs_client.get_collection(outcome.hash).await?;
+
+    let snapshot = SnapshotInfo {
+        id,
+        name: name.to_string(),
+        automatic,
+        created_ms,
+        hash: outcome.hash,
+        file_count: collection.len(),
+    };
+    info!(
+        "Snapshot {} of {:?}: {} files",
+        snapshot.id, sync_folder, snapshot.file_count
+    );
+    app_state.events.emit("snapshots://created", &snapshot).ok();
+    Ok(snapshot)
+}
+
+/// The collection of snapshot `id`.
+async fn load(app_state: &AppState, id: &str) -> Result<Collection> {
+    if !id.starts_with(TAG_PREFIX) {
+        bail!("{:?} is not a snapshot id", id);
+    }
+    let blobs_client = app_state.blobs.client();
+    let tag = blobs_client
+        .tags()
+        .get(id)
+        .await?
+        .with_context(|| format!("No snapshot {}", id))?;
+    blobs_client.get_collection(tag.hash).await
+}
+
+fn file_map(collection: &Collection) -> BTreeMap<String, Hash> {
+    collection
+        .iter()
+        .map(|(name, hash)| (name.clone(), *hash))
+        .collect()
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        bail!("Snapshot name must not be empty");
+    }
+    if name.chars().count() > MAX_SNAPSHOT_NAME_LEN {
+        bail!(
+            "Snapshot name is longer than {} characters",
+            MAX_SNAPSHOT_NAME_LEN
+        );
+    }
+    if name.chars().any(char::is_control) {
+        bail!("Snapshot name must not contain control characters");
+    }
+    Ok(())
+}
+
+/// Splits a snapshot tag into kind, creation time and name.
+fn parse_tag(tag: &Tag) -> Option<(bool, u64, String)> {
+    let tag = std::str::from_utf8(tag.as_ref()).ok()?;
+    let mut parts = tag.strip_prefix(TAG_PREFIX)?.splitn(3, '/');
+    let automatic = match parts.next()? {
+        "auto" => true,
+        "manual" => false,
+        _ => return None,
+    };
+    let created_ms = parts.next()?.parse().ok()?;
+    let name = parts.next()?.to_string();
+    Some((automatic, created_ms, name))
+}
+
+fn tag_string(tag: &Tag) -> String {
+    String::from_utf8_lossy(tag.as_ref()).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_splits_kind_time_and_name() {
+        assert_eq!(
+            parse_tag(&Tag::from("snapshot/manual/1700000000000/before/upgrade")),
+            Some((false, 1_700_000_000_000, "before/upgrade".to_string()))
+        );
+        assert_eq!(
+            parse_tag(&Tag::from("snapshot/auto/0000000000001/hourly")),
+            Some((true, 1, "hourly".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_other_tags() {
+        assert_eq!(parse_tag(&Tag::from("trash/1/n/x")), None);
+        assert_eq!(parse_tag(&Tag::from("snapshot/other/1/x")), None);
+        assert_eq!(parse_tag(&Tag::from("snapshot/manual/x/y")), None);
+        assert_eq!(parse_tag(&Tag::from("snapshot/manual/1")), None);
+    }
+}
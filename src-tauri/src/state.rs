@@ -1,34 +1,225 @@
 // src-tauri/src/state.rs
-use iroh::endpoint::Endpoint;
 use iroh::protocol::Router;
+use iroh::{endpoint::Endpoint, NodeId};
 use iroh_blobs::net_protocol::Blobs;
 use iroh_gossip::{
     net::{Gossip, GossipSender},
     proto::TopicId,
 };
-use std::{path::PathBuf, sync::Arc};
-use tokio::{sync::Mutex, task::JoinHandle};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64},
+        Arc,
+    },
+};
+use tokio::{
+    sync::{broadcast, Mutex},
+    task::JoinHandle,
+};
+use tokio_util::task::TaskTracker;
+
+use crate::{
+    bandwidth::Bandwidth,
+    events::{EngineEvent, EventSink},
+    fs_watcher::WatcherHandle,
+    pause::PauseState,
+    presence::DeviceRecord,
+    quota::{DeferredDownload, Quotas},
+    settings::{DeviceProfile, MetadataSettings, SymlinkPolicy, TrashSettings, VersionSettings},
+    storage::HistoryIndex,
+};
 
 /// Holds the core state based on the setup function provided.
 /// Stores the Endpoint and the protocol handlers needed for later interaction.
-#[derive(Default)] // Handlers might not implement Debug easily
+///
+/// Every field is a cheap handle, so the state is cloned into the tasks that
+/// need it. Nothing here depends on Tauri; the desktop app and the headless
+/// daemon share it.
+#[derive(Clone)] // Handlers might not implement Debug easily
 pub struct AppState {
     // --- Core Iroh Components ---
     /// The network endpoint managing connections and identity.
-    pub endpoint: Option<Endpoint>,
+    pub endpoint: Endpoint,
 
     // --- Protocol Handlers ---
     /// Handler for the iroh-blobs protocol.
-    pub blobs: Option<Blobs<iroh_blobs::store::fs::Store>>,
+    pub blobs: Blobs<iroh_blobs::store::fs::Store>,
+    /// Where the blob store keeps its files.
+    pub blobs_root: PathBuf,
+    /// Unix time in milliseconds the blob store's garbage collector last
+    /// finished a round, 0 before the first. See `storage`.
+    pub last_gc_ms: Arc<AtomicU64>,
 
     /// Handler for the iroh-gossip protocol.
-    pub gossip: Option<Gossip>,
+    pub gossip: Gossip,
     pub gossip_topic: Arc<Mutex<Option<TopicId>>>,
     pub gossip_sender: Arc<Mutex<Option<GossipSender>>>,
+    /// Direct gossip neighbors currently up on the topic.
+    pub neighbors: Arc<Mutex<BTreeSet<NodeId>>>,
+    /// How this node presents itself to peers.
+    pub device: Arc<Mutex<DeviceProfile>>,
+    /// Peers that have announced themselves on the topic.
+    pub devices: Arc<Mutex<BTreeMap<NodeId, DeviceRecord>>>,
     // --- Active Handles ---
+    /// Discovery services the endpoint was built with.
+    pub discovery: Vec<DiscoveryService>,
     /// Handle for the main Iroh Router task. Essential for shutdown.
-    pub router: Option<Router>,
+    pub router: Router,
+    /// Task supervising `subscribe_loop` for the joined topic.
+    pub gossip_receiver_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// Health of the gossip receiver, see [`GossipStats`].
+    pub gossip_stats: Arc<std::sync::Mutex<GossipStats>>,
+    /// In-flight gossip broadcasts and blob downloads, drained on shutdown.
+    pub tasks: TaskTracker,
+    /// Set once shutdown has started so it only runs once.
+    pub shutting_down: Arc<AtomicBool>,
+    /// Local control API server, if one was started.
+    pub control_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// Task taking automatic snapshots, see `snapshot::apply_snapshot_settings`.
+    pub snapshot_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+
+    // --- Sync Folder ---
+    /// Folder currently being synced. `None` once the folder has been removed.
+    pub sync_folder: Arc<Mutex<Option<PathBuf>>>,
+    /// Watcher for `sync_folder`. Stopped and replaced when the folder changes.
+    pub sync_task_handle: Arc<Mutex<Option<WatcherHandle>>>,
+    /// How many earlier versions of each file to keep, see `versions`.
+    pub version_settings: Arc<std::sync::Mutex<VersionSettings>>,
+    /// How long files deleted by peers are kept, see `trash`.
+    pub trash_settings: Arc<std::sync::Mutex<TrashSettings>>,
+    /// Transfer limits, shared with the blobs protocol handler.
+    pub bandwidth: Arc<Bandwidth>,
+    /// Limits on what downloads may take, see `quota`.
+    pub quotas: Arc<std::sync::Mutex<Quotas>>,
+    /// What versions, snapshots and the trash refer to, see `storage`.
+    pub history: Arc<Mutex<HistoryIndex>>,
+    /// Which file attributes are synced, see `metadata`.
+    pub metadata_settings: Arc<std::sync::Mutex<MetadataSettings>>,
+    /// What happens to links in the sync folder, see `symlinks`.
+    pub symlink_policy: Arc<std::sync::Mutex<SymlinkPolicy>>,
+    /// Files announced by peers but not downloaded, by relative path.
+    pub deferred: Arc<std::sync::Mutex<BTreeMap<String, DeferredDownload>>>,
+    /// Edited files waiting to be imported again, see
+    /// `storage::schedule_rehash`.
+    pub rehash_pending: Arc<std::sync::Mutex<BTreeSet<PathBuf>>>,
+    /// What is paused and the changes waiting for it, see `pause`.
+    pub pause: Arc<std::sync::Mutex<PauseState>>,
+    /// Where the node key, topic and blob store are kept.
+    pub data_root: PathBuf,
+
+    /// Where the node is in its lifecycle, see [`NodeStatus`].
+    pub lifecycle: NodeLifecycle,
+
+    // --- Frontend ---
+    /// Where `fs-event`, `gossip://*` and other events are delivered.
+    pub events: Arc<dyn EventSink>,
+    /// Copy of every event in `events`, for local subscribers.
+    pub event_feed: broadcast::Sender<EngineEvent>,
+}
+
+/// Lifecycle of the node, reported through `node://status` events.
+///
+/// Serializes as `{ "state": "failed", "reason": "..." }`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", content = "reason", rename_all = "camelCase")]
+pub enum NodeStatus {
+    /// Keys, blob store and endpoint are being set up.
+    Starting,
+    /// The node is up and can share, fetch and sync.
+    Ready,
+    /// The node is up but part of it is not working, e.g. the sync folder
+    /// cannot be watched.
+    Degraded(String),
+    /// Setup failed; the node cannot be used until it is started again.
+    Failed(String),
+    /// `shutdown_node` is running. Terminal.
+    ShuttingDown,
+}
+
+/// Shared, observable [`NodeStatus`]. Every change is emitted as a
+/// `node://status` event on the sink it was created with, or the one set by
+/// [`NodeLifecycle::emit_to`] once the node's event feed exists.
+#[derive(Clone)]
+pub struct NodeLifecycle {
+    current: Arc<std::sync::Mutex<NodeStatus>>,
+    events: Arc<std::sync::Mutex<Arc<dyn EventSink>>>,
+}
+
+impl NodeLifecycle {
+    pub fn new(events: Arc<dyn EventSink>) -> Self {
+        Self {
+            current: Arc::new(std::sync::Mutex::new(NodeStatus::Starting)),
+            events: Arc::new(std::sync::Mutex::new(events)),
+        }
+    }
+
+    /// Emits later changes on `events`, for every clone of this lifecycle.
+    pub fn emit_to(&self, events: Arc<dyn EventSink>) {
+        *self.events.lock().expect("node status sink poisoned") = events;
+    }
+
+    pub fn status(&self) -> NodeStatus {
+        self.current.lock().expect("node status poisoned").clone()
+    }
+
+    /// Moves to `status` and emits it. Nothing leaves `ShuttingDown`.
+    pub fn set(&self, status: NodeStatus) {
+        {
+            let mut current = self.current.lock().expect("node status poisoned");
+            if *current == NodeStatus::ShuttingDown || *current == status {
+                return;
+            }
+            *current = status.clone();
+        }
+        match &status {
+            NodeStatus::Degraded(reason) | NodeStatus::Failed(reason) => {
+                warn!("Node status: {:?} ({})", status, reason)
+            }
+            _ => info!("Node status: {:?}", status),
+        }
+        let events = self
+            .events
+            .lock()
+            .expect("node status sink poisoned")
+            .clone();
+        if let Err(e) = events.emit("node://status", &status) {
+            warn!("Failed to emit node://status: {}", e);
+        }
+    }
+
+    /// Returns to `Ready` if the node is currently `Degraded`.
+    pub fn recover(&self) {
+        if matches!(self.status(), NodeStatus::Degraded(_)) {
+            self.set(NodeStatus::Ready);
+        }
+    }
+}
+
+/// Counters kept by the gossip receiver, reported in node info and the
+/// control API status.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GossipStats {
+    /// Messages received on the topic, including ones that were dropped.
+    pub received: u64,
+    /// Messages dropped because they could not be decoded.
+    pub decode_failures: u64,
+    pub last_decode_error: Option<String>,
+    /// Times the topic was subscribed again after the receiver stopped.
+    pub restarts: u64,
+}
 
-    pub sync_folder: PathBuf,
-    pub sync_task_handle: Option<JoinHandle<()>>,
+/// A way for peers to find this node's addresses from its node id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiscoveryService {
+    /// Publishing to and resolving from the n0 DNS server.
+    N0Dns,
+    /// mDNS-style discovery on the local network.
+    LocalNetwork,
+    /// Publishing to and resolving from the mainline DHT.
+    Dht,
 }
@@ -0,0 +1,693 @@
+// src-tauri/src/storage.rs
+
+//! What the blob store keeps, and for how long.
+//!
+//! Content stays in the store only while a tag refers to it. Every tag this
+//! node sets has a prefix naming why the content is kept:
+//!
+//! | Prefix      | Kept while                                                 |
+//! |-------------|------------------------------------------------------------|
+//! | `file/`     | it is the current content of that path in the sync folder |
+//! | `share/`    | nothing else is shared from the same local path            |
+//! | `download/` | a download is being exported; released right after         |
+//! | `version/`  | see [`crate::versions`]                                    |
+//! | `snapshot/` | see [`crate::snapshot`]                                    |
+//! | `trash/`    | see [`crate::trash`]                                       |
+//!
+//! A new version of a path replaces its tag, so the old content is released
+//! unless a version, snapshot or trash entry still refers to it. Released
+//! content is deleted by the store's garbage collector, which runs every
+//! [`GC_PERIOD`]. `auto-…` tags were set before this policy existed.
+//!
+//! Files in the sync folder are not copied into the store: shared files are
+//! referenced where they are and downloads are moved to their destination,
+//! see [`export_blob`]. An edited file is imported again, see
+//! [`schedule_rehash`], and content that was only kept in that file is
+//! dropped rather than served with the wrong bytes. Content versions,
+//! snapshots or the trash refer to is always copied; [`HistoryIndex`] tells
+//! which that is without reading every such tag per download.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use futures_util::StreamExt;
+use iroh::NodeAddr;
+use iroh_blobs::{
+    get::{
+        fsm::{self, ConnectedNext, EndBlobNext},
+        Stats,
+    },
+    hashseq::HashSeq,
+    net_protocol::Blobs,
+    protocol::GetRequest,
+    rpc::client::{
+        blobs::{DownloadOutcome, WrapOption},
+        tags::TagInfo,
+    },
+    store::{
+        fs::Store, BaoBatchWriter as _, EntryStatus, ExportFormat, ExportMode, GcConfig, Map as _,
+        MapEntry as _, MapEntryMut as _, MapMut as _, Store as _,
+    },
+    util::{SetTagOption, Tag},
+    BlobFormat, Hash, HashAndFormat,
+};
+use log::{info, warn};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    bandwidth::{Direction, ThrottledWriter},
+    iroh_fns,
+    presence::unix_ms,
+    protocol::{from_wire_path, to_wire_path},
+    snapshot,
+    state::AppState,
+    trash, versions,
+};
+
+/// How often the garbage collector deletes content no tag refers to.
+pub const GC_PERIOD: Duration = Duration::from_secs(5 * 60);
+/// How long a file must stop changing before it is imported again.
+const REHASH_DELAY: Duration = Duration::from_secs(2);
+
+const FILE_TAG_PREFIX: &str = "file/";
+const SHARE_TAG_PREFIX: &str = "share/";
+const DOWNLOAD_TAG_PREFIX: &str = "download/";
+/// Tags iroh-blobs names itself, set before tags had a purpose here.
+const LEGACY_TAG_PREFIX: &str = "auto-";
+/// Tags whose content must not live only in a file the user can edit.
+const HISTORY_TAG_PREFIXES: [&str; 3] = [
+    versions::TAG_PREFIX,
+    snapshot::TAG_PREFIX,
+    trash::TAG_PREFIX,
+];
+
+/// What the version, snapshot and trash tags refer to. Built from the tags
+/// the first time an export needs it; whoever sets or deletes such a tag
+/// calls [`refresh_history`] afterwards.
+#[derive(Debug, Default)]
+pub struct HistoryIndex {
+    /// Hashes each tag keeps, see [`references`]. `None` until built.
+    tags: Option<BTreeMap<Tag, Vec<Hash>>>,
+    /// How many tags keep each hash.
+    counts: HashMap<Hash, usize>,
+}
+
+/// Reported by `storage_stats`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageStats {
+    /// Space the blob store takes on disk. Files shared in place are not
+    /// counted, they stay where they are.
+    pub store_bytes: u64,
+    pub blob_count: u64,
+    /// Size of all complete blobs, including ones shared in place.
+    pub blob_bytes: u64,
+    /// Bytes every tag and folder snapshot refers to, counted once per
+    /// reference.
+    pub referenced_bytes: u64,
+    /// `referenced_bytes` minus the size of the distinct blobs behind them:
+    /// what storing identical content once saves.
+    pub dedup_savings_bytes: u64,
+    /// Size of blobs nothing refers to any more. Deleted by the next
+    /// garbage collection.
+    pub reclaimable_bytes: u64,
+    /// Tags by prefix, e.g. `file` or `snapshot`.
+    pub tags: BTreeMap<String, u64>,
+    /// Unix time in milliseconds the garbage collector last finished.
+    pub last_gc_ms: Option<u64>,
+}
+
+/// Reported by `collect_garbage`.
+#[derive(Debug, Clone, Serialize)]
+pub struct GcReport {
+    pub released_tags: u64,
+    /// Deleted by the next garbage collection, within `next_gc_within_secs`.
+    pub reclaimable_bytes: u64,
+    pub next_gc_within_secs: u64,
+}
+
+/// Tag holding the current content of `relative_path` in the sync folder.
+pub fn file_tag(relative_path: &str) -> Tag {
+    Tag::from(format!("{}{}", FILE_TAG_PREFIX, relative_path))
+}
+
+/// What the tag of `relative_path` currently holds.
+pub async fn file_content(app_state: &AppState, relative_path: &str) -> Result<Option<Hash>> {
+    let tag = app_state
+        .blobs
+        .client()
+        .tags()
+        .get(file_tag(relative_path))
+        .await?;
+    Ok(tag.map(|tag| tag.hash))
+}
+
+/// Tag holding what was last shared from `path`.
+pub fn share_tag(path: &Path) -> Tag {
+    Tag::from(format!("{}{}", SHARE_TAG_PREFIX, path.to_string_lossy()))
+}
+
+/// Downloads `hash` from `node_addr` and tags it `tag`, or with a download
+/// tag the caller must [`release_download`] when `tag` is `None`. Content
+/// already in the store is only tagged. Aborts if the blob is larger than
+/// `max_size`.
+pub async fn download_blob(
+    app_state: &AppState,
+    hash: Hash,
+    node_addr: NodeAddr,
+    tag: Option<Tag>,
+    max_size: Option<u64>,
+) -> Result<DownloadOutcome> {
+    let tag = tag.unwrap_or_else(|| download_tag(hash));
+    let store = app_state.blobs.store();
+    // Kept from the garbage collector until it is tagged.
+    let _temp_tag = store.temp_tag(HashAndFormat::raw(hash));
+    let outcome = match store.entry_status(&hash).await? {
+        EntryStatus::Complete => DownloadOutcome {
+            local_size: store
+                .get(&hash)
+                .await?
+                .map_or(0, |entry| entry.size().value()),
+            downloaded_size: 0,
+            stats: Stats::default(),
+        },
+        EntryStatus::Partial | EntryStatus::NotFound => {
+            fetch_blob(app_state, hash, node_addr, max_size).await?
+        }
+    };
+    app_state
+        .blobs
+        .client()
+        .tags()
+        .set(tag, HashAndFormat::raw(hash))
+        .await?;
+    Ok(outcome)
+}
+
+/// Fetches all of `hash` from `node_addr` into the store, in a download slot
+/// and no faster than the download rate in `bandwidth`.
+async fn fetch_blob(
+    app_state: &AppState,
+    hash: Hash,
+    node_addr: NodeAddr,
+    max_size: Option<u64>,
+) -> Result<DownloadOutcome> {
+    let _slot = app_state.bandwidth.acquire(Direction::Download).await;
+    let store = app_state.blobs.store().clone();
+    let endpoint = app_state.endpoint.clone();
+    let bandwidth = app_state.bandwidth.clone();
+    // The store's writers are not `Send`, so this runs on the store's pool.
+    let fetch = move || async move {
+        let connection = endpoint.connect(node_addr, iroh_blobs::ALPN).await?;
+        let connected = fsm::start(connection, GetRequest::single(hash))
+            .next()
+            .await?;
+        let ConnectedNext::StartRoot(root) = connected.next().await? else {
+            bail!("Unexpected response to the request for {}", hash);
+        };
+        let (content, size) = root.next().next().await?;
+        // The size is checked against the hash as the content arrives.
+        if let Some(max_size) = max_size.filter(|max_size| size > *max_size) {
+            bail!(
+                "{} is {} bytes, more than the {} announced",
+                hash,
+                size,
+                max_size
+            );
+        }
+        let entry = store.get_or_create(hash, size).await?;
+        let mut writer = ThrottledWriter::new(entry.batch_writer().await?, bandwidth);
+        let end = content.write_all_batch(&mut writer).await?;
+        writer.sync().await?;
+        drop(writer);
+        store.insert_complete(entry).await?;
+        let EndBlobNext::Closing(closing) = end.next() else {
+            bail!("Unexpected response to the request for {}", hash);
+        };
+        let stats = closing.next().await?;
+        Ok(DownloadOutcome {
+            local_size: 0,
+            downloaded_size: size,
+            stats,
+        })
+    };
+    app_state.blobs.rt().try_spawn(fetch)?.await?
+}
+
+/// How [`export_blob`] wrote a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exported {
+    /// The store's copy was moved there; the file is now the only copy and
+    /// changes when it is edited.
+    Referenced,
+    Copied,
+}
+
+/// Writes `hash` to `dest`. Unless versions, snapshots or the trash keep the
+/// content, the store's copy is moved there and referenced, so it is not
+/// stored twice. Falls back to copying where moving is not possible.
+pub async fn export_blob(app_state: &AppState, hash: Hash, dest: PathBuf) -> Result<Exported> {
+    let blobs_client = app_state.blobs.client();
+    if !is_history(app_state, hash).await? {
+        let referenced = blobs_client
+            .export(
+                hash,
+                dest.clone(),
+                ExportFormat::Blob,
+                ExportMode::TryReference,
+            )
+            .await?
+            .finish()
+            .await;
+        match referenced {
+            Ok(_) => return Ok(Exported::Referenced),
+            Err(e) => info!("Copying {} to {:?} instead of moving it: {}", hash, dest, e),
+        }
+    }
+    blobs_client
+        .export(hash, dest, ExportFormat::Blob, ExportMode::Copy)
+        .await?
+        .finish()
+        .await?;
+    Ok(Exported::Copied)
+}
+
+/// Deletes `hash` from the store if what it holds no longer matches it,
+/// which happens when the only copy was a file that has since changed.
+pub async fn drop_if_stale(blobs: &Blobs<Store>, hash: Hash) -> Result<()> {
+    if is_intact(blobs, hash).await {
+        return Ok(());
+    }
+    warn!(
+        "Content {} changed on disk. Dropping it from the store",
+        hash
+    );
+    blobs.store().delete(vec![hash]).await?;
+    Ok(())
+}
+
+/// Imports `path` again once it has stopped changing for a moment, then
+/// announces the edit if the content changed. Edits arrive as bursts of
+/// events, each of which would otherwise hash the whole file.
+pub fn schedule_rehash(app_state: &AppState, path: PathBuf) {
+    if !app_state
+        .rehash_pending
+        .lock()
+        .expect("rehash queue poisoned")
+        .insert(path.clone())
+    {
+        return;
+    }
+    let app_state = app_state.clone();
+    let tasks = app_state.tasks.clone();
+    tokio::spawn(tasks.track_future(async move {
+        tokio::time::sleep(REHASH_DELAY).await;
+        app_state
+            .rehash_pending
+            .lock()
+            .expect("rehash queue poisoned")
+            .remove(&path);
+        match rehash_file(&app_state, &path).await {
+            Ok(Some(hash)) => {
+                if let Err(e) = iroh_fns::announce_edit(&app_state, &path, hash).await {
+                    warn!("Failed to announce the edit of {:?}: {:#}", path, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to re-hash {:?}: {:#}", path, e),
+        }
+    }));
+}
+
+/// Releases the download tag of `hash` once its content has been exported.
+pub async fn release_download(blobs: &Blobs<Store>, hash: Hash) -> Result<()> {
+    blobs.client().tags().delete(download_tag(hash)).await
+}
+
+/// Releases the content of `relative_path` and, for a folder, of everything
+/// in it.
+pub async fn release_file_tags(app_state: &AppState, relative_path: &str) -> Result<()> {
+    let tags = app_state.blobs.client().tags();
+    tags.delete(file_tag(relative_path)).await?;
+    tags.delete_prefix(format!("{}{}/", FILE_TAG_PREFIX, relative_path))
+        .await
+}
+
+/// Moves the tags of `old_path` and everything in it to `new_path`.
+pub async fn rename_file_tags(app_state: &AppState, old_path: &str, new_path: &str) -> Result<()> {
+    let tags = app_state.blobs.client().tags();
+    let old_prefix = format!("{}{}", FILE_TAG_PREFIX, old_path);
+    let mut listed = tags.list_prefix(old_prefix.as_str()).await?;
+    let mut renames = Vec::new();
+    while let Some(tag) = listed.next().await {
+        let name = tag?.name;
+        let rest = &name.as_ref()[old_prefix.len()..];
+        // `a` must not take `ab` along.
+        if rest.is_empty() || rest.starts_with(b"/") {
+            let mut new_name = format!("{}{}", FILE_TAG_PREFIX, new_path).into_bytes();
+            new_name.extend_from_slice(rest);
+            renames.push((name, new_name));
+        }
+    }
+    for (old_name, new_name) in renames {
+        tags.rename(old_name, new_name).await?;
+    }
+    Ok(())
+}
+
+/// Starts the store's garbage collector. Each finished round is recorded for
+/// [`storage_stats`].
+pub fn start_gc(app_state: &AppState) -> Result<()> {
+    let last_gc_ms = app_state.last_gc_ms.clone();
+    app_state.blobs.start_gc(GcConfig {
+        period: GC_PERIOD,
+        done_callback: Some(Box::new(move || {
+            let now = unix_ms(SystemTime::now()).unwrap_or_default();
+            last_gc_ms.store(now, Ordering::SeqCst);
+        })),
+    })
+}
+
+/// Releases tags that no longer keep anything useful: `file/` tags of paths
+/// gone from the sync folder, leftover `download/` tags, expired trash and,
+/// with `release_legacy`, the `auto-…` tags from before this policy. Tickets
+/// shared through a legacy tag stop working once it is released.
+pub async fn collect_garbage(app_state: &AppState, release_legacy: bool) -> Result<GcReport> {
+    trash::purge_expired(app_state).await?;
+    let sync_folder = app_state.sync_folder.lock().await.clone();
+
+    let tags = app_state.blobs.client().tags();
+    let mut listed = tags.list().await?;
+    let mut stale = Vec::new();
+    while let Some(tag) = listed.next().await {
+        let name = tag?.name;
+        let Ok(text) = std::str::from_utf8(name.as_ref()) else {
+            continue;
+        };
+        let release = if let Some(relative_path) = text.strip_prefix(FILE_TAG_PREFIX) {
+            // Without a folder there is nothing to compare with.
+            sync_folder.as_ref().is_some_and(|sync_folder| {
+                from_wire_path(relative_path)
+                    .is_none_or(|relative| !sync_folder.join(relative).is_file())
+            })
+        } else {
+            text.starts_with(DOWNLOAD_TAG_PREFIX)
+                || (release_legacy && text.starts_with(LEGACY_TAG_PREFIX))
+        };
+        if release {
+            stale.push(name);
+        }
+    }
+    for name in &stale {
+        tags.delete(name.clone()).await?;
+    }
+    info!("Released {} stale tags", stale.len());
+
+    let stats = storage_stats(app_state).await?;
+    Ok(GcReport {
+        released_tags: stale.len() as u64,
+        reclaimable_bytes: stats.reclaimable_bytes,
+        next_gc_within_secs: GC_PERIOD.as_secs(),
+    })
+}
+
+/// Sizes of the blob store, what deduplication saves and what garbage
+/// collection would reclaim.
+pub async fn storage_stats(app_state: &AppState) -> Result<StorageStats> {
+    let blobs_client = app_state.blobs.client();
+    let mut stats = StorageStats::default();
+
+    let mut sizes = BTreeMap::new();
+    let mut listed = blobs_client.list().await?;
+    while let Some(blob) = listed.next().await {
+        let blob = blob?;
+        sizes.insert(blob.hash, blob.size);
+    }
+    stats.blob_count = sizes.len() as u64;
+    stats.blob_bytes = sizes.values().sum();
+
+    // Mark what tags refer to, as the garbage collector does.
+    let mut live = BTreeSet::new();
+    let mut tags = blobs_client.tags().list().await?;
+    while let Some(tag) = tags.next().await {
+        let tag = tag?;
+        *stats.tags.entry(tag_kind(tag.name.as_ref())).or_default() += 1;
+        for hash in references(&app_state.blobs, &tag).await {
+            stats.referenced_bytes += sizes.get(&hash).copied().unwrap_or_default();
+            live.insert(hash);
+        }
+    }
+    let live_bytes: u64 = live.iter().filter_map(|hash| sizes.get(hash)).sum();
+    stats.dedup_savings_bytes = stats.referenced_bytes.saturating_sub(live_bytes);
+    stats.reclaimable_bytes = sizes
+        .iter()
+        .filter(|(hash, _)| !live.contains(*hash))
+        .map(|(_, size)| size)
+        .sum();
+
+    stats.store_bytes = disk_usage(&app_state.blobs_root)?;
+    stats.last_gc_ms = Some(app_state.last_gc_ms.load(Ordering::SeqCst)).filter(|ms| *ms > 0);
+    Ok(stats)
+}
+
+/// Imports an edited sync-folder file under its tag. If the content changed,
+/// the old content is dropped when this file was its only copy and the new
+/// hash is returned. Writing a downloaded file leaves it unchanged, so
+/// downloads are not announced back.
+async fn rehash_file(app_state: &AppState, path: &Path) -> Result<Option<Hash>> {
+    let Some(sync_folder) = app_state.sync_folder.lock().await.clone() else {
+        return Ok(None);
+    };
+    let Ok(relative) = path.strip_prefix(&sync_folder) else {
+        return Ok(None);
+    };
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let relative_path = to_wire_path(relative);
+    // Files never announced are not referenced from the store.
+    let Some(previous) = file_content(app_state, &relative_path).await? else {
+        return Ok(None);
+    };
+    let outcome = app_state
+        .blobs
+        .client()
+        .add_from_path(
+            path.to_path_buf(),
+            true,
+            SetTagOption::Named(file_tag(&relative_path)),
+            WrapOption::NoWrap,
+        )
+        .await?
+        .finish()
+        .await?;
+    if outcome.hash == previous {
+        return Ok(None);
+    }
+    info!("Re-hashed {} after it was edited", relative_path);
+    drop_if_stale(&app_state.blobs, previous).await?;
+    Ok(Some(outcome.hash))
+}
+
+/// Whether versions, snapshots or the trash refer to `hash`.
+async fn is_history(app_state: &AppState, hash: Hash) -> Result<bool> {
+    let mut index = app_state.history.lock().await;
+    if index.tags.is_none() {
+        let mut tags = BTreeMap::new();
+        for prefix in HISTORY_TAG_PREFIXES {
+            tags.extend(history_tags(&app_state.blobs, prefix).await?);
+        }
+        index.tags = Some(BTreeMap::new());
+        for (name, hashes) in tags {
+            index.insert(name, hashes);
+        }
+    }
+    Ok(index.counts.contains_key(&hash))
+}
+
+/// Updates [`HistoryIndex`] after tags starting with `prefix` were set or
+/// deleted.
+pub async fn refresh_history(app_state: &AppState, prefix: &str) -> Result<()> {
+    let mut index = app_state.history.lock().await;
+    if index.tags.is_none() {
+        return Ok(());
+    }
+    let tags = match history_tags(&app_state.blobs, prefix).await {
+        Ok(tags) => tags,
+        Err(e) => {
+            // Read again from scratch when next needed.
+            *index = HistoryIndex::default();
+            return Err(e);
+        }
+    };
+    index.remove_prefix(prefix.as_bytes());
+    for (name, hashes) in tags {
+        index.insert(name, hashes);
+    }
+    Ok(())
+}
+
+/// Tags starting with `prefix` and the hashes they keep.
+async fn history_tags(blobs: &Blobs<Store>, prefix: &str) -> Result<Vec<(Tag, Vec<Hash>)>> {
+    let mut tags = blobs.client().tags().list_prefix(prefix).await?;
+    let mut listed = Vec::new();
+    while let Some(tag) = tags.next().await {
+        let tag = tag?;
+        let hashes = references(blobs, &tag).await;
+        listed.push((tag.name, hashes));
+    }
+    Ok(listed)
+}
+
+impl HistoryIndex {
+    fn insert(&mut self, name: Tag, hashes: Vec<Hash>) {
+        self.remove(&name);
+        for hash in &hashes {
+            *self.counts.entry(*hash).or_default() += 1;
+        }
+        if let Some(tags) = &mut self.tags {
+            tags.insert(name, hashes);
+        }
+    }
+
+    fn remove(&mut self, name: &Tag) {
+        let Some(hashes) = self.tags.as_mut().and_then(|tags| tags.remove(name)) else {
+            return;
+        };
+        for hash in hashes {
+            if let Some(count) = self.counts.get_mut(&hash) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&hash);
+                }
+            }
+        }
+    }
+
+    fn remove_prefix(&mut self, prefix: &[u8]) {
+        let names: Vec<Tag> = self
+            .tags
+            .iter()
+            .flat_map(|tags| tags.keys())
+            .filter(|name| name.0.starts_with(prefix))
+            .cloned()
+            .collect();
+        for name in names {
+            self.remove(&name);
+        }
+    }
+}
+
+/// Hashes `tag` keeps: its own and, for a hash sequence, its children.
+async fn references(blobs: &Blobs<Store>, tag: &TagInfo) -> Vec<Hash> {
+    let mut referenced = vec![tag.hash];
+    if tag.format == BlobFormat::HashSeq {
+        match blobs
+            .client()
+            .read_to_bytes(tag.hash)
+            .await
+            .map(HashSeq::try_from)
+        {
+            Ok(Ok(hash_seq)) => referenced.extend(hash_seq.iter()),
+            _ => warn!("Could not read hash sequence {}", tag.hash),
+        }
+    }
+    referenced
+}
+
+/// Reads `hash` back and checks the bytes still hash to it.
+async fn is_intact(blobs: &Blobs<Store>, hash: Hash) -> bool {
+    let Ok(mut reader) = blobs.client().read(hash).await else {
+        return false;
+    };
+    let mut hasher = blake3::Hasher::new();
+    while let Some(chunk) = reader.next().await {
+        match chunk {
+            Ok(chunk) => hasher.update(&chunk),
+            Err(_) => return false,
+        };
+    }
+    Hash::from_bytes(*hasher.finalize().as_bytes()) == hash
+}
+
+fn download_tag(hash: Hash) -> Tag {
+    Tag::from(format!("{}{}", DOWNLOAD_TAG_PREFIX, hash))
+}
+
+/// `file` for `file/docs/a.txt`, `legacy` for `auto-…`.
+fn tag_kind(name: &[u8]) -> String {
+    let name = String::from_utf8_lossy(name);
+    if name.starts_with(LEGACY_TAG_PREFIX) {
+        return "legacy".to_string();
+    }
+    match name.split_once('/') {
+        Some((kind, _)) => kind.to_string(),
+        None => "other".to_string(),
+    }
+}
+
+/// Total size of the files under `root`.
+pub(crate) fn disk_usage(root: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in WalkDir::new(root) {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", root))?;
+        if entry.file_type().is_file() {
+            total += entry.metadata()?.len();
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn built() -> HistoryIndex {
+        HistoryIndex {
+            tags: Some(BTreeMap::new()),
+            counts: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn history_counts_every_tag_keeping_a_hash() {
+        let (a, b) = (Hash::new(b"a"), Hash::new(b"b"));
+        let mut index = built();
+        index.insert(Tag::from("version/x//1"), vec![a]);
+        index.insert(
+            Tag::from("snapshot/manual/1/s"),
+            vec![Hash::new(b"seq"), a, b],
+        );
+        index.remove(&Tag::from("version/x//1"));
+        assert!(index.counts.contains_key(&a));
+        index.remove(&Tag::from("snapshot/manual/1/s"));
+        assert!(index.counts.is_empty());
+    }
+
+    #[test]
+    fn history_insert_replaces_the_tag() {
+        let (a, b) = (Hash::new(b"a"), Hash::new(b"b"));
+        let mut index = built();
+        index.insert(Tag::from("trash/1/n/x"), vec![a]);
+        index.insert(Tag::from("trash/1/n/x"), vec![b]);
+        assert!(!index.counts.contains_key(&a));
+        assert_eq!(index.counts.get(&b), Some(&1));
+    }
+
+    #[test]
+    fn history_remove_prefix_keeps_other_tags() {
+        let (a, b) = (Hash::new(b"a"), Hash::new(b"b"));
+        let mut index = built();
+        index.insert(Tag::from("trash/1/n/x"), vec![a]);
+        index.insert(Tag::from("trash/2/n/y"), vec![a]);
+        index.insert(Tag::from("version/x//1"), vec![b]);
+        index.remove_prefix(b"trash/");
+        assert!(!index.counts.contains_key(&a));
+        assert!(index.counts.contains_key(&b));
+    }
+}
@@ -0,0 +1,348 @@
+// src-tauri/src/symlinks.rs
+
+//! Symbolic links in the sync folder, see [`SymlinkPolicy`].
+//!
+//! With `Preserve`, a link is announced as `Message::SymlinkChanged` with its
+//! target relative to the folder holding it, and peers that preserve links
+//! too create the same link. A link whose target resolves outside the sync
+//! folder is neither announced nor created, whatever the sender claims.
+//! With `Follow`, links are walked like the files and folders they point to
+//! when announcing, except for links back into one of their own parents. With
+//! `Ignore`, they are left out.
+//!
+//! Whatever the policy, nothing from a peer goes through a link: a link where
+//! a file arrives is replaced, a path below a linked folder is refused, and so
+//! is any destination that does not resolve to somewhere inside the sync
+//! folder. Link targets are resolved lexically, without looking at the disk,
+//! so checking a link does not depend on whether its target arrived yet.
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+
+use crate::protocol::from_wire_path;
+
+/// The target of the link at `link` in wire form, relative to the folder
+/// holding the link. `None` if it points outside `sync_folder`.
+pub fn link_target(sync_folder: &Path, link: &Path) -> Result<Option<String>> {
+    let target =
+        std::fs::read_link(link).with_context(|| format!("Failed to read link {:?}", link))?;
+    let relative_link = link
+        .strip_prefix(sync_folder)
+        .with_context(|| format!("{:?} is not inside {:?}", link, sync_folder))?;
+    let parent = relative_link.parent().unwrap_or(Path::new(""));
+    let resolved = if target.is_absolute() {
+        normalize(&target)
+            .and_then(|target| target.strip_prefix(sync_folder).ok().map(Path::to_path_buf))
+    } else {
+        normalize(&parent.join(&target))
+    };
+    Ok(resolved.map(|resolved| relative_to(parent, &resolved)))
+}
+
+/// Where the wire `target` of a link at `relative_link` points, relative to
+/// the sync folder. `None` if it leaves the folder.
+pub fn resolve_target(relative_link: &Path, target: &str) -> Option<PathBuf> {
+    let mut resolved = relative_link.parent()?.to_path_buf();
+    if target.starts_with('/') {
+        return None;
+    }
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            part => resolved.push(from_wire_path(part)?),
+        }
+    }
+    Some(resolved)
+}
+
+/// Creates the link a peer announced at `relative_link`, replacing a file or
+/// link already there. Does nothing if a link to the same place is already
+/// there, however its target is spelled.
+pub async fn create_link(sync_folder: &Path, relative_link: &Path, target: &str) -> Result<()> {
+    let Some(resolved) = resolve_target(relative_link, target) else {
+        bail!(
+            "Not creating {:?}: {:?} points outside the sync folder",
+            relative_link,
+            target
+        );
+    };
+    let path = resolve(sync_folder, relative_link)?;
+    let parent = relative_link.parent().unwrap_or(Path::new(""));
+    let target = relative_to(parent, &resolved);
+    let native_target = PathBuf::from_iter(target.split('/'));
+    match tokio::fs::symlink_metadata(&path).await {
+        Ok(metadata) if metadata.is_symlink() => {
+            if link_target(sync_folder, &path)?.as_deref() == Some(target.as_str()) {
+                return Ok(());
+            }
+            tokio::fs::remove_file(&path).await?;
+        }
+        Ok(metadata) if metadata.is_dir() => {
+            bail!("Not replacing folder {:?} with a link", path);
+        }
+        Ok(_) => tokio::fs::remove_file(&path).await?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    symlink(&native_target, &path)
+        .await
+        .with_context(|| format!("Failed to create link {:?}", path))?;
+    info!("Linked {:?} to {:?}", path, native_target);
+    Ok(())
+}
+
+/// Where a file a peer sent, or a restore, for `relative` in `sync_folder`
+/// is written. A link there is replaced rather than written through.
+pub async fn prepare_destination(sync_folder: &Path, relative: &Path) -> Result<PathBuf> {
+    let path = resolve(sync_folder, relative)?;
+    let is_link = tokio::fs::symlink_metadata(&path)
+        .await
+        .is_ok_and(|metadata| metadata.is_symlink());
+    if is_link {
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to replace link {:?}", path))?;
+    }
+    Ok(path)
+}
+
+/// `relative` in `sync_folder`, for a change from a peer. Fails if a folder
+/// above it is a link, or if it does not end up inside the sync folder once
+/// resolved on disk. The path itself may still be a link.
+pub fn resolve(sync_folder: &Path, relative: &Path) -> Result<PathBuf> {
+    check_parents(sync_folder, relative)?;
+    let path = sync_folder.join(relative);
+    let root = std::fs::canonicalize(sync_folder)
+        .with_context(|| format!("Failed to resolve {:?}", sync_folder))?;
+    // The closest parent that exists, as the rest is yet to be created.
+    let existing = path
+        .ancestors()
+        .skip(1)
+        .find_map(|parent| std::fs::canonicalize(parent).ok());
+    if !existing.is_some_and(|existing| existing.starts_with(&root)) {
+        bail!("{:?} resolves outside the sync folder", relative);
+    }
+    Ok(path)
+}
+
+/// Fails if a folder above `relative` in `sync_folder` is a link.
+pub fn check_parents(sync_folder: &Path, relative: &Path) -> Result<()> {
+    let mut path = sync_folder.to_path_buf();
+    let mut parents = relative.components().collect::<Vec<_>>();
+    parents.pop();
+    for component in parents {
+        path.push(component);
+        if std::fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_symlink()) {
+            bail!("{:?} is inside the link {:?}", relative, path);
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `.` and `..` in `path` without touching the disk. `None` if a
+/// relative path climbs above where it starts.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    Some(normalized)
+}
+
+/// `to` relative to the folder `from`, both relative to the sync folder, in
+/// wire form.
+fn relative_to(from: &Path, to: &Path) -> String {
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let parts: Vec<String> =
+        std::iter::repeat_n("..".to_string(), from.components().count() - common)
+            .chain(
+                to.components()
+                    .skip(common)
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+            )
+            .collect();
+    if parts.is_empty() {
+        ".".to_string()
+    } else {
+        parts.join("/")
+    }
+}
+
+#[cfg(unix)]
+async fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    tokio::fs::symlink(target, link).await
+}
+
+#[cfg(windows)]
+async fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    let resolved = link.parent().map(|parent| parent.join(target));
+    if resolved.is_some_and(|resolved| resolved.is_dir()) {
+        tokio::fs::symlink_dir(target, link).await
+    } else {
+        tokio::fs::symlink_file(target, link).await
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "links are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty folder under the system temp dir, with links resolved.
+    fn temp_folder(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("fastsync-symlinks-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::canonicalize(&path).unwrap()
+    }
+
+    #[test]
+    fn resolve_target_stays_in_folder() {
+        let link = Path::new("a/b/link");
+        assert_eq!(resolve_target(link, "c"), Some(PathBuf::from("a/b/c")));
+        assert_eq!(
+            resolve_target(link, "../../c/d"),
+            Some(PathBuf::from("c/d"))
+        );
+        assert_eq!(
+            resolve_target(link, "./c/./d"),
+            Some(PathBuf::from("a/b/c/d"))
+        );
+        assert_eq!(resolve_target(link, "."), Some(PathBuf::from("a/b")));
+        assert_eq!(resolve_target(link, "../.."), Some(PathBuf::new()));
+    }
+
+    #[test]
+    fn resolve_target_rejects_escapes() {
+        assert_eq!(resolve_target(Path::new("a/b/link"), "../../../c"), None);
+        assert_eq!(resolve_target(Path::new("link"), ".."), None);
+        assert_eq!(resolve_target(Path::new("a/link"), "../x/../../y"), None);
+        assert_eq!(resolve_target(Path::new("a/link"), "/etc/passwd"), None);
+    }
+
+    #[test]
+    fn relative_to_climbs_to_common_parent() {
+        assert_eq!(relative_to(Path::new("a/b"), Path::new("a/b/c")), "c");
+        assert_eq!(relative_to(Path::new("a/b"), Path::new("c")), "../../c");
+        assert_eq!(relative_to(Path::new("a/b"), Path::new("a/d/e")), "../d/e");
+        assert_eq!(relative_to(Path::new("a/b"), Path::new("a/b")), ".");
+        assert_eq!(relative_to(Path::new(""), Path::new("c")), "c");
+    }
+
+    #[test]
+    fn normalize_rejects_climbing_above_start() {
+        assert_eq!(
+            normalize(Path::new("a/./b/../c")),
+            Some(PathBuf::from("a/c"))
+        );
+        assert_eq!(normalize(Path::new("a/../..")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_target_round_trips_through_resolve_target() {
+        let sync_folder = temp_folder("round-trip");
+        std::fs::create_dir_all(sync_folder.join("a/b")).unwrap();
+        let link = sync_folder.join("a/b/link");
+        let cases = [
+            (PathBuf::from("c"), Some("c")),
+            (PathBuf::from("../../d/e"), Some("../../d/e")),
+            (PathBuf::from("./c/../d"), Some("d")),
+            (PathBuf::from("."), Some(".")),
+            (PathBuf::from("../../../outside"), None),
+            (sync_folder.join("a/x"), Some("../x")),
+            (sync_folder.join("a/b"), Some(".")),
+            (sync_folder.join("a/../../outside"), None),
+            (PathBuf::from("/etc/passwd"), None),
+        ];
+        for (target, expected) in cases {
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(&target, &link).unwrap();
+            let wire = link_target(&sync_folder, &link).unwrap();
+            assert_eq!(wire.as_deref(), expected, "link to {:?}", target);
+            if let Some(wire) = wire {
+                let relative_link = Path::new("a/b/link");
+                let resolved = resolve_target(relative_link, &wire).unwrap();
+                let direct = if target.is_absolute() {
+                    normalize(&target)
+                        .unwrap()
+                        .strip_prefix(&sync_folder)
+                        .unwrap()
+                        .to_path_buf()
+                } else {
+                    normalize(&Path::new("a/b").join(&target)).unwrap()
+                };
+                assert_eq!(resolved, direct, "link to {:?}", target);
+            }
+        }
+        std::fs::remove_dir_all(&sync_folder).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_parents_rejects_nested_links() {
+        let sync_folder = temp_folder("parents");
+        std::fs::create_dir_all(sync_folder.join("real/dir")).unwrap();
+        std::os::unix::fs::symlink("real", sync_folder.join("linked")).unwrap();
+        std::os::unix::fs::symlink("dir", sync_folder.join("real/inner")).unwrap();
+
+        assert!(check_parents(&sync_folder, Path::new("real/dir/file")).is_ok());
+        assert!(check_parents(&sync_folder, Path::new("missing/dir/file")).is_ok());
+        // The link itself may be replaced, just not written through.
+        assert!(check_parents(&sync_folder, Path::new("linked")).is_ok());
+        assert!(check_parents(&sync_folder, Path::new("linked/dir/file")).is_err());
+        assert!(check_parents(&sync_folder, Path::new("real/inner/file")).is_err());
+        std::fs::remove_dir_all(&sync_folder).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_rejects_paths_outside_folder() {
+        let sync_folder = temp_folder("resolve");
+        let outside = temp_folder("resolve-outside");
+        std::fs::create_dir_all(sync_folder.join("dir")).unwrap();
+
+        assert_eq!(
+            resolve(&sync_folder, Path::new("dir/new/file")).unwrap(),
+            sync_folder.join("dir/new/file")
+        );
+        assert!(resolve(&sync_folder, Path::new("../escape")).is_err());
+
+        // A linked sync folder is fine; it is compared once resolved.
+        let linked_folder = outside.join("linked-folder");
+        std::os::unix::fs::symlink(&sync_folder, &linked_folder).unwrap();
+        assert!(resolve(&linked_folder, Path::new("dir/file")).is_ok());
+        std::fs::remove_dir_all(&sync_folder).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+// src-tauri/src/trash.rs
+
+//! Files deleted from the sync folder by peers.
+//!
+//! Before a [`Message::FileDeleted`](crate::protocol::Message) from a peer is
+//! applied, every file it removes is copied into the blob store and kept
+//! under a tag:
+//!
+//! ```text
+//! trash/<unix ms, 13 digits>/<deleting node id>/<relative path>
+//! ```
+//!
+//! so one `rm` on one device can be undone on every other. Entries older
+//! than [`TrashSettings::retention`] are purged. Content overwritten by a
+//! peer is kept as a version instead, see [`crate::versions`].
+
+use std::{path::Path, str::FromStr, time::SystemTime};
+
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use iroh::NodeId;
+use iroh_blobs::{
+    rpc::client::blobs::{BlobStatus, WrapOption},
+    util::{SetTagOption, Tag},
+    Hash,
+};
+use log::{info, warn};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    iroh_fns::announce_local_paths, presence::unix_ms, protocol::to_wire_path,
+    settings::TrashSettings, state::AppState, storage, versions::restore_blob,
+};
+
+/// Prefix of every trash tag.
+pub(crate) const TAG_PREFIX: &str = "trash/";
+
+/// A file in the trash, as reported by `list_trash`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashEntry {
+    /// Tag the file is kept under; pass it back to restore it.
+    pub id: String,
+    pub relative_path: String,
+    /// Unix time in milliseconds the file was deleted.
+    pub deleted_ms: u64,
+    /// Node whose deletion removed the file here.
+    pub deleted_by: NodeId,
+    pub size: u64,
+    pub hash: Hash,
+}
+
+/// A trash tag split into its parts.
+struct TrashTag {
+    deleted_ms: u64,
+    deleted_by: NodeId,
+    relative_path: String,
+}
+
+/// Copies every file at or under `path` into the trash before it is
+/// removed from the sync folder at the request of `deleted_by`.
+pub async fn move_to_trash(
+    app_state: &AppState,
+    sync_folder: &Path,
+    path: &Path,
+    deleted_by: NodeId,
+) -> Result<()> {
+    let deleted_ms = now_ms();
+    let blobs_client = app_state.blobs.client();
+    let mut trashed = 0;
+    // A deleted link goes without what it points to.
+    for entry in WalkDir::new(path)
+        .follow_links(false)
+        .follow_root_links(false)
+    {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(sync_folder)
+            .with_context(|| format!("{:?} is not inside {:?}", entry.path(), sync_folder))?;
+        let name = tag_name(deleted_ms, deleted_by, &to_wire_path(relative));
+        // Copied, not referenced: the file is about to be removed.
+        blobs_client
+            .add_from_path(
+                entry.path().to_path_buf(),
+                false,
+                SetTagOption::Named(Tag::from(name.as_str())),
+                WrapOption::NoWrap,
+            )
+            .await?
+            .finish()
+            .await
+            .with_context(|| format!("Failed to move {:?} to the trash", entry.path()))?;
+        trashed += 1;
+    }
+    if trashed > 0 {
+        info!("Moved {} files under {:?} to the trash", trashed, path);
+        let prefix = format!("{}{:013}/{}/", TAG_PREFIX, deleted_ms, deleted_by);
+        storage::refresh_history(app_state, &prefix).await?;
+    }
+    purge_expired(app_state).await
+}
+
+/// Everything in the trash, most recently deleted first.
+pub async fn list_trash(app_state: &AppState) -> Result<Vec<TrashEntry>> {
+    purge_expired(app_state).await?;
+    let blobs_client = app_state.blobs.client();
+    let mut entries = Vec::new();
+    for (tag, hash) in trash_tags(app_state).await? {
+        let size = match blobs_client.status(hash).await? {
+            BlobStatus::Complete { size } => size,
+            _ => continue,
+        };
+        entries.push(TrashEntry {
+            id: tag_name(tag.deleted_ms, tag.deleted_by, &tag.relative_path),
+            relative_path: tag.relative_path,
+            deleted_ms: tag.deleted_ms,
+            deleted_by: tag.deleted_by,
+            size,
+            hash,
+        });
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Puts trash entry `id` back at its path and announces it to peers. A file
+/// that has since appeared at that path is kept as a version first. Returns
+/// the restored path relative to the folder.
+pub async fn restore_from_trash(app_state: &AppState, id: &str) -> Result<String> {
+    let tag = parse_tag(id.as_bytes()).with_context(|| format!("{:?} is not a trash id", id))?;
+    let blobs_client = app_state.blobs.client();
+    let hash = blobs_client
+        .tags()
+        .get(id)
+        .await?
+        .with_context(|| format!("No trash entry {}", id))?
+        .hash;
+
+    let path = restore_blob(app_state, &tag.relative_path, hash).await?;
+    blobs_client.tags().delete(id).await?;
+    storage::refresh_history(app_state, id).await?;
+    info!("Restored {} from the trash", tag.relative_path);
+
+    announce_local_paths(app_state, &[path]).await?;
+    Ok(tag.relative_path)
+}
+
+/// Deletes everything in the trash.
+pub async fn empty_trash(app_state: &AppState) -> Result<()> {
+    app_state
+        .blobs
+        .client()
+        .tags()
+        .delete_prefix(TAG_PREFIX)
+        .await?;
+    storage::refresh_history(app_state, TAG_PREFIX).await?;
+    info!("Emptied the trash");
+    Ok(())
+}
+
+/// Replaces the retention period and purges what it no longer keeps.
+pub async fn apply_trash_settings(app_state: &AppState, settings: TrashSettings) -> Result<()> {
+    *app_state
+        .trash_settings
+        .lock()
+        .expect("trash settings poisoned") = settings;
+    purge_expired(app_state).await
+}
+
+/// Deletes trash entries older than the retention period.
+pub async fn purge_expired(app_state: &AppState) -> Result<()> {
+    let retention = app_state
+        .trash_settings
+        .lock()
+        .expect("trash settings poisoned")
+        .retention();
+    let cutoff = now_ms().saturating_sub(retention.as_millis() as u64);
+    let tags = app_state.blobs.client().tags();
+    let mut purged = 0;
+    for (tag, _) in trash_tags(app_state).await? {
+        // Sorted oldest first.
+        if tag.deleted_ms >= cutoff {
+            break;
+        }
+        tags.delete(tag_name(tag.deleted_ms, tag.deleted_by, &tag.relative_path))
+            .await?;
+        purged += 1;
+    }
+    if purged > 0 {
+        info!("Purged {} expired files from the trash", purged);
+        storage::refresh_history(app_state, TAG_PREFIX).await?;
+    }
+    Ok(())
+}
+
+/// Trash entries, oldest first.
+async fn trash_tags(app_state: &AppState) -> Result<Vec<(TrashTag, Hash)>> {
+    let mut tags = app_state
+        .blobs
+        .client()
+        .tags()
+        .list_prefix(TAG_PREFIX)
+        .await?;
+    let mut entries = Vec::new();
+    while let Some(tag) = tags.next().await {
+        let tag = tag?;
+        match parse_tag(tag.name.as_ref()) {
+            Some(parsed) => entries.push((parsed, tag.hash)),
+            None => warn!("Ignoring malformed trash tag {}", tag.name),
+        }
+    }
+    entries.sort_by_key(|(tag, _)| tag.deleted_ms);
+    Ok(entries)
+}
+
+fn tag_name(deleted_ms: u64, deleted_by: NodeId, relative_path: &str) -> String {
+    format!(
+        "{}{:013}/{}/{}",
+        TAG_PREFIX, deleted_ms, deleted_by, relative_path
+    )
+}
+
+fn parse_tag(name: &[u8]) -> Option<TrashTag> {
+    let name = std::str::from_utf8(name).ok()?;
+    let mut parts = name.strip_prefix(TAG_PREFIX)?.splitn(3, '/');
+    Some(TrashTag {
+        deleted_ms: parts.next()?.parse().ok()?,
+        deleted_by: NodeId::from_str(parts.next()?).ok()?,
+        relative_path: parts.next()?.to_string(),
+    })
+}
+
+fn now_ms() -> u64 {
+    unix_ms(SystemTime::now()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_id() -> NodeId {
+        iroh::SecretKey::from_bytes(&[7; 32]).public()
+    }
+
+    #[test]
+    fn tag_round_trips() {
+        let name = tag_name(1_700_000_000_000, node_id(), "docs/a/b.txt");
+        let tag = parse_tag(name.as_bytes()).unwrap();
+        assert_eq!(tag.deleted_ms, 1_700_000_000_000);
+        assert_eq!(tag.deleted_by, node_id());
+        assert_eq!(tag.relative_path, "docs/a/b.txt");
+    }
+
+    #[test]
+    fn tag_pads_the_time_so_entries_sort_by_age() {
+        assert!(tag_name(999, node_id(), "z") < tag_name(1_000, node_id(), "a"));
+    }
+
+    #[test]
+    fn parse_rejects_other_tags() {
+        assert!(parse_tag(b"version/a//1/x").is_none());
+        assert!(parse_tag(b"trash/1").is_none());
+        assert!(parse_tag(b"trash/x/y/z").is_none());
+        assert!(parse_tag(b"trash/1/not-a-node/z").is_none());
+    }
+}
@@ -0,0 +1,346 @@
+// src-tauri/src/versions.rs
+
+//! Earlier versions of files in the sync folder.
+//!
+//! Every version is a blob kept alive by a tag in the blob store:
+//!
+//! ```text
+//! version/<relative path>//<unix ms, 13 digits>/<author node id>
+//! ```
+//!
+//! Wire paths never contain `//`, so the path can be read back from the tag.
+//! The author is the node whose change produced the content. The content a
+//! peer's change is about to overwrite is copied into the store first, and
+//! the new content is recorded once it is in place. Older versions are
+//! pruned according to [`VersionSettings`].
+//!
+//! Received content that is moved into the sync folder rather than copied
+//! (see [`crate::storage::export_blob`]) is not recorded: the file is its
+//! only copy, so an edit here would change the version under its tag. It is
+//! kept like local content, once a peer's change is about to overwrite it.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use futures_util::StreamExt;
+use iroh::NodeId;
+use iroh_blobs::{
+    rpc::client::blobs::{BlobStatus, WrapOption},
+    store::{ExportFormat, ExportMode},
+    util::{SetTagOption, Tag},
+    Hash,
+};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    iroh_fns::announce_local_paths,
+    presence::unix_ms,
+    protocol::{from_wire_path, to_wire_path},
+    settings::VersionSettings,
+    state::AppState,
+    storage, symlinks,
+};
+
+/// Prefix of every version tag.
+pub(crate) const TAG_PREFIX: &str = "version/";
+/// Between the path and the rest of a version tag.
+const PATH_END: &str = "//";
+
+/// A version as reported by `list_versions`, newest first.
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    /// Tag the version is kept under; pass it back to restore it.
+    pub id: String,
+    pub relative_path: String,
+    /// Unix time in milliseconds the version was recorded.
+    pub created_ms: u64,
+    pub author: NodeId,
+    /// Announced name of `author`, if known.
+    pub author_name: Option<String>,
+    pub size: u64,
+    pub hash: Hash,
+}
+
+/// A version tag split into its parts.
+struct VersionTag {
+    relative_path: String,
+    created_ms: u64,
+    author: NodeId,
+}
+
+/// Versions of `relative_path`, newest first.
+pub async fn list_versions(app_state: &AppState, relative_path: &str) -> Result<Vec<VersionInfo>> {
+    let relative_path = &from_wire_path(relative_path)
+        .map(|relative| to_wire_path(&relative))
+        .with_context(|| format!("{:?} is not a path inside the sync folder", relative_path))?;
+    let blobs_client = app_state.blobs.client();
+    let own_id = app_state.endpoint.node_id();
+    let own_name = app_state.device.lock().await.name.clone();
+    let devices = app_state.devices.lock().await.clone();
+
+    let mut versions = Vec::new();
+    for (tag, hash) in version_tags(app_state, relative_path).await? {
+        let size = match blobs_client.status(hash).await? {
+            BlobStatus::Complete { size } => size,
+            _ => continue,
+        };
+        let author_name = if tag.author == own_id {
+            Some(own_name.clone())
+        } else {
+            devices.get(&tag.author).map(|record| record.name.clone())
+        };
+        versions.push(VersionInfo {
+            id: tag_name(&tag.relative_path, tag.created_ms, tag.author),
+            relative_path: tag.relative_path,
+            created_ms: tag.created_ms,
+            author: tag.author,
+            author_name,
+            size,
+            hash,
+        });
+    }
+    versions.reverse();
+    Ok(versions)
+}
+
+/// Keeps what is at `path` now as a version of `relative_path`, unless it is
+/// already the newest one. Content not recorded before is attributed to this
+/// node. Does nothing if there is no file at `path` or history is off.
+pub async fn preserve_current(
+    app_state: &AppState,
+    relative_path: &str,
+    path: &Path,
+) -> Result<()> {
+    if !path.is_file() || !settings(app_state).enabled() {
+        return Ok(());
+    }
+    let newest = version_tags(app_state, relative_path).await?.pop();
+    let name = tag_name(relative_path, now_ms(), app_state.endpoint.node_id());
+    // Copied, not referenced: the file is about to be overwritten.
+    let outcome = app_state
+        .blobs
+        .client()
+        .add_from_path(
+            path.to_path_buf(),
+            false,
+            SetTagOption::Named(Tag::from(name.as_str())),
+            WrapOption::NoWrap,
+        )
+        .await?
+        .finish()
+        .await
+        .with_context(|| format!("Failed to keep the current version of {:?}", path))?;
+    if newest.is_some_and(|(_, hash)| hash == outcome.hash) {
+        app_state.blobs.client().tags().delete(&name).await?;
+        return storage::refresh_history(app_state, &path_prefix(relative_path)).await;
+    }
+    info!("Kept the current version of {}", relative_path);
+    prune(app_state, relative_path).await
+}
+
+/// Records `hash`, already in the store, as the newest version of
+/// `relative_path`, written by `author`.
+pub async fn record_version(
+    app_state: &AppState,
+    relative_path: &str,
+    hash: Hash,
+    author: NodeId,
+) -> Result<()> {
+    if !settings(app_state).enabled() {
+        return Ok(());
+    }
+    let newest = version_tags(app_state, relative_path).await?.pop();
+    if newest.is_some_and(|(_, newest_hash)| newest_hash == hash) {
+        return Ok(());
+    }
+    let name = tag_name(relative_path, now_ms(), author);
+    app_state
+        .blobs
+        .client()
+        .tags()
+        .set(name.as_str(), hash)
+        .await?;
+    prune(app_state, relative_path).await
+}
+
+/// Writes version `id` back into the sync folder and announces it to peers,
+/// which keep the content it replaces as a version of their own. Returns the
+/// restored path relative to the folder.
+pub async fn restore_version(app_state: &AppState, id: &str) -> Result<String> {
+    let tag = parse_tag(id.as_bytes()).with_context(|| format!("{:?} is not a version id", id))?;
+    let hash = app_state
+        .blobs
+        .client()
+        .tags()
+        .get(id)
+        .await?
+        .with_context(|| format!("No version {}", id))?
+        .hash;
+
+    let path = restore_blob(app_state, &tag.relative_path, hash).await?;
+    record_version(
+        app_state,
+        &tag.relative_path,
+        hash,
+        app_state.endpoint.node_id(),
+    )
+    .await?;
+    info!("Restored {} to version {}", tag.relative_path, id);
+
+    announce_local_paths(app_state, &[path]).await?;
+    Ok(tag.relative_path)
+}
+
+/// Writes `hash` to `relative_path` in the sync folder for a restore, keeping
+/// what is there now as a version first. Paths in versions and the trash come
+/// from peers, so like their changes this never writes through a link.
+/// Returns the path written.
+pub(crate) async fn restore_blob(
+    app_state: &AppState,
+    relative_path: &str,
+    hash: Hash,
+) -> Result<PathBuf> {
+    let Some(sync_folder) = app_state.sync_folder.lock().await.clone() else {
+        bail!("No sync folder set");
+    };
+    let relative = from_wire_path(relative_path)
+        .with_context(|| format!("{:?} points outside the sync folder", relative_path))?;
+    let path = symlinks::prepare_destination(&sync_folder, &relative).await?;
+    preserve_current(app_state, relative_path, &path).await?;
+    app_state
+        .blobs
+        .client()
+        .export(hash, path.clone(), ExportFormat::Blob, ExportMode::Copy)
+        .await?
+        .finish()
+        .await
+        .with_context(|| format!("Failed to restore {:?}", path))?;
+    Ok(path)
+}
+
+/// Deletes the versions of `relative_path` the settings no longer keep.
+/// The newest version is never deleted. Also brings the history index up to
+/// date with a version just added.
+async fn prune(app_state: &AppState, relative_path: &str) -> Result<()> {
+    let settings = settings(app_state);
+    let window = settings.retention_window();
+    let now = now_ms();
+    let tags = app_state.blobs.client().tags();
+
+    let versions = version_tags(app_state, relative_path).await?;
+    let keep = (settings.keep as usize).max(1);
+    let excess = versions.len().saturating_sub(keep);
+    for (tag, _) in &versions[..excess] {
+        let age = Duration::from_millis(now.saturating_sub(tag.created_ms));
+        if window.is_some_and(|window| age < window) {
+            continue;
+        }
+        tags.delete(tag_name(relative_path, tag.created_ms, tag.author))
+            .await?;
+    }
+    storage::refresh_history(app_state, &path_prefix(relative_path)).await
+}
+
+/// Versions of `relative_path`, oldest first.
+async fn version_tags(
+    app_state: &AppState,
+    relative_path: &str,
+) -> Result<Vec<(VersionTag, Hash)>> {
+    let mut tags = app_state
+        .blobs
+        .client()
+        .tags()
+        .list_prefix(path_prefix(relative_path))
+        .await?;
+    let mut versions = Vec::new();
+    while let Some(tag) = tags.next().await {
+        let tag = tag?;
+        if let Some(parsed) = parse_tag(tag.name.as_ref()) {
+            versions.push((parsed, tag.hash));
+        }
+    }
+    versions.sort_by_key(|(tag, _)| tag.created_ms);
+    Ok(versions)
+}
+
+/// Start of every version tag of `relative_path`.
+fn path_prefix(relative_path: &str) -> String {
+    format!("{}{}{}", TAG_PREFIX, relative_path, PATH_END)
+}
+
+fn tag_name(relative_path: &str, created_ms: u64, author: NodeId) -> String {
+    format!(
+        "{}{}{}{:013}/{}",
+        TAG_PREFIX, relative_path, PATH_END, created_ms, author
+    )
+}
+
+fn parse_tag(name: &[u8]) -> Option<VersionTag> {
+    let name = std::str::from_utf8(name).ok()?;
+    let (relative_path, rest) = name.strip_prefix(TAG_PREFIX)?.rsplit_once(PATH_END)?;
+    let (created_ms, author) = rest.split_once('/')?;
+    Some(VersionTag {
+        relative_path: relative_path.to_string(),
+        created_ms: created_ms.parse().ok()?,
+        author: NodeId::from_str(author).ok()?,
+    })
+}
+
+fn settings(app_state: &AppState) -> VersionSettings {
+    app_state
+        .version_settings
+        .lock()
+        .expect("version settings poisoned")
+        .clone()
+}
+
+fn now_ms() -> u64 {
+    unix_ms(SystemTime::now()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_id() -> NodeId {
+        iroh::SecretKey::from_bytes(&[7; 32]).public()
+    }
+
+    #[test]
+    fn tag_round_trips() {
+        let name = tag_name("docs/a b.txt", 1_700_000_000_000, node_id());
+        let tag = parse_tag(name.as_bytes()).unwrap();
+        assert_eq!(tag.relative_path, "docs/a b.txt");
+        assert_eq!(tag.created_ms, 1_700_000_000_000);
+        assert_eq!(tag.author, node_id());
+    }
+
+    #[test]
+    fn tag_pads_the_time_so_versions_sort_by_age() {
+        let older = tag_name("a", 999, node_id());
+        let newer = tag_name("a", 1_000, node_id());
+        assert!(older < newer);
+        assert_eq!(parse_tag(older.as_bytes()).unwrap().created_ms, 999);
+    }
+
+    #[test]
+    fn tags_of_a_path_share_its_prefix_only() {
+        let name = tag_name("a/b", 1, node_id());
+        assert!(name.starts_with(&path_prefix("a/b")));
+        assert!(!name.starts_with(&path_prefix("a")));
+    }
+
+    #[test]
+    fn parse_rejects_other_tags() {
+        assert!(parse_tag(b"file/a").is_none());
+        assert!(parse_tag(b"version/a").is_none());
+        assert!(parse_tag(b"version/a//x/y").is_none());
+        assert!(parse_tag(b"version/a//1/not-a-node").is_none());
+        assert!(parse_tag(&[0xff]).is_none());
+    }
+}
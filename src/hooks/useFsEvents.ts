@@ -3,8 +3,10 @@ import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 
 // Define constants for event types (optional but good practice)
 export type FsEventType = {
-  event_type: 'Modify' | 'Create' | 'Remove' | 'Error' | 'Other';
+  event_type: 'Modify' | 'Create' | 'Remove' | 'Rename' | 'Error' | 'Other';
   path: string;
+  /** Previous path of a `Rename`. */
+  from?: string;
 };
 
 export const useFsEvents = () => {
@@ -4,3 +4,235 @@ import { twMerge } from 'tailwind-merge';
 export function cn(...inputs: ClassValue[]) {
   return twMerge(clsx(inputs));
 }
+
+/** Error kinds returned by backend commands, see `FastSyncError` in Rust. */
+export type FastSyncErrorKind =
+  | 'notInitialized'
+  | 'invalidTicket'
+  | 'io'
+  | 'network'
+  | 'permissionDenied'
+  | 'pathRejected'
+  | 'invalidSetting'
+  | 'internal';
+
+export interface FastSyncError {
+  kind: FastSyncErrorKind;
+  message: string;
+}
+
+export function isFastSyncError(error: unknown): error is FastSyncError {
+  return (
+    typeof error === 'object' &&
+    error !== null &&
+    'kind' in error &&
+    'message' in error
+  );
+}
+
+/** Human-readable message for anything thrown by `invoke`. */
+export function errorMessage(error: unknown, fallback: string): string {
+  if (isFastSyncError(error)) return error.message;
+  if (typeof error === 'string') return error;
+  return fallback;
+}
+
+/** Node lifecycle, from the `node_status` command and `node://status` events. */
+export type NodeStatus =
+  | { state: 'starting' }
+  | { state: 'ready' }
+  | { state: 'degraded'; reason: string }
+  | { state: 'failed'; reason: string }
+  | { state: 'shuttingDown' };
+
+/** Stored under `network-settings`; applied when the node next starts. */
+export interface NetworkSettings {
+  mode: 'default' | 'lanOnly';
+  relay_url: string | null;
+  discovery_n0: boolean;
+  discovery_local_network: boolean;
+  discovery_dht: boolean;
+}
+
+/** Stored under `device-profile`; announced to peers. */
+export interface DeviceProfile {
+  name: string;
+  color: string | null;
+}
+
+/** A peer from `list_devices` or a `devices://changed` event. */
+export interface DeviceInfo {
+  node_id: string;
+  name: string | null;
+  color: string | null;
+  online: boolean;
+  last_seen_ms: number | null;
+}
+
+/** A file of a shared folder, from `list_collection`. */
+export interface CollectionEntry {
+  name: string;
+  hash: string;
+}
+
+/** Payload of `blob://collection-progress`, sent after each file of a shared folder. */
+export interface CollectionProgress {
+  collection: string;
+  name: string;
+  size: number;
+  files_done: number;
+  files_total: number;
+  bytes_done: number;
+}
+
+/** Stored under `snapshot-settings`; applied as soon as it is saved. */
+export interface SnapshotSettings {
+  schedule: 'off' | 'hourly' | 'daily';
+  keep: number;
+}
+
+/** A snapshot from `list_snapshots` or a `snapshots://created` event. */
+export interface SnapshotInfo {
+  id: string;
+  name: string;
+  automatic: boolean;
+  created_ms: number;
+  hash: string;
+  file_count: number;
+}
+
+/** Result of `compare_snapshots`. */
+export interface SnapshotDiff {
+  added: string[];
+  removed: string[];
+  modified: string[];
+}
+
+/** Stored under `version-settings`. */
+export interface VersionSettings {
+  keep: number;
+  keep_days: number | null;
+}
+
+/** An earlier version of a file, from `list_versions` (newest first). */
+export interface VersionInfo {
+  id: string;
+  relative_path: string;
+  created_ms: number;
+  author: string;
+  author_name: string | null;
+  size: number;
+  hash: string;
+}
+
+/** Stored under `trash-settings`. */
+export interface TrashSettings {
+  retention_days: number;
+}
+
+/** A file deleted by a peer, from `list_trash` (most recent first). */
+export interface TrashEntry {
+  id: string;
+  relative_path: string;
+  deleted_ms: number;
+  deleted_by: string;
+  size: number;
+  hash: string;
+}
+
+export interface StorageStats {
+  store_bytes: number;
+  blob_count: number;
+  blob_bytes: number;
+  referenced_bytes: number;
+  dedup_savings_bytes: number;
+  reclaimable_bytes: number;
+  tags: Record<string, number>;
+  last_gc_ms: number | null;
+}
+
+export interface GcReport {
+  released_tags: number;
+  reclaimable_bytes: number;
+  next_gc_within_secs: number;
+}
+
+/** Byte limits; each is off when null. */
+export interface QuotaSettings {
+  store_max_bytes: number | null;
+  folder_max_bytes: number | null;
+  /** By absolute folder path; overrides `folder_max_bytes`. */
+  folders: Record<string, number>;
+  auto_download_max_bytes: number | null;
+}
+
+/** Each limit is off when null. */
+export interface TransferLimits {
+  upload_bytes_per_sec: number | null;
+  download_bytes_per_sec: number | null;
+  max_concurrent_uploads: number | null;
+  max_concurrent_downloads: number | null;
+}
+
+/** Stored under `bandwidth-settings`; applied at once. */
+export interface BandwidthSettings {
+  limits: TransferLimits;
+  /** Local "HH:MM" windows; the first containing the current time wins. */
+  schedule: { start: string; end: string; limits: TransferLimits }[];
+}
+
+/** Stored under `metadata-settings`; which file attributes are synced. */
+export interface MetadataSettings {
+  mtime: boolean;
+  permissions: boolean;
+  xattrs: boolean;
+}
+
+/** Stored under `symlink-policy`; what happens to links in the sync folder. */
+export type SymlinkPolicy = "preserve" | "follow" | "ignore";
+
+/** Argument of `pause_sync` / `resume_sync`; everything when left out. */
+export type PauseTarget =
+  | { kind: "all" }
+  | { kind: "folder"; path: string }
+  | { kind: "peer"; node_id: string };
+
+/** From `pause_status` and `sync://paused` events. */
+export interface PauseStatus {
+  all: boolean;
+  folders: string[];
+  peers: string[];
+  /** Auto-sync is off; changes wait for `sync_now`. */
+  manual: boolean;
+  queued_outgoing: number;
+  queued_incoming: number;
+}
+
+/** From `pending_changes`. */
+export interface PendingChange {
+  direction: "outgoing" | "incoming";
+  kind: "changed" | "deleted" | "renamed" | "dirCreated" | "symlink";
+  path: string;
+  old_path: string | null;
+  size: number | null;
+  from: string | null;
+  paused: boolean;
+}
+
+export type DeferReason = "tooLarge" | "storeQuota" | "folderQuota";
+
+export interface DeferredDownload {
+  relative_path: string;
+  size: number | null;
+  from: string;
+  reason: DeferReason;
+  deferred_ms: number;
+}
+
+/** Payload of `sync://quota-exceeded`. */
+export interface QuotaExceeded {
+  reason: DeferReason;
+  limit_bytes: number;
+  used_bytes: number;
+  files: DeferredDownload[];
+}
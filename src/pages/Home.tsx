@@ -10,10 +10,10 @@ import {
 import { Button } from '@/components/ui/button';
 import { toast } from 'sonner';
 import { load } from '@tauri-apps/plugin-store';
-import type { Store } from '@tauri-apps/plugin-store';
 import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
 import { useFsEvents } from '@/hooks/useFsEvents';
+import { errorMessage, type NodeStatus } from '@/lib/utils';
 
 const username = 'FastSync User'; // Replace this with a dynamic way to fetch/store the username in the future
 type TContent = { name: string; type: 'file' | 'directory' };
@@ -22,9 +22,8 @@ const Home: React.FC = () => {
   const [syncFolderPath, setSyncFolderPath] = useState<string | null>(null);
   const [directoryContents, setDirectoryContents] = useState<TContent[]>([]);
   const [isLoading, setIsLoading] = useState(true);
-  const [store, setStore] = useState<Store | null>(null);
   const [initialLoadComplete, setInitialLoadComplete] = useState(false);
-  const [autoSync, setAutoSync] = useState<boolean>(false);
+  const [autoSync, setAutoSync] = useState<boolean>(true);
   const [gossipTicket, setGossipTicket] = useState<string | null>(null);
 
   useFsEvents();
@@ -46,11 +45,10 @@ const Home: React.FC = () => {
     const initializeStore = async () => {
       try {
         const newStore = await load('store.json', { autoSave: false });
-        setStore(newStore);
 
         const savedPath = await newStore.get<string>('sync-folder-path');
         setSyncFolderPath(savedPath || null);
-        setAutoSync((await newStore.get<boolean>('auto-sync')) || false);
+        setAutoSync((await newStore.get<boolean>('auto-sync')) ?? true);
       } catch (error) {
         console.error('Error initializing store:', error);
         toast.error('Error Initializing Store', {
@@ -65,23 +63,32 @@ const Home: React.FC = () => {
   }, []);
 
   useEffect(() => {
-    const checkIrohLoaded = async () => {
-      try {
-        const response = await invoke('get_node_info');
-        console.info(response);
+    // Report readiness from the node's lifecycle instead of guessing.
+    const reportStatus = (status: NodeStatus) => {
+      if (status.state === 'ready') {
+        toast.info('Iroh is loaded', { description: 'Iroh is ready to use.' });
+      } else if (status.state === 'degraded') {
+        toast.warning('Sync Degraded', { description: status.reason });
+      } else if (status.state === 'failed') {
+        toast.error('Iroh Failed to Start', { description: status.reason });
+      }
+    };
 
-        toast.info('Iroh is loaded', {
-          description: `Iroh is ready to use. ${response}`,
-        });
-      } catch (err) {
+    const unlistenStatus = listen<NodeStatus>('node://status', (event) =>
+      reportStatus(event.payload),
+    );
+    invoke<NodeStatus>('node_status')
+      .then(reportStatus)
+      .catch((err) => {
         console.error('Error checking Iroh status:', err);
         toast.error('Error Checking Iroh Status', {
-          description: 'Failed to check Iroh status.',
+          description: errorMessage(err, 'Failed to check Iroh status.'),
         });
-      }
-    };
+      });
 
-    checkIrohLoaded();
+    return () => {
+      unlistenStatus.then((unlistenFn) => unlistenFn());
+    };
   }, []);
 
   useEffect(() => {
@@ -118,20 +125,27 @@ const Home: React.FC = () => {
     const newAutoSyncValue = !autoSync;
     setAutoSync(newAutoSyncValue);
 
-    if (store) {
-      try {
-        await store.set('auto-sync', newAutoSyncValue);
-        await store.save();
-      } catch (error) {
-        console.error('Error saving auto-sync preference:', error);
-        toast.error('Error Saving Preference', {
-          description: 'Could not save auto-sync preference.',
-        });
-      }
-    } else {
-      console.error('Store not initialized yet.');
-      toast.error('Store Error', {
-        description: 'Could not save the auto-sync value.',
+    try {
+      // The backend saves the preference and stops or resumes syncing.
+      await invoke('set_auto_sync', { enabled: newAutoSyncValue });
+    } catch (error) {
+      console.error('Error saving auto-sync preference:', error);
+      toast.error('Error Saving Preference', {
+        description: 'Could not save auto-sync preference.',
+      });
+    }
+  };
+
+  const handleSyncNow = async () => {
+    try {
+      await invoke('sync_now');
+      toast.success('Synced', {
+        description: 'Pending changes have been sent and applied.',
+      });
+    } catch (error) {
+      console.error('Error syncing:', error);
+      toast.error('Sync Failed', {
+        description: errorMessage(error, 'Could not sync pending changes.'),
       });
     }
   };
@@ -147,10 +161,10 @@ const Home: React.FC = () => {
     } catch (error) {
       console.error('Error creating/copying gossip ticket:', error);
       toast.error('Failed to Create Gossip Ticket', {
-        description:
-          typeof error === 'string'
-            ? error
-            : 'Could not create or copy the gossip ticket.',
+        description: errorMessage(
+          error,
+          'Could not create or copy the gossip ticket.',
+        ),
       });
     }
   };
@@ -201,6 +215,15 @@ const Home: React.FC = () => {
             <Button variant="outline" onClick={toggleAutoSync}>
               {autoSync ? 'Disable Auto-Sync' : 'Enable Auto-Sync'}
             </Button>
+            {!autoSync && (
+              <Button
+                variant="outline"
+                className="ml-2"
+                onClick={handleSyncNow}
+              >
+                Sync Now
+              </Button>
+            )}
           </div>
           <div className="space-y-2">
             <h3 className="text-lg font-semibold">Share Sync Session</h3>